@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde_json::{json, Value};
+
+use super::config::Target;
+use super::transport::Transport;
+
+/// One line of a batch file: `[<name> =] <command>`, where the command uses the grammar of
+/// `RequestFuncs::from_str`, e.g. `inv = invoice,create,1000` then `invoice,pay,${inv.invoice}`.
+struct Step<'a> {
+    capture: Option<&'a str>,
+    command: &'a str,
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_step(line: &str) -> Step<'_> {
+    if let Some(idx) = line.find('=') {
+        let name = line[..idx].trim();
+        if is_identifier(name) {
+            return Step { capture: Some(name), command: line[idx + 1..].trim() };
+        }
+    }
+    Step { capture: None, command: line }
+}
+
+// `${name}` or `${name.field.0}`, strings are inserted as is and anything else as json
+fn lookup(vars: &HashMap<String, Value>, reference: &str) -> Result<String, String> {
+    let mut path = reference.split('.');
+    let name = path.next().unwrap_or("");
+    let mut value = vars.get(name).ok_or_else(|| format!("Undefined variable {}", name))?;
+    for key in path {
+        value = match value {
+            Value::Array(values) => key.parse::<usize>().ok().and_then(|i| values.get(i)),
+            Value::Object(fields) => fields.get(key),
+            _ => None,
+        }
+        .ok_or_else(|| format!("{} has no field {}", name, reference))?;
+    }
+    Ok(match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    })
+}
+
+fn substitute(command: &str, vars: &HashMap<String, Value>) -> Result<String, String> {
+    let mut res = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("${") {
+        res.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or_else(|| "Unterminated ${".to_string())? + start;
+        res.push_str(&lookup(vars, &rest[start + 2..end])?);
+        rest = &rest[end + 1..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+// The json line printed for the command on `line`, and whether it succeeded. Only an error
+// response counts as a failure, the result of anything else is captured into `vars`.
+fn step_report(line: usize, step: &Step, resp: protocol::ResponseFuncs, vars: &mut HashMap<String, Value>) -> (bool, Value) {
+    let mut report = json!({ "line": line, "command": step.command });
+    if let protocol::ResponseFuncs::Error(e) = resp {
+        report["ok"] = json!(false);
+        report["error"] = json!(e);
+        return (false, report);
+    }
+    let result = super::output::to_json(resp);
+    report["ok"] = json!(true);
+    report["result"] = result.clone();
+    if let Some(name) = step.capture {
+        vars.insert(name.to_string(), result);
+    }
+    (true, report)
+}
+
+// Runs every command of `script` through `exec` and hands a json line per command to `print`.
+// Returns whether all of them succeeded.
+fn run_script(
+    script: &str,
+    keep_going: bool,
+    mut exec: impl FnMut(&str) -> protocol::ResponseFuncs,
+    mut print: impl FnMut(Value),
+) -> bool {
+    let mut vars: HashMap<String, Value> = HashMap::new();
+    let mut all_ok = true;
+
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let step = parse_step(line);
+        let resp = match substitute(step.command, &vars) {
+            Ok(command) => exec(&command),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        };
+
+        let (ok, report) = step_report(idx + 1, &step, resp, &mut vars);
+        print(report);
+
+        if !ok {
+            all_ok = false;
+            if !keep_going {
+                break;
+            }
+        }
+    }
+    all_ok
+}
+
+// Runs every command of the file over one connection and prints a json line per command.
+// Returns whether all of them succeeded.
+pub fn run(target: &Target, path: &str, keep_going: bool) -> bool {
+    let script = match fs::read_to_string(path) {
+        Ok(script) => script,
+        Err(e) => {
+            println!("{}", json!({ "ok": false, "error": format!("Could not read {}: {}", path, e) }));
+            return false;
+        }
+    };
+    let mut transport: Option<Transport> = None;
+    let exec = |command: &str| {
+        if transport.as_ref().map(|t| t.is_broken()).unwrap_or(true) {
+            transport = None;
+        }
+        match transport.as_mut() {
+            Some(transport) => super::handle(command, transport),
+            None => match super::connect(target) {
+                Ok(connected) => super::handle(command, transport.get_or_insert(connected)),
+                Err(e) => e,
+            },
+        }
+    };
+    run_script(&script, keep_going, exec, |report| println!("{}", report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::ResponseFuncs;
+
+    // Runs `script` against canned responses by command, returns the result and the reports
+    fn run_canned(script: &str, keep_going: bool, responses: &[(&str, ResponseFuncs)]) -> (bool, Vec<Value>) {
+        let mut reports = vec![];
+        let ok = run_script(
+            script,
+            keep_going,
+            |command| match responses.iter().find(|(c, _)| *c == command) {
+                Some((_, resp)) => resp.clone(),
+                None => ResponseFuncs::Error(format!("unexpected {}", command)),
+            },
+            |report| reports.push(report),
+        );
+        (ok, reports)
+    }
+
+    #[test]
+    fn captures_and_substitutes() {
+        let step = parse_step("inv = invoice,create,1000");
+        assert_eq!((step.capture, step.command), (Some("inv"), "invoice,create,1000"));
+        let step = parse_step("peer,connect,02aa@host:9735?x=1");
+        assert_eq!((step.capture, step.command), (None, "peer,connect,02aa@host:9735?x=1"));
+
+        let mut vars = HashMap::new();
+        vars.insert("inv".to_string(), json!({ "invoice": "lnbcrt1", "amount": 1000, "hops": ["a", "b"] }));
+        assert_eq!(substitute("invoice,pay,${inv.invoice}", &vars), Ok("invoice,pay,lnbcrt1".to_string()));
+        assert_eq!(substitute("${inv.amount}/${inv.hops.1}", &vars), Ok("1000/b".to_string()));
+        assert!(substitute("${nope}", &vars).is_err());
+        assert!(substitute("${inv.missing}", &vars).is_err());
+        assert!(substitute("${inv.invoice", &vars).is_err());
+
+        let script = "inv = invoice,create,1000\n# comment\n\ninvoice,pay,${inv.invoice}\n";
+        let (ok, reports) = run_canned(
+            script,
+            false,
+            &[
+                ("invoice,create,1000", ResponseFuncs::InvoiceCreate("lnbcrt1".to_string())),
+                ("invoice,pay,lnbcrt1", ResponseFuncs::InvoicePay),
+            ],
+        );
+        assert!(ok);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1]["line"], json!(4));
+        assert_eq!(reports[1]["ok"], json!(true));
+    }
+
+    #[test]
+    fn stops_or_keeps_going_on_errors() {
+        let script = "node,info\nchannel,close,00\nnode,info\n";
+        let responses = [
+            ("node,info", ResponseFuncs::GetNodeInfo("02aa".to_string())),
+            ("channel,close,00", ResponseFuncs::Error("Invalid channel_id".to_string())),
+        ];
+
+        let (ok, reports) = run_canned(script, false, &responses);
+        assert!(!ok);
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1]["ok"], json!(false));
+        assert_eq!(reports[1]["error"], json!("Invalid channel_id"));
+
+        let (ok, reports) = run_canned(script, true, &responses);
+        assert!(!ok);
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[2]["ok"], json!(true));
+
+        // A reference to a variable that never got captured fails the step without running it
+        let (ok, reports) = run_canned("x = channel,close,00\ninvoice,pay,${x.invoice}", true, &responses);
+        assert!(!ok);
+        assert_eq!(reports[1]["error"], json!("Undefined variable x"));
+    }
+
+    #[test]
+    fn only_error_responses_fail() {
+        // A successful key import has no error to report
+        let import = protocol::KeyImport { imported: true, ..Default::default() };
+        let (ok, reports) = run_canned("keys = info,addresses", false, &[("info,addresses", ResponseFuncs::GetAddresses(import))]);
+        assert!(ok);
+        assert!(reports[0]["result"].get("error").is_none());
+
+        let mut vars = HashMap::new();
+        let step = parse_step("keys = info,addresses");
+        let import = protocol::KeyImport { error: Some("no wallet".to_string()), ..Default::default() };
+        let (ok, _) = step_report(1, &step, ResponseFuncs::GetAddresses(import), &mut vars);
+        assert!(ok);
+        assert_eq!(vars["keys"]["error"], json!("no wallet"));
+
+        let (ok, report) = step_report(2, &step, ResponseFuncs::Error("denied".to_string()), &mut vars);
+        assert!(!ok);
+        assert_eq!(report["error"], json!("denied"));
+        assert_eq!(vars["keys"]["error"], json!("no wallet"));
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8123";
+
+/// `~/.config/rbcli/config.toml`, e.g.
+///
+/// ```toml
+/// default = "alice"
+///
+/// [profiles.alice]
+/// address = "127.0.0.1:8123"
+/// transport = "tcp"
+/// credentials = "~/lightning/ln/data_1/auth/admin.token"
+/// format = "table"
+/// ```
+#[derive(Deserialize, Default)]
+struct Config {
+    default: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+struct Profile {
+    address: Option<String>,
+    transport: Option<String>,
+    credentials: Option<String>,
+    format: Option<String>,
+}
+
+/// The node to talk to and how, from the command line flags falling back to the profile.
+pub struct Target {
+    pub profile: Option<String>,
+    pub address: String,
+    pub transport: String,
+    pub credentials: Option<String>,
+    pub format: String,
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".config").join("rbcli").join("config.toml"))
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.starts_with("~/"), dirs::home_dir()) {
+        (true, Some(home)) => home.join(&path[2..]).to_string_lossy().into_owned(),
+        _ => path.to_string(),
+    }
+}
+
+fn load() -> Result<Config, String> {
+    let path = match config_path() {
+        Some(path) if path.exists() => path,
+        _ => return Ok(Config::default()),
+    };
+    let content = fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+fn check(name: &str, value: &str, allowed: &[&str]) -> Result<(), String> {
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("Invalid {} {}, expected one of {}", name, value, allowed.join(", ")))
+    }
+}
+
+impl Target {
+    pub fn resolve(matches: &clap::ArgMatches) -> Result<Self, String> {
+        let config = load()?;
+        let name = matches.value_of("profile").map(|p| p.to_string()).or(config.default);
+        let profile = match &name {
+            Some(name) => config
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("Unknown profile {}", name))?,
+            None => Profile::default(),
+        };
+
+        let target = Target {
+            address: matches
+                .value_of("node")
+                .map(|v| v.to_string())
+                .or(profile.address)
+                .unwrap_or_else(|| DEFAULT_ADDRESS.to_string()),
+            transport: matches
+                .value_of("transport")
+                .map(|v| v.to_string())
+                .or(profile.transport)
+                .unwrap_or_else(|| "tcp".to_string()),
+            credentials: matches
+                .value_of("credentials")
+                .map(|v| v.to_string())
+                .or(profile.credentials)
+                .map(|v| expand_home(&v)),
+            format: matches
+                .value_of("format")
+                .map(|v| v.to_string())
+                .or(profile.format)
+                .unwrap_or_else(|| "table".to_string()),
+            profile: name,
+        };
+        check("transport", &target.transport, &["tcp", "unix", "udp"])?;
+        check("format", &target.format, &["table", "json", "csv"])?;
+        Ok(target)
+    }
+}
@@ -1,62 +1,131 @@
-use std::net::UdpSocket;
+mod batch;
+mod config;
 mod output;
+pub mod shell;
+mod transport;
 
-pub fn req_rep(sock: std::net::UdpSocket, req: protocol::RequestFuncs) -> protocol::ResponseFuncs {
-    let msg = protocol::Message::Request(req);
-    let ser = protocol::serialize_message(msg);
+use config::Target;
+use std::fs;
+use transport::Transport;
 
-    sock.send(&ser).expect("Failed to write to server");
+// Subcommands that map onto a request, each with the flags that select the request
+const COMMANDS: &[&str] = &["info", "invoice", "channel", "peer", "wallet", "tx"];
+const SUB_COMMANDS: &[&str] = &[
+    "node", "addresses", "create", "pay", "kill", "killall", "list", "connect", "fund", "psbts", "balance",
+    "newaddress", "send", "listunspent", "bump",
+];
 
-    let mut buf = [0u8; 1500];
-    let (len, _src) = sock
-        .recv_from(&mut buf)
-        .expect("Could not read into buffer");
-
-    let buf = &mut buf[..len]; // resize buffer
+fn handle(value: &str, transport: &mut Transport) -> protocol::ResponseFuncs {
+    let req = match value.parse::<protocol::RequestFuncs>() {
+        Ok(req) => req,
+        Err(e) => return protocol::ResponseFuncs::Error(e.to_string()),
+    };
+    let server = match transport.handshake() {
+        Ok(server) => server,
+        Err(e) => return protocol::ResponseFuncs::Error(format!("Handshake failed: {}", e)),
+    };
+    if let Some(capability) = req.capability() {
+        if !server.supports(capability) {
+            return protocol::ResponseFuncs::Error(format!("{} does not support {} requests", server.server, capability));
+        }
+    }
+    match transport.request(req) {
+        Ok(resp) => resp,
+        Err(e) => protocol::ResponseFuncs::Error(format!("Request failed: {}", e)),
+    }
+}
 
-    let resp = protocol::deserialize_message(buf.to_vec());
-    if let protocol::Message::Response(resp) = resp {
-        return resp;
+// Prints node events as they come in until the connection drops
+pub fn watch(matches: &clap::ArgMatches) {
+    let target = match Target::resolve(matches) {
+        Ok(target) => target,
+        Err(e) => return output::table(protocol::ResponseFuncs::Error(e)),
+    };
+    let fn_output_format = output_format(&target);
+    let mut transport = match connect(&target) {
+        Ok(transport) => transport,
+        Err(e) => return fn_output_format(e),
+    };
+    let server = match transport.handshake() {
+        Ok(server) => server,
+        Err(e) => return fn_output_format(protocol::ResponseFuncs::Error(format!("Handshake failed: {}", e))),
+    };
+    if !server.supports("events") {
+        return fn_output_format(protocol::ResponseFuncs::Error(format!("{} does not support events requests", server.server)));
+    }
+    if let Err(e) = transport.subscribe(fn_output_format) {
+        fn_output_format(protocol::ResponseFuncs::Error(format!("Event stream closed: {}", e)));
     }
+}
 
-    return protocol::ResponseFuncs::Error("No valid response".to_string());
+fn output_format(target: &Target) -> fn(protocol::ResponseFuncs) {
+    match target.format.as_str() {
+        "json" => output::json,
+        "csv" => output::csv,
+        _ => output::table,
+    }
 }
 
-fn handle(value: &str, sock: std::net::UdpSocket) -> protocol::ResponseFuncs {
-    if let Ok(protocol) = value.parse() {
-        req_rep(
-            sock.try_clone().expect("Could not clone socket"),
-            protocol
-        )
-    } else {
-        protocol::ResponseFuncs::Error("Invalid Internal Value".to_string())
+fn connect(target: &Target) -> Result<Transport, protocol::ResponseFuncs> {
+    let node_addr = &target.address;
+    let token = match &target.credentials {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(token) => Some(token.trim().to_string()),
+            Err(e) => return Err(protocol::ResponseFuncs::Error(format!("Could not read credentials {}: {}", path, e))),
+        },
+        None => None,
+    };
+
+    match Transport::connect(&target.transport, node_addr) {
+        Ok(transport) => Ok(transport.with_token(token)),
+        Err(e) => Err(protocol::ResponseFuncs::Error(format!("Could not connect to {}: {}", node_addr, e))),
     }
 }
 
-pub fn react(command: &str, sub_command: &str, matches: &clap::ArgMatches, sub_matches: &clap::ArgMatches) {
-    let node_addr = matches
-        .value_of("node")
-        .unwrap_or("127.0.0.1:8123");
+// Runs the request selected by the parsed command line over an open connection
+pub fn execute(matches: &clap::ArgMatches, transport: &mut Transport, fn_output_format: fn(protocol::ResponseFuncs)) {
+    for command in COMMANDS {
+        if let Some(sub_matches) = matches.subcommand_matches(command) {
+            for sub_command in SUB_COMMANDS {
+                if sub_matches.is_present(sub_command) {
+                    fn_output_format(react(command, sub_command, sub_matches, transport));
+                }
+            }
+        }
+    }
+}
 
-    let fn_output_format = match matches.is_present("json") {
-        true => output::json,
-        false => output::human
+// Runs a batch file, see `batch::run`, the exit code tells whether every command succeeded
+pub fn batch(matches: &clap::ArgMatches, batch_matches: &clap::ArgMatches) {
+    let target = match Target::resolve(matches) {
+        Ok(target) => target,
+        Err(e) => return output::table(protocol::ResponseFuncs::Error(e)),
     };
+    let path = batch_matches.value_of("file").unwrap_or_default();
+    if !batch::run(&target, path, batch_matches.is_present("continue")) {
+        std::process::exit(1);
+    }
+}
 
-    let socket =
-        UdpSocket::bind("0.0.0.0:5000")
-        .expect("Could not bind client socket");
-
-    socket
-        .connect(node_addr)
-        .expect("Could not connect to server");
+pub fn run(matches: &clap::ArgMatches) {
+    if !COMMANDS.iter().any(|c| matches.subcommand_matches(c).is_some()) {
+        return;
+    }
+    let target = match Target::resolve(matches) {
+        Ok(target) => target,
+        Err(e) => return output::table(protocol::ResponseFuncs::Error(e)),
+    };
+    let fn_output_format = output_format(&target);
 
-    // println!("matches:{:#?}", &matches);
-    // println!("sub_matches:{:#?}", &sub_matches);
-    // println!("command:{}", &command);
-    // println!("sub_command:{}", &sub_command);
+    let mut transport = match connect(&target) {
+        Ok(transport) => transport,
+        Err(e) => return fn_output_format(e),
+    };
+    execute(matches, &mut transport, fn_output_format);
+}
 
-    let resp = match sub_matches.values_of(sub_command) {
+fn react(command: &str, sub_command: &str, sub_matches: &clap::ArgMatches, transport: &mut Transport) -> protocol::ResponseFuncs {
+    match sub_matches.values_of(sub_command) {
         Some(values) => {
             let value: Vec<String> = values
                 .into_iter()
@@ -65,12 +134,10 @@ pub fn react(command: &str, sub_command: &str, matches: &clap::ArgMatches, sub_m
                 })
                 .collect();
             let command_and_value = format!("{},{},{}", command, sub_command, value.join(","));
-            handle(&command_and_value, socket)
+            handle(&command_and_value, transport)
         }
         _ => {
             protocol::ResponseFuncs::Error("Invalid Command or Arguments Provided\nTry running with --help or -h".to_string())
         }
-    };
-
-    fn_output_format(resp);
+    }
 }
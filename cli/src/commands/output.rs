@@ -1,8 +1,25 @@
 pub fn json(resp: protocol::ResponseFuncs) {
+    // One line per event so the output can be piped
+    if let protocol::ResponseFuncs::Event(_) = resp {
+        return println!("{}", to_json(resp));
+    }
+    println!("{}", serde_json::to_string_pretty(&to_json(resp)).unwrap());
+}
+
+pub fn to_json(resp: protocol::ResponseFuncs) -> serde_json::Value {
     use serde_json::json;
-    let res = match resp {
-        protocol::ResponseFuncs::GetAddresses(addrs) => {
-            json!({ "imported_addresses": addrs })
+    match resp {
+        protocol::ResponseFuncs::GetAddresses(import) => {
+            let mut res = json!({
+                "imported_addresses": import.addresses,
+                "import_mode": import.mode.map(|m| m.to_string()),
+                "imported": import.imported,
+            });
+            // Only failed imports carry an error, like every other response
+            if let Some(error) = import.error {
+                res["error"] = json!(error);
+            }
+            res
         }
         protocol::ResponseFuncs::GetNodeInfo(info) => {
             json!({ "node_id": info })
@@ -24,7 +41,7 @@ pub fn json(resp: protocol::ResponseFuncs) {
         }
         protocol::ResponseFuncs::ChannelClose(c) => {
             json!({ 
-                "response": "Channel closed",
+                "response": "Channel closing",
                 "channel": c,
             })
         }
@@ -45,37 +62,235 @@ pub fn json(resp: protocol::ResponseFuncs) {
                 "error": e 
             })
         }
-    };
-    println!("{}", serde_json::to_string_pretty(&res).unwrap());
-}
-
-pub fn human(resp: protocol::ResponseFuncs) {
-    match resp {
-        protocol::ResponseFuncs::GetAddresses(addrs) => {
-            println!("Imported Addresses:"); 
-            for addr in addrs {
-                println!("{}", addr);
-            }
+        protocol::ResponseFuncs::Hello(server) => {
+            json!({
+                "server": server.server,
+                "version": server.version,
+                "min_version": server.min_version,
+                "capabilities": server.capabilities,
+            })
         }
-        protocol::ResponseFuncs::GetNodeInfo(info) => {
-            println!("{}", info);
+        protocol::ResponseFuncs::Subscribed => {
+            json!({ "response": "Subscribed" })
         }
-        protocol::ResponseFuncs::PeerConnect => {
-            println!("Request Acknowledged ...");
+        protocol::ResponseFuncs::ChannelFund(txid) => {
+            json!({
+                "response": "Channel funded",
+                "funding_txid": txid,
+            })
         }
-        protocol::ResponseFuncs::PeerList(peers) => {
-            println!("Connected Peers:");
-            for peer in peers {
-                println!("{}", peer);
+        protocol::ResponseFuncs::WalletBalance(balance) => {
+            json!(balance)
+        }
+        protocol::ResponseFuncs::WalletNewAddress(address) => {
+            json!({ "address": address })
+        }
+        protocol::ResponseFuncs::WalletSend(txid) => {
+            json!({
+                "response": "Sent",
+                "txid": txid,
+            })
+        }
+        protocol::ResponseFuncs::TxBump(txid) => {
+            json!({
+                "response": "Fee bumped",
+                "txid": txid,
+            })
+        }
+        protocol::ResponseFuncs::WalletListUnspent(utxos) => {
+            let utxos: Vec<serde_json::Value> = utxos.iter().map(|u| serde_json::from_str(u).unwrap_or_default()).collect();
+            json!({ "unspent": utxos })
+        }
+        protocol::ResponseFuncs::ChannelPsbtList(psbts) => {
+            let psbts: Vec<serde_json::Value> = psbts.iter().map(|p| serde_json::from_str(p).unwrap_or_default()).collect();
+            json!({ "psbts": psbts })
+        }
+        protocol::ResponseFuncs::Event(event) => {
+            // {"PaymentReceived": {..}} flattened to {"event": "payment_received", ..}
+            let mut res = json!({ "event": event.kind() });
+            if let Some(serde_json::Value::Object(fields)) = serde_json::to_value(&event)
+                .ok()
+                .and_then(|v| v.as_object().and_then(|o| o.values().next().cloned()))
+            {
+                res.as_object_mut().unwrap().extend(fields);
             }
+            res
         }
-        protocol::ResponseFuncs::InvoiceCreate(invoice) => {
-            println!("Invoice created");
-            println!("{}", invoice);
+    }
+}
+
+// What a response looks like once the protocol details are gone, shared by table and csv
+enum Rendered {
+    Message { ok: bool, text: String },
+    Table { headers: Vec<&'static str>, rows: Vec<Vec<String>> },
+}
+
+fn ok(text: impl Into<String>) -> Rendered {
+    Rendered::Message { ok: true, text: text.into() }
+}
+
+fn render(resp: protocol::ResponseFuncs) -> Rendered {
+    match resp {
+        protocol::ResponseFuncs::GetAddresses(import) => {
+            let mode = import.mode.map(|m| m.to_string()).unwrap_or_else(|| "none".to_string());
+            let status = match (&import.error, import.imported) {
+                (Some(e), _) => format!("failed: {}", e),
+                (None, true) => "imported".to_string(),
+                (None, false) => "not imported".to_string(),
+            };
+            Rendered::Table {
+                headers: vec!["IMPORTED ADDRESS", "MODE", "STATUS"],
+                rows: import.addresses.into_iter().map(|a| vec![a, mode.clone(), status.clone()]).collect(),
+            }
         }
-        protocol::ResponseFuncs::Error(e) => {
-            println!("{}", e);
+        protocol::ResponseFuncs::GetNodeInfo(info) => ok(info),
+        protocol::ResponseFuncs::PeerConnect => ok("Connecting to peer ..."),
+        protocol::ResponseFuncs::PeerList(peers) => Rendered::Table {
+            headers: vec!["PEER"],
+            rows: peers.into_iter().map(|p| vec![p]).collect(),
+        },
+        protocol::ResponseFuncs::ChannelCreate(channel) => ok(format!("Channel created: {}", channel)),
+        protocol::ResponseFuncs::ChannelClose(channel) => ok(format!("Closing channel: {}", channel)),
+        protocol::ResponseFuncs::ChannelCloseAll => ok("Force closing all channels ..."),
+        protocol::ResponseFuncs::ChannelList(channels) => Rendered::Table {
+            headers: vec!["ID", "SHORT ID", "PEER", "CAPACITY (SAT)", "CONFIRMED"],
+            rows: channels
+                .iter()
+                .map(|c| {
+                    let c: serde_json::Value = serde_json::from_str(c).unwrap_or_default();
+                    vec![
+                        field(&c, "id"),
+                        field(&c, "short_id"),
+                        field(&c, "peer"),
+                        field(&c, "value_sats"),
+                        if c["confirmed"].as_bool().unwrap_or(false) { "yes" } else { "no" }.to_string(),
+                    ]
+                })
+                .collect(),
+        },
+        protocol::ResponseFuncs::InvoiceCreate(invoice) => ok(format!("Invoice created: {}", invoice)),
+        protocol::ResponseFuncs::InvoicePay => ok("Payment sent"),
+        protocol::ResponseFuncs::Error(e) => Rendered::Message { ok: false, text: e },
+        protocol::ResponseFuncs::Hello(server) => ok(format!(
+            "{} (protocol v{} to v{}, supports {})",
+            server.server,
+            server.min_version,
+            server.version,
+            server.capabilities.join(", ")
+        )),
+        protocol::ResponseFuncs::Subscribed => ok("Watching node events, press Ctrl-C to stop ..."),
+        protocol::ResponseFuncs::Event(event) => ok(event.to_string()),
+        protocol::ResponseFuncs::WalletBalance(balance) => Rendered::Table {
+            headers: vec!["FUNDS", "AMOUNT (SAT)"],
+            rows: vec![
+                vec!["wallet".to_string(), balance.confirmed_sat.to_string()],
+                vec!["unconfirmed".to_string(), balance.unconfirmed_sat.to_string()],
+                vec!["channels".to_string(), balance.channels_sat.to_string()],
+                vec!["claimable".to_string(), balance.claimable_sat.to_string()],
+            ],
+        },
+        protocol::ResponseFuncs::ChannelFund(txid) => ok(format!("Channel funded by {}", txid)),
+        protocol::ResponseFuncs::WalletNewAddress(address) => ok(address),
+        protocol::ResponseFuncs::WalletSend(txid) => ok(format!("Sent: {}", txid)),
+        protocol::ResponseFuncs::TxBump(txid) => ok(format!("Fee bumped by {}", txid)),
+        protocol::ResponseFuncs::WalletListUnspent(utxos) => Rendered::Table {
+            headers: vec!["TXID", "VOUT", "ADDRESS", "AMOUNT (SAT)", "CONFIRMATIONS"],
+            rows: utxos
+                .iter()
+                .map(|u| {
+                    let u: serde_json::Value = serde_json::from_str(u).unwrap_or_default();
+                    vec![
+                        field(&u, "txid"),
+                        field(&u, "vout"),
+                        field(&u, "address"),
+                        field(&u, "amount_sats"),
+                        field(&u, "confirmations"),
+                    ]
+                })
+                .collect(),
+        },
+        protocol::ResponseFuncs::ChannelPsbtList(psbts) => Rendered::Table {
+            headers: vec!["TEMPORARY ID", "ADDRESS", "VALUE (SAT)", "PSBT"],
+            rows: psbts
+                .iter()
+                .map(|p| {
+                    let p: serde_json::Value = serde_json::from_str(p).unwrap_or_default();
+                    vec![
+                        field(&p, "temporary_channel_id"),
+                        field(&p, "address"),
+                        field(&p, "value_sat"),
+                        field(&p, "psbt"),
+                    ]
+                })
+                .collect(),
+        },
+    }
+}
+
+// A cell of a json encoded listing
+fn field(value: &serde_json::Value, name: &str) -> String {
+    match &value[name] {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+pub fn table(resp: protocol::ResponseFuncs) {
+    match render(resp) {
+        Rendered::Message { ok: true, text } => println!("{}", text),
+        Rendered::Message { ok: false, text } => println!("Error: {}", text),
+        Rendered::Table { headers, rows } => {
+            if rows.is_empty() {
+                println!("(none)");
+                return;
+            }
+            let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+            for row in &rows {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.len());
+                }
+            }
+            let line = |cells: Vec<&str>| {
+                let padded: Vec<String> = cells
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                    .collect();
+                println!("{}", padded.join("  ").trim_end());
+            };
+            line(headers.clone());
+            for row in &rows {
+                line(row.iter().map(|c| c.as_str()).collect());
+            }
         }
-        _ => {}
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// "CAPACITY (SAT)" becomes capacity_sat
+fn csv_header(header: &str) -> String {
+    header.to_lowercase().replace(" (sat)", "_sat").replace(' ', "_")
+}
+
+pub fn csv(resp: protocol::ResponseFuncs) {
+    let (headers, rows): (Vec<String>, _) = match render(resp) {
+        Rendered::Message { ok, text } => (
+            vec!["status".to_string(), "message".to_string()],
+            vec![vec![if ok { "ok" } else { "error" }.to_string(), text]],
+        ),
+        Rendered::Table { headers, rows } => (headers.iter().map(|h| csv_header(h)).collect(), rows),
     };
+    println!("{}", headers.join(","));
+    for row in rows {
+        let row: Vec<String> = row.iter().map(|f| csv_field(f)).collect();
+        println!("{}", row.join(","));
+    }
 }
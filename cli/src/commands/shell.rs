@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+
+use clap::App;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use yaml_rust::Yaml;
+
+use super::config::Target;
+use super::transport::Transport;
+
+// Commands that don't make sense inside a session
+const NOT_IN_SHELL: &[&str] = &["shell", "watch", "server", "batch"];
+
+/// Completes subcommands and their flags as declared in `conf/en_US.yml`.
+struct ShellHelper {
+    commands: Vec<(String, Vec<String>)>,
+}
+
+impl ShellHelper {
+    fn new(yaml: &Yaml) -> Self {
+        let mut commands = vec![("help".to_string(), vec![]), ("exit".to_string(), vec![])];
+        for sub in yaml["subcommands"].as_vec().into_iter().flatten() {
+            for (name, spec) in sub.as_hash().into_iter().flatten() {
+                let name = match name.as_str() {
+                    Some(name) if !NOT_IN_SHELL.contains(&name) => name.to_string(),
+                    _ => continue,
+                };
+                let mut flags = vec![];
+                for arg in spec["args"].as_vec().into_iter().flatten() {
+                    for (_, arg) in arg.as_hash().into_iter().flatten() {
+                        if let Some(long) = arg["long"].as_str() {
+                            flags.push(format!("--{}", long));
+                        }
+                        if let Some(short) = arg["short"].as_str() {
+                            flags.push(format!("-{}", short));
+                        }
+                    }
+                }
+                commands.push((name, flags));
+            }
+        }
+        ShellHelper { commands }
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let candidates: Vec<&String> = match words.first() {
+            None => self.commands.iter().map(|(name, _)| name).collect(),
+            Some(command) => self
+                .commands
+                .iter()
+                .filter(|(name, _)| name == command)
+                .flat_map(|(_, flags)| flags)
+                .collect(),
+        };
+        let matches = candidates
+            .into_iter()
+            .filter(|c| c.starts_with(word))
+            .cloned()
+            .collect();
+        Ok((start, matches))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+fn history_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".rbcli_history"))
+}
+
+// Interactive session against one node, reusing the connection between commands
+pub fn run(yaml: &Yaml, matches: &clap::ArgMatches) {
+    let target = match Target::resolve(matches) {
+        Ok(target) => target,
+        Err(e) => return super::output::table(protocol::ResponseFuncs::Error(e)),
+    };
+    let fn_output_format = super::output_format(&target);
+    let mut transport: Option<Transport> = None;
+
+    let mut rl = Editor::<ShellHelper>::new();
+    rl.set_helper(Some(ShellHelper::new(yaml)));
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = rl.load_history(path);
+    }
+    let prompt = format!("rbcli {}> ", target.profile.as_ref().unwrap_or(&target.address));
+
+    loop {
+        let line = match rl.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                println!("{}", e);
+                break;
+            }
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.first() {
+            Some(command) => *command,
+            None => continue,
+        };
+        rl.add_history_entry(line.as_str());
+        match command {
+            "exit" | "quit" => break,
+            "help" => {
+                let _ = App::from_yaml(yaml).print_help();
+                println!();
+                continue;
+            }
+            c if NOT_IN_SHELL.contains(&c) => {
+                println!("{} is not available in the shell", c);
+                continue;
+            }
+            _ => {}
+        }
+
+        let line_matches = match App::from_yaml(yaml).get_matches_from_safe(std::iter::once("rbcli").chain(words)) {
+            Ok(line_matches) => line_matches,
+            Err(e) => {
+                println!("{}", e.message);
+                continue;
+            }
+        };
+        if transport.as_ref().map(|t| t.is_broken()).unwrap_or(true) {
+            transport = match super::connect(&target) {
+                Ok(transport) => Some(transport),
+                Err(e) => {
+                    fn_output_format(e);
+                    continue;
+                }
+            };
+        }
+        if let Some(transport) = transport.as_mut() {
+            super::execute(&line_matches, transport, fn_output_format);
+        }
+    }
+
+    if let Some(path) = &history {
+        let _ = rl.save_history(path);
+    }
+}
@@ -0,0 +1,179 @@
+use std::io;
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+enum Conn {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+    Udp(UdpSocket),
+}
+
+pub struct Transport {
+    conn: Conn,
+    next_id: u64,
+    token: Option<String>,
+    server: Option<protocol::ServerHello>,
+    broken: bool,
+}
+
+fn protocol_err(e: protocol::ProtocolError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Transport {
+    pub fn connect(kind: &str, node_addr: &str) -> io::Result<Self> {
+        let conn = match kind {
+            "unix" => {
+                let stream = UnixStream::connect(node_addr)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Conn::Unix(stream)
+            }
+            "udp" => {
+                // Any free port, so several invocations can run side by side
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(node_addr)?;
+                socket.set_read_timeout(Some(TIMEOUT))?;
+                Conn::Udp(socket)
+            }
+            _ => {
+                let stream = TcpStream::connect(node_addr)?;
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                Conn::Tcp(stream)
+            }
+        };
+        Ok(Transport { conn, next_id: 1, token: None, server: None, broken: false })
+    }
+
+    /// Sends `token` along with every request, see `protocol::Permission`.
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Whether an earlier request failed on the connection itself, it should be reopened.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Exchanges hellos and fails unless the server speaks our protocol version. The server's
+    /// hello is kept, later calls on the same connection don't ask again.
+    pub fn handshake(&mut self) -> io::Result<protocol::ServerHello> {
+        if let Some(server) = &self.server {
+            return Ok(server.clone());
+        }
+        let hello = protocol::ClientHello::new(concat!("rbcli ", env!("CARGO_PKG_VERSION")));
+        match self.request(protocol::RequestFuncs::Hello(hello))? {
+            protocol::ResponseFuncs::Hello(server) => {
+                if !server.accepts(protocol::PROTOCOL_VERSION) {
+                    return Err(protocol_err(protocol::ProtocolError::VersionMismatch {
+                        ours: protocol::PROTOCOL_VERSION,
+                        min: protocol::MIN_PROTOCOL_VERSION,
+                        theirs: server.version,
+                    }));
+                }
+                self.server = Some(server.clone());
+                Ok(server)
+            }
+            protocol::ResponseFuncs::Error(e) => Err(io::Error::new(io::ErrorKind::ConnectionRefused, e)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected hello response")),
+        }
+    }
+
+    pub fn request(&mut self, req: protocol::RequestFuncs) -> io::Result<protocol::ResponseFuncs> {
+        let resp = self.send_request(req);
+        if resp.is_err() {
+            self.broken = true;
+        }
+        resp
+    }
+
+    fn send_request(&mut self, req: protocol::RequestFuncs) -> io::Result<protocol::ResponseFuncs> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let frame = protocol::Frame::new(id, protocol::Message::Request(req)).with_token(self.token.clone());
+        let resp = match &mut self.conn {
+            Conn::Tcp(stream) => Self::exchange(stream, frame)?,
+            Conn::Unix(stream) => Self::exchange(stream, frame)?,
+            Conn::Udp(socket) => {
+                socket.send(&protocol::serialize_frame(&frame))?;
+                let mut buf = [0u8; 65507];
+                loop {
+                    let len = socket.recv(&mut buf)?;
+                    let resp = protocol::deserialize_frame(&buf[..len]).map_err(protocol_err)?;
+                    if resp.id == id || resp.id == 0 {
+                        break resp.message;
+                    }
+                }
+            }
+        };
+        match resp {
+            protocol::Message::Response(resp) => Ok(resp),
+            _ => Ok(protocol::ResponseFuncs::Error("No valid response".to_string())),
+        }
+    }
+
+    /// Subscribes to node events and hands every response for the subscription to `on_event`
+    /// until the server closes the connection or answers with an error.
+    pub fn subscribe<F: FnMut(protocol::ResponseFuncs)>(&mut self, on_event: F) -> io::Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let frame = protocol::Frame::new(id, protocol::Message::Request(protocol::RequestFuncs::Subscribe))
+            .with_token(self.token.clone());
+        match &mut self.conn {
+            Conn::Tcp(stream) => {
+                // Events can be hours apart
+                stream.set_read_timeout(None)?;
+                Self::stream_events(stream, frame, on_event)
+            }
+            Conn::Unix(stream) => {
+                stream.set_read_timeout(None)?;
+                Self::stream_events(stream, frame, on_event)
+            }
+            Conn::Udp(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Watching events needs a tcp or unix connection",
+            )),
+        }
+    }
+
+    fn stream_events<S: io::Read + io::Write, F: FnMut(protocol::ResponseFuncs)>(
+        stream: &mut S,
+        frame: protocol::Frame,
+        mut on_event: F,
+    ) -> io::Result<()> {
+        protocol::write_frame(stream, &frame)?;
+        loop {
+            let resp = protocol::read_frame(stream)?;
+            if resp.id != frame.id && resp.id != 0 {
+                continue;
+            }
+            if let protocol::Message::Response(resp) = resp.message {
+                if let protocol::ResponseFuncs::Error(_) = resp {
+                    on_event(resp);
+                    return Ok(());
+                }
+                on_event(resp);
+            }
+        }
+    }
+
+    fn exchange<S: io::Read + io::Write>(
+        stream: &mut S,
+        frame: protocol::Frame,
+    ) -> io::Result<protocol::Message> {
+        protocol::write_frame(stream, &frame)?;
+        loop {
+            let resp = protocol::read_frame(stream)?;
+            // Anything else on the stream belongs to an earlier request, id 0 is an error the
+            // server couldn't tie to a request
+            if resp.id == frame.id || resp.id == 0 {
+                return Ok(resp.message);
+            }
+        }
+    }
+}
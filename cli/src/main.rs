@@ -6,18 +6,15 @@ mod commands;
 fn main() {
     let yaml = load_yaml!("conf/en_US.yml");
     let matches = App::from_yaml(yaml).get_matches();
-    let commands = vec!["info", "invoice", "channel", "peer"];
 
-    commands.into_iter().for_each(
-        |command| if let Some(sub_matches) =
-            matches.subcommand_matches(command)
-        {
-            let sub_commands = vec![ "node", "addresses", "create", "pay", "kill", "killall", "list", "connect",];
-            sub_commands.into_iter().for_each(|sub_command| {
-                if sub_matches.is_present(sub_command) {
-                    commands::react(command, sub_command, &matches, sub_matches);
-                }
-            });
-        },
-    );
+    if matches.subcommand_matches("watch").is_some() {
+        return commands::watch(&matches);
+    }
+    if let Some(batch_matches) = matches.subcommand_matches("batch") {
+        return commands::batch(&matches, batch_matches);
+    }
+    if matches.subcommand_matches("shell").is_some() {
+        return commands::shell::run(yaml, &matches);
+    }
+    commands::run(&matches);
 }
@@ -1,5 +1,6 @@
 #![feature(async_closure)]
 extern crate base64;
+extern crate bech32;
 extern crate bitcoin;
 extern crate bitcoin_bech32;
 extern crate bitcoin_hashes;
@@ -19,6 +20,7 @@ extern crate tokio_fs;
 extern crate tokio_io;
 extern crate tokio_net;
 extern crate futures_timer;
+extern crate protocol;
 
 #[macro_use]
 extern crate log;
@@ -34,7 +36,7 @@ pub mod utils;
 use std::collections::HashMap;
 use std::fs;
 use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use rand::Rng;
 use futures::future;
@@ -45,31 +47,50 @@ use bitcoin::network::constants;
 use lightning::chain::keysinterface::{KeysInterface, KeysManager};
 use lightning::ln::channelmanager::{ChannelManager, PaymentHash, PaymentPreimage};
 use lightning::ln::peer_handler::PeerManager;
-use lightning::ln::{channelmanager, channelmonitor, peer_handler, router};
+use lightning::ln::{channelmanager, peer_handler, router};
 use lightning::util::logger::{Level};
+use protocol::{KeyImport, MAX_FUNDING_SATOSHIS};
 use secp256k1::key::PublicKey;
 use secp256k1::{All, Secp256k1};
 
 use ln_bridge::connection::{Connection, SocketDescriptor};
-use ln_bridge::chain_monitor::{spawn_chain_monitor, ChainWatchInterfaceUtil, ChainBroadcaster, FeeEstimator};
+use ln_bridge::chain_monitor::{catch_up, spawn_chain_monitor, ChainWatchInterfaceUtil, ChainBroadcaster, FeeEstimator};
+use ln_bridge::chain_source::ChainSource;
+use ln_bridge::esplora::EsploraClient;
 use ln_bridge::channel_monitor::ChannelMonitor;
 use ln_bridge::channel_manager::RestoreArgs as RestoreManagerArgs;
-use ln_bridge::event_handler::EventHandler;
+use ln_bridge::event_handler::{EventHandler, EventSubscribers};
+use ln_bridge::funding::Fundings;
 use ln_bridge::rpc_client::RPCClient;
 use ln_bridge::log_printer::LogPrinter;
-use ln_bridge::settings::Settings;
+use ln_bridge::settings::{Lightning, Settings};
+use ln_bridge::signet::GenesisWatch;
+use ln_bridge::sweeper::Sweeper;
 use ln_bridge::Restorable;
+use utils::{to_network, Chain};
 
 use executor::Larva;
 
+// Cap on channels we fund on mainnet unless max_channel_sat says otherwise
+const MAINNET_MAX_CHANNEL_SAT: u64 = 1_000_000;
+
 pub struct LnManager<T: Larva> {
     pub rpc_client: Arc<RPCClient>,
+    pub chain_source: Arc<dyn ChainSource>,
+    pub chain: Chain,
+    /// Network of our keys and addresses, testnet on signet.
     pub network: constants::Network,
     pub router: Arc<router::Router>,
     pub event_notify: mpsc::Sender<()>,
+    pub event_subscribers: Arc<EventSubscribers>,
+    pub sweeper: Arc<Sweeper>,
+    pub chain_broadcaster: Arc<ChainBroadcaster<T>>,
     pub channel_manager: Arc<ChannelManager>,
     pub peer_manager: Arc<PeerManager<SocketDescriptor<T>>>,
     pub payment_preimages: Arc<Mutex<HashMap<PaymentHash, PaymentPreimage>>>,
+    pub fundings: Arc<Fundings>,
+    pub key_import: KeyImport,
+    pub max_channel_sat: u64,
     pub secp_ctx: Secp256k1<All>,
     pub keys: Arc<KeysManager>,
     pub settings: Settings,
@@ -88,7 +109,36 @@ impl<T: Larva> LnManager<T> {
         let fee_estimator = Arc::new(FeeEstimator::new());
 
         info!("Checking validity of RPC URL to bitcoind...");
-        let network = get_network(&rpc_client).await?;
+        let esplora_url = settings.chain.as_ref().and_then(|c| c.esplora_url.clone());
+        let chain = get_network(&rpc_client, settings.lightning.allow_mainnet.unwrap_or(false), esplora_url.is_none()).await?;
+        let network = to_network(chain);
+        let chain_source: Arc<dyn ChainSource> = match esplora_url.clone() {
+            Some(esplora_url) => {
+                let esplora = EsploraClient::new(&esplora_url);
+                if let Err(e) = esplora.check_network(chain).await {
+                    panic!("Esplora at {} can't be used: {}", esplora_url, e);
+                }
+                info!("Following the chain through Esplora at {}", esplora_url);
+                Arc::new(esplora)
+            }
+            None => rpc_client.clone(),
+        };
+
+        let (block_notify, block_notifications) = mpsc::unbounded();
+        let zmq_endpoints = [
+            ("hashblock", &settings.bitcoind.zmqpubhashblock),
+            ("rawblock", &settings.bitcoind.zmqpubrawblock),
+        ];
+        for (topic, endpoint) in zmq_endpoints.iter() {
+            if let Some(endpoint) = endpoint {
+                ln_bridge::zmq::subscribe(endpoint.clone(), *topic, block_notify.clone());
+            }
+        }
+        // Polling only catches what notifications missed, and public Esplora instances rate limit
+        let zmq = zmq_endpoints.iter().any(|(_, endpoint)| endpoint.is_some());
+        let poll_interval = settings.chain.as_ref().and_then(|c| c.poll_interval).unwrap_or(
+            if zmq { 60 } else if esplora_url.is_some() { 30 } else { 1 }
+        );
         info!("Success! Starting up...");
 
         // Data Storage
@@ -99,66 +149,63 @@ impl<T: Larva> LnManager<T> {
 
         let _ = fs::create_dir(data_path.clone() + "/monitors"); // If it already exists, ignore, hopefully perms are ok
 
+        let (backup_dir, max_channel_sat) = match network {
+            constants::Network::Bitcoin => (
+                Some(check_mainnet_backup(&settings.lightning)),
+                settings.lightning.max_channel_sat.unwrap_or(MAINNET_MAX_CHANNEL_SAT),
+            ),
+            _ => (
+                settings.lightning.backup_dir.clone(),
+                settings.lightning.max_channel_sat.unwrap_or(MAX_FUNDING_SATOSHIS - 1),
+            ),
+        };
+        if let Some(backup_dir) = &backup_dir {
+            let _ = fs::create_dir(backup_dir.clone() + "/monitors");
+        }
+
         // Key Seed
         let our_node_seed = ln_bridge::key::get_key_seed(data_path.clone());
+        if let Some(backup_dir) = &backup_dir {
+            ln_bridge::key::backup_key_seed(&our_node_seed, backup_dir);
+        }
 
         let (secs, nano) = get_seeds_from_time();
         let keys = Arc::new(KeysManager::new(&our_node_seed, network, logger.clone(), secs, nano));
 
-        let (import_key_1, import_key_2) = ln_bridge::key::get_import_secret_keys(network, &our_node_seed);
-
         let chain_watcher = Arc::new(ChainWatchInterfaceUtil::new(network, logger.clone()));
-        let chain_broadcaster = Arc::new(ChainBroadcaster::new(rpc_client.clone(),larva.clone()));
+        let event_subscribers = Arc::new(EventSubscribers::default());
+        let chain_broadcaster = Arc::new(ChainBroadcaster::load(
+            &data_path,
+            settings.lightning.rebroadcast_confirmations.unwrap_or(6),
+            chain_source.clone(),
+            event_subscribers.clone(),
+            larva.clone(),
+        ));
 
-        let async_client = rpc_client.clone();
-        let _ = larva.clone().spawn_task(async move {
-            let k = &[
-                &("\"".to_string()
-                  + &bitcoin::util::key::PrivateKey {
-                      key: import_key_1,
-                      compressed: true,
-                      network,
-                  }
-                  .to_wif()
-                  + "\""),
-                "\"rust-lightning ChannelMonitor claim\"",
-                "false",
-            ];
-            async_client.make_rpc_call("importprivkey", k, false).map(|_| Ok(())).await
-        });
-        let async_client = rpc_client.clone();
-        let _ = larva.clone().spawn_task(async move {
-            let k = &[
-                &("\"".to_string()
-                  + &bitcoin::util::key::PrivateKey {
-                      key: import_key_2,
-                      compressed: true,
-                      network,
-                  }
-                  .to_wif()
-                  + "\""),
-                "\"rust-lightning cooperative close\"",
-                "false",
-            ];
-            async_client.make_rpc_call("importprivkey", k, false).map(|_| Ok(())).await
-        });
+        // The wallet has to see what rust-lightning pays to these keys
+        let key_import = ln_bridge::key_import::import_keys(&rpc_client, network, &our_node_seed).await;
+        match (&key_import.mode, &key_import.error) {
+            (Some(mode), None) => info!("Imported claim keys into the bitcoind wallet ({})", mode),
+            (_, Some(e)) => error!("Could not import claim keys into the bitcoind wallet: {}", e),
+            (None, None) => {}
+        }
 
-        let monitors_loaded = ChannelMonitor::load_from_disk(&(data_path.clone() + "/monitors"));
+        let (monitors_loaded, monitor_blocks) = ChannelMonitor::load_from_disk(&(data_path.clone() + "/monitors"));
 
-        let monitor = Arc::new(ChannelMonitor {
-            monitor: channelmonitor::SimpleManyChannelMonitor::new(
-                chain_watcher.clone(),
-                chain_broadcaster.clone(),
-                logger.clone(),
-                fee_estimator.clone(),
-            ),
-            file_prefix: data_path.clone() + "/monitors",
-        });
+        let monitor = Arc::new(ChannelMonitor::new(
+            chain_watcher.clone(),
+            chain_broadcaster.clone(),
+            logger.clone(),
+            fee_estimator.clone(),
+            data_path.clone() + "/monitors",
+            backup_dir.map(|dir| dir + "/monitors"),
+        ));
+        monitor.mirror_all();
 
-        let channel_manager = channelmanager::ChannelManager::try_restore(RestoreManagerArgs::new(
+        let (channel_manager, manager_block) = channelmanager::ChannelManager::try_restore(RestoreManagerArgs::new(
             data_path.clone(),
             monitors_loaded,
-            network.clone(),
+            chain,
             fee_estimator.clone(),
             monitor.clone(),
             chain_watcher.clone(),
@@ -167,9 +214,29 @@ impl<T: Larva> LnManager<T> {
             keys.clone(),
         ));
 
+        // Blocks mined while we were down, before peers or events act on stale channel state.
+        // Monitors go first so they are current when the manager's replay updates them. Each of
+        // them and the manager are replayed from their own block: a block seen twice counts
+        // funding confirmations twice, one never seen disconnected drops onchain claims
+        let (tip, _) = chain_source
+            .best_block()
+            .await
+            .unwrap_or_else(|e| panic!("Could not get the best block: {}", e));
+        let mut replayed = 0;
+        for (funding_txo, last_block) in &monitor_blocks {
+            replayed += catch_up(&*chain_source, &*monitor.channel(funding_txo), last_block, &tip)
+                .await
+                .unwrap_or_else(|e| panic!("Could not catch the monitor of {}:{} up to {}: {}", funding_txo.txid, funding_txo.index, tip, e));
+        }
+        info!("Replayed {} blocks to the channel monitors", replayed);
+        let replayed = catch_up(&*chain_source, &*channel_manager, &manager_block, &tip)
+            .await
+            .unwrap_or_else(|e| panic!("Could not catch the channel manager up to {}: {}", tip, e));
+        info!("Replayed {} blocks to the channel manager", replayed);
+
         let router = Arc::new(router::Router::new(
             PublicKey::from_secret_key(&secp_ctx, &keys.get_node_secret()),
-            chain_watcher.clone(), // chain watch
+            Arc::new(GenesisWatch::new(chain_watcher.clone(), chain)), // chain watch
             logger.clone(),
         ));
 
@@ -184,17 +251,23 @@ impl<T: Larva> LnManager<T> {
         ));
 
         let payment_preimages = Arc::new(Mutex::new(HashMap::new()));
+        let fundings = Arc::new(Fundings::load(&data_path));
+        let sweeper = Arc::new(Sweeper::load(&data_path, rpc_client.clone(), chain_source.clone(), fee_estimator.clone()));
 
         // clone for move (handle receiver)
         let event_notify = EventHandler::<T>::setup(
-            network,
+            chain,
             data_path,
             rpc_client.clone(),
+            chain_source.clone(),
             peer_manager.clone(),
-            monitor.monitor.clone(),
+            monitor.clone(),
             channel_manager.clone(),
             chain_broadcaster.clone(), // chain broadcaster
             payment_preimages.clone(),
+            fundings.clone(),
+            event_subscribers.clone(),
+            sweeper.clone(),
             larva.clone(),
         );
 
@@ -222,14 +295,25 @@ impl<T: Larva> LnManager<T> {
         let _ = larva.clone().spawn_task(
             spawn_chain_monitor(
                 fee_estimator,
-                rpc_client.clone(),
+                chain_source.clone(),
                 chain_watcher,
-                chain_broadcaster,
+                chain_broadcaster.clone(),
+                sweeper.clone(),
                 event_notify.clone(),
+                tip,
+                Duration::from_secs(poll_interval),
+                block_notifications,
                 larva.clone(),
             ).map(|_| Ok(()))
         );
 
+        // Pending sweeps from before a restart
+        let startup_sweeper = sweeper.clone();
+        let _ = larva.clone().spawn_task(async move {
+            startup_sweeper.sweep().await;
+            Ok(())
+        });
+
         // TODO see below
         // let _ = larva.clone().spawn_task(Box::new(
         //     tokio::timer::Interval::new(Instant::now(), Duration::new(1, 0))
@@ -243,12 +327,20 @@ impl<T: Larva> LnManager<T> {
 
         let ln_manager = Self {
             rpc_client,
+            chain_source,
+            chain,
             network,
             router,
             event_notify,
+            event_subscribers,
+            sweeper,
+            chain_broadcaster,
             channel_manager,
             peer_manager,
             payment_preimages,
+            fundings,
+            key_import,
+            max_channel_sat,
             secp_ctx,
             keys,
             settings,
@@ -267,17 +359,39 @@ fn get_seeds_from_time() -> (u64, u32) {
     (since_the_epoch.as_secs() as u64, since_the_epoch.subsec_nanos() as u32)
 }
 
-pub async fn get_network(rpc_client: &Arc<RPCClient>) -> Result<constants::Network, ()> {
+/// The chain bitcoind is on. `need_synced` is false when another chain source follows the
+/// chain, bitcoind then only serves the wallet and may still be syncing.
+pub async fn get_network(rpc_client: &Arc<RPCClient>, allow_mainnet: bool, need_synced: bool) -> Result<Chain, ()> {
     let v = rpc_client.make_rpc_call("getblockchaininfo", &[], false).await?;
-    assert!(v["verificationprogress"].as_f64().unwrap() > 0.99);
-    assert_eq!(v["bip9_softforks"]["segwit"]["status"].as_str().unwrap(), "active");
+    if need_synced {
+        assert!(v["verificationprogress"].as_f64().unwrap() > 0.99);
+        // bip9_softforks before bitcoind 0.19, which signet needs
+        let segwit = v["bip9_softforks"]["segwit"]["status"].as_str() == Some("active")
+            || v["softforks"]["segwit"]["active"].as_bool() == Some(true);
+        assert!(segwit, "segwit is not active on bitcoind");
+    }
     match v["chain"].as_str().unwrap() {
-        "main" => {
-            panic!("LOL, you're insane");
-            // Ok(constants::Network::Bitcoin)
-        },
-        "test" => Ok(constants::Network::Testnet),
-        "regtest" => Ok(constants::Network::Regtest),
-        _ => panic!("Unknown Network")
+        "main" if allow_mainnet => Ok(Chain::Bitcoin),
+        "main" => panic!("bitcoind is on mainnet, set allow_mainnet = true in [lightning] to risk real funds"),
+        "test" => Ok(Chain::Testnet),
+        "signet" => Ok(Chain::Signet),
+        "regtest" => Ok(Chain::Regtest),
+        chain => panic!("Unknown Network {}", chain)
+    }
+}
+
+// Real funds: the seed and channel monitors must survive losing the lndata disk
+fn check_mainnet_backup(settings: &Lightning) -> String {
+    let backup_dir = settings.backup_dir.clone()
+        .expect("Mainnet needs backup_dir in [lightning], on another disk than lndata");
+    let backup_path = fs::canonicalize(&backup_dir)
+        .expect("Need backup_dir to exist and be a directory (or symlink to one)");
+    if !backup_path.is_dir() {
+        panic!("Need backup_dir to exist and be a directory (or symlink to one)");
+    }
+    let data_path = fs::canonicalize(&settings.lndata).unwrap();
+    if backup_path.starts_with(&data_path) || data_path.starts_with(&backup_path) {
+        panic!("backup_dir and lndata must not contain one another");
     }
+    backup_dir
 }
@@ -1,32 +1,32 @@
-use super::rpc_client::{GetHeaderResponse, RPCClient};
-use super::utils::hex_to_vec;
+use super::chain_source::ChainSource;
+use super::event_handler::EventSubscribers;
+use super::sweeper::Sweeper;
+use super::utils::{hex_to_vec, write_atomically};
 
-use tokio::runtime::current_thread::Runtime;
-use std::thread;
-
-use bitcoin;
 use serde_json;
 
 use bitcoin_hashes::hex::ToHex;
 use bitcoin_hashes::sha256d::Hash as Sha256dHash;
 
-use futures::future;
 use futures::prelude::*;
+use futures::stream;
 use futures::channel::mpsc;
-use futures::executor::block_on;
 use futures_timer::Interval;
 
 use lightning::chain::chaininterface;
-pub use lightning::chain::chaininterface::{ChainWatchInterface, ChainWatchInterfaceUtil};
+pub use lightning::chain::chaininterface::{ChainListener, ChainWatchInterface, ChainWatchInterfaceUtil};
 
-use bitcoin::blockdata::block::Block;
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::consensus::encode;
 use bitcoin::util::hash::BitcoinHash;
 
 use crate::executor::Larva;
 use log::info;
+use protocol::NodeEvent;
 use std::cmp;
 use std::collections::HashMap;
+use std::fs;
 use std::marker::{Sync};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
@@ -49,57 +49,19 @@ impl FeeEstimator {
         }
     }
 
-    pub async fn update_values(this: Arc<Self>, rpc_client: Arc<RPCClient>) -> Result<(), ()> {
-        // Expected Error when testing with Regtest
-        let values = vec![
-            ("6", "\"CONSERVATIVE\""),
-            ("18", "\"ECONOMICAL\""),
-            ("144", "\"ECONOMICAL\""),
+    pub async fn update_values(this: Arc<Self>, chain_source: Arc<dyn ChainSource>) -> Result<(), ()> {
+        let targets = [
+            (6, &this.high_prio_est),
+            (18, &this.normal_est),
+            (144, &this.background_est),
         ];
-        let reqs = values.into_iter().map(move |value| {
-            let value = value.clone();
-            let async_this = this.clone();
-            let async_client = rpc_client.clone();
-            async move {
-                let (a, b) = value.clone();
-                let p = vec![a, b];
-                let v = async_client
-                    .make_rpc_call("estimatesmartfee", &p, false).await;
-                let v = v.unwrap();
-                match value {
-                    ("6", _) => {
-                        if let Some(serde_json::Value::Number(hp_btc_per_kb)) = v.get("feerate") {
-                            async_this.high_prio_est.store(
-                                (hp_btc_per_kb.as_f64().unwrap() * 100_000_000.0 / 250.0) as usize
-                                    + 3,
-                                Ordering::Release,
-                            );
-                        };
-                    },
-                    ("18", _) => {
-                        if let Some(serde_json::Value::Number(np_btc_per_kb)) = v.get("feerate") {
-                            async_this.normal_est.store(
-                                (np_btc_per_kb.as_f64().unwrap() * 100_000_000.0 / 250.0) as usize
-                                    + 3,
-                                Ordering::Release,
-                            );
-                        }
-                    },
-                    ("144", _) => {
-                        if let Some(serde_json::Value::Number(bp_btc_per_kb)) = v.get("feerate") {
-                            async_this.background_est.store(
-                                (bp_btc_per_kb.as_f64().unwrap() * 100_000_000.0 / 250.0) as usize
-                                    + 3,
-                                Ordering::Release,
-                            );
-                        }
-                    },
-                    _ => {},
-                }
+        for (conf_target, est) in targets.iter() {
+            // Expected Error when testing with Regtest
+            if let Ok(Some(sat_per_vbyte)) = chain_source.estimate_fee(*conf_target).await {
+                // 1 vbyte is 4 weight units, so 1 sat/vB is 250 sat per 1000 weight
+                est.store((sat_per_vbyte * 250.0) as usize + 3, Ordering::Release);
             }
-        });
-
-        future::join_all(reqs).await;
+        }
         Ok(())
     }
 }
@@ -122,313 +84,745 @@ impl chaininterface::FeeEstimator for FeeEstimator {
     }
 }
 
+/// A transaction we broadcast, resent on every block until it is deep enough.
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingBroadcast {
+    // consensus encoded
+    tx: String,
+    // height of the block confirming it, or a conflicting spend of one of its inputs
+    confirmed_at: Option<u32>,
+    conflicted: bool,
+    // last refusal of the chain source, published once until it changes
+    last_error: Option<String>,
+}
+
+fn decode_tx(hex: &str) -> Option<Transaction> {
+    hex_to_vec(hex).and_then(|tx| encode::deserialize(&tx).ok())
+}
+
+// The pending set and its file, shared with the broadcast tasks
+struct PendingBroadcasts {
+    file: String,
+    txn: Mutex<HashMap<Sha256dHash, PendingBroadcast>>,
+}
+
+impl PendingBroadcasts {
+    fn persist(&self, txn: &HashMap<Sha256dHash, PendingBroadcast>) {
+        let pending: Vec<&PendingBroadcast> = txn.values().collect();
+        let res = serde_json::to_vec(&pending)
+            .map_err(|e| e.to_string())
+            .and_then(|content| write_atomically(&self.file, &content, 0o644));
+        if let Err(e) = res {
+            error!("Could not persist pending broadcasts to {}: {}", self.file, e);
+        }
+    }
+}
+
+// Sends one pending transaction, publishing why it was refused unless that was published before
+async fn resend(
+    chain_source: Arc<dyn ChainSource>,
+    pending: Arc<PendingBroadcasts>,
+    subscribers: Arc<EventSubscribers>,
+    tx: Transaction,
+) -> Result<(), String> {
+    let txid = tx.txid();
+    let (res, confirmed_at) = match chain_source.broadcast(&tx).await {
+        // Confirmed in a block we never connected, e.g. mined while we were down. Counting from
+        // the tip only keeps it a little longer than needed
+        Err(ref e) if e.contains("already in block chain") => {
+            (Ok(()), chain_source.best_block().await.ok().map(|(_, height)| height))
+        }
+        res => (res.map(|_| ()), None),
+    };
+    let mut txn = pending.txn.lock().unwrap();
+    if let Some(broadcast) = txn.get_mut(&txid) {
+        let error = res.as_ref().err().cloned();
+        let mut changed = false;
+        if broadcast.confirmed_at.is_none() && confirmed_at.is_some() {
+            info!("{} is already in the chain", txid);
+            broadcast.confirmed_at = confirmed_at;
+            changed = true;
+        }
+        if broadcast.last_error != error {
+            if let Some(e) = &error {
+                error!("Broadcast of {} refused: {}", txid, e);
+                subscribers.publish(NodeEvent::BroadcastFailed { txid: txid.to_string(), error: e.clone() });
+            }
+            broadcast.last_error = error;
+            changed = true;
+        }
+        if changed {
+            pending.persist(&txn);
+        }
+    }
+    res
+}
+
+/// Broadcasts the transactions of rust-lightning and our own through the chain source. Until
+/// they are `confirmations` deep they live in `<lndata>/broadcasts.json` and get resent on every
+/// block, so commitment and justice transactions survive a restart.
 pub struct ChainBroadcaster<T> {
-    txn_to_broadcast: Mutex<HashMap<Sha256dHash, bitcoin::blockdata::transaction::Transaction>>,
-    rpc_client: Arc<RPCClient>,
+    confirmations: u32,
+    pending: Arc<PendingBroadcasts>,
+    chain_source: Arc<dyn ChainSource>,
+    subscribers: Arc<EventSubscribers>,
     larva: T,
 }
 
 impl<T> ChainBroadcaster<T> {
-    pub fn new(rpc_client: Arc<RPCClient>, larva: T) -> Self {
+    pub fn load(
+        data_path: &str,
+        confirmations: u32,
+        chain_source: Arc<dyn ChainSource>,
+        subscribers: Arc<EventSubscribers>,
+        larva: T,
+    ) -> Self {
+        let file = format!("{}/broadcasts.json", data_path);
+        let pending: Vec<PendingBroadcast> = match fs::read(&file) {
+            // Starting without them could lose a justice transaction
+            Ok(content) => serde_json::from_slice(&content)
+                .unwrap_or_else(|e| panic!("Could not read pending broadcasts from {}: {}", file, e)),
+            Err(_) => vec![],
+        };
+        let txn: HashMap<Sha256dHash, PendingBroadcast> = pending
+            .into_iter()
+            .filter_map(|p| Some((decode_tx(&p.tx)?.txid(), p)))
+            .collect();
+        if !txn.is_empty() {
+            info!("Loaded {} pending broadcasts", txn.len());
+        }
         Self {
-            txn_to_broadcast: Mutex::new(HashMap::new()),
-            rpc_client,
+            confirmations,
+            pending: Arc::new(PendingBroadcasts { file, txn: Mutex::new(txn) }),
+            chain_source,
+            subscribers,
             larva,
         }
     }
 
-    async fn rebroadcast_txn(&self) {
-        let txn = self.txn_to_broadcast.lock().unwrap();
+    fn track(&self, tx: &Transaction) {
+        let mut txn = self.pending.txn.lock().unwrap();
+        if !txn.contains_key(&tx.txid()) {
+            txn.insert(tx.txid(), PendingBroadcast {
+                tx: encode::serialize_hex(tx),
+                confirmed_at: None,
+                conflicted: false,
+                last_error: None,
+            });
+            self.pending.persist(&txn);
+        }
+    }
+
+    /// A transaction we broadcast that isn't buried yet, looked up by its txid as bitcoind
+    /// prints it.
+    pub fn sent(&self, txid: &str) -> Option<Transaction> {
+        self.pending
+            .txn
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(id, _)| id.to_string() == txid)
+            .and_then(|(_, p)| decode_tx(&p.tx))
+    }
 
-        warn!("REBROADCAST UNPROCESSED");
-        for (_, tx) in txn.iter() {
-            debug!("REBROADCAST UNPROCESSED: {:?}", tx);
+    /// Broadcasts and remembers a transaction of our own, unlike `broadcast_transaction` the
+    /// caller learns whether the chain source took it.
+    pub async fn send(&self, tx: &Transaction) -> Result<(), String> {
+        self.track(tx);
+        let (chain_source, pending, subscribers) = (self.chain_source.clone(), self.pending.clone(), self.subscribers.clone());
+        resend(chain_source, pending, subscribers, tx.clone()).await
+    }
+
+    /// Notes which pending transactions the block confirms or double spends.
+    pub fn block_connected(&self, block: &Block, height: u32) {
+        let mut txn = self.pending.txn.lock().unwrap();
+        let mut spent_by_pending = HashMap::new();
+        for (txid, pending) in txn.iter().filter(|(_, p)| p.confirmed_at.is_none()) {
+            for input in decode_tx(&pending.tx).map(|tx| tx.input).unwrap_or_default() {
+                spent_by_pending.insert(input.previous_output, *txid);
+            }
         }
+        let mut changed = false;
+        for block_tx in &block.txdata {
+            let block_txid = block_tx.txid();
+            if let Some(pending) = txn.get_mut(&block_txid) {
+                pending.confirmed_at = Some(height);
+                pending.conflicted = false;
+                changed = true;
+                continue;
+            }
+            for input in &block_tx.input {
+                let txid = match spent_by_pending.get(&input.previous_output) {
+                    Some(txid) => *txid,
+                    None => continue,
+                };
+                if let Some(pending) = txn.get_mut(&txid) {
+                    if pending.confirmed_at.is_none() {
+                        let error = format!("double spent by {} in block {}", block_txid, height);
+                        warn!("{} was {}", txid, error);
+                        self.subscribers.publish(NodeEvent::BroadcastFailed { txid: txid.to_string(), error });
+                        pending.confirmed_at = Some(height);
+                        pending.conflicted = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if changed {
+            self.pending.persist(&txn);
+        }
+    }
 
-        // TODO: There is nothing to rebroadcast
-        // let actions = txn.iter().map(|(_, tx)| {
-        //     let tx_ser = format!("\"{}\"", &encode::serialize_hex(&tx.clone()));
-        //     let tx_ser = [&tx_ser[..]];
-        //     async move {
-        //         self.rpc_client.make_rpc_call("sendrawtransaction", &tx_ser, true).await;
-        //     }
-        // });
-        // future::join_all(actions).await;
+    /// Forgets the confirmations a reorg undid.
+    pub fn block_disconnected(&self, height: u32) {
+        let mut txn = self.pending.txn.lock().unwrap();
+        let mut changed = false;
+        for pending in txn.values_mut() {
+            if pending.confirmed_at.map(|h| h >= height).unwrap_or(false) {
+                pending.confirmed_at = None;
+                pending.conflicted = false;
+                changed = true;
+            }
+        }
+        if changed {
+            self.pending.persist(&txn);
+        }
+    }
+
+    /// Prunes what is `confirmations` deep at `tip` and resends the rest.
+    pub async fn rebroadcast_txn(&self, tip: u32) {
+        let unconfirmed: Vec<Transaction> = {
+            let mut txn = self.pending.txn.lock().unwrap();
+            let before = txn.len();
+            let confirmations = self.confirmations;
+            txn.retain(|txid, pending| match pending.confirmed_at {
+                Some(height) if tip + 1 >= height + confirmations => {
+                    if pending.conflicted {
+                        info!("Conflicting spend of {} is {} blocks deep, giving up on it", txid, confirmations);
+                    } else {
+                        info!("{} is {} blocks deep, done rebroadcasting it", txid, confirmations);
+                    }
+                    false
+                }
+                _ => true,
+            });
+            if txn.len() != before {
+                self.pending.persist(&txn);
+            }
+            txn.iter()
+                .filter(|(_, p)| p.confirmed_at.is_none())
+                .filter_map(|(_, p)| decode_tx(&p.tx))
+                .collect()
+        };
+        for tx in unconfirmed {
+            let txid = tx.txid();
+            let (chain_source, pending, subscribers) = (self.chain_source.clone(), self.pending.clone(), self.subscribers.clone());
+            if resend(chain_source, pending, subscribers, tx).await.is_ok() {
+                debug!("Rebroadcast {}", txid);
+            }
+        }
     }
 }
 
 impl<T: Sync + Send + Larva> chaininterface::BroadcasterInterface for ChainBroadcaster<T> {
-    fn broadcast_transaction(&self, tx: &bitcoin::blockdata::transaction::Transaction) {
-        self.txn_to_broadcast.lock().unwrap().insert(tx.txid(), tx.clone());
-        let tx_ser = format!("\"{}\"", &encode::serialize_hex(tx));
-        let async_client = self.rpc_client.clone();
+    fn broadcast_transaction(&self, tx: &Transaction) {
+        self.track(tx);
+        let (chain_source, pending, subscribers) = (self.chain_source.clone(), self.pending.clone(), self.subscribers.clone());
+        let tx = tx.clone();
         let _ = self.larva.clone().spawn_task(async move {
-            let k = &[&tx_ser[..]];
-            let _ = async_client.make_rpc_call("sendrawtransaction", k, true).await;
+            let _ = resend(chain_source, pending, subscribers, tx).await;
             Ok(())
         });
     }
 }
 
-enum ForkStep {
-    DisconnectBlock(bitcoin::blockdata::block::BlockHeader, u32),
-    ConnectBlock((String, u32)),
+#[derive(Debug, PartialEq)]
+pub enum ForkStep {
+    DisconnectBlock(BlockHeader, u32),
+    ConnectBlock(String, u32),
 }
 
-fn find_fork_step(
-    mut steps_tx: mpsc::Sender<ForkStep>,
-    current_header: GetHeaderResponse,
-    target_header_opt: Option<(String, GetHeaderResponse)>,
-    rpc_client: Arc<RPCClient>
-) {
-    debug!(">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>> FIND FORK STEP >>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>");
-    debug!("current height: {}", &current_header.height);
-    debug!("target height: {}", target_header_opt.as_ref().unwrap().1.height);
-    debug!(">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>> FIND FORK STEP >>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>");
-
-    if target_header_opt.is_some()
-        && target_header_opt.as_ref().unwrap().0 == current_header.previousblockhash
-    {
-        // Target is the parent of current, we're done!
-        return
-    }
-    if current_header.height == 1 {
-        return;
-    }
-
-    if target_header_opt.is_none()
-        || target_header_opt.as_ref().unwrap().1.height < current_header.height
-    {
-        // currentheader--
-        let send_res = steps_tx.try_send(ForkStep::ConnectBlock((
-            current_header.previousblockhash.clone(),
-            current_header.height - 1,
-        )));
-        match send_res {
-            Ok(_) => {
-                // Spawn a future onto the runtime
-                thread::spawn(move || {
-                    let mut rt = Runtime::new().unwrap();
-                    rt.block_on(async move { 
-                        debug!("recursive");
-                        let new_cur_header = rpc_client.get_block_header(&current_header.previousblockhash).await;
-                        find_fork_step(
-                            steps_tx,
-                            new_cur_header.unwrap(),
-                            target_header_opt,
-                            rpc_client
-                        );
-                    });
-                });
-                return;
+/// Walks back from `new_block` and `old_block` to their common ancestor. Returns the blocks to
+/// disconnect from `old_block` down, then the blocks to connect up to `new_block`.
+pub async fn find_fork(
+    chain_source: &dyn ChainSource,
+    new_block: &str,
+    old_block: &str,
+) -> Result<Vec<ForkStep>, String> {
+    let mut new_header = chain_source.get_header(new_block).await?;
+    let mut old_header = chain_source.get_header(old_block).await?;
+    let mut disconnect = vec![];
+    let mut connect = vec![];
+    while new_header.hash != old_header.hash {
+        let (walk_new, walk_old) = (
+            new_header.height >= old_header.height,
+            old_header.height >= new_header.height,
+        );
+        if walk_old {
+            disconnect.push(ForkStep::DisconnectBlock(old_header.header, old_header.height));
+            old_header = chain_source.get_header(&old_header.prev_hash()).await?;
+        }
+        if walk_new {
+            connect.push(ForkStep::ConnectBlock(new_header.hash.clone(), new_header.height));
+            new_header = chain_source.get_header(&new_header.prev_hash()).await?;
+        }
+    }
+    if disconnect.is_empty() && connect.len() > 1 {
+        info!("Found non consecutive blocks ... scanning ...");
+    }
+    disconnect.extend(connect.into_iter().rev());
+    Ok(disconnect)
+}
 
+/// Feeds the blocks from `cur_block` to `new_block` to the watcher and the broadcaster. If the
+/// chain source fails midway `cur_block` is left at the last block they saw.
+pub async fn sync_chain<T>(
+    chain_source: &dyn ChainSource,
+    cur_block: &Mutex<String>,
+    new_block: &str,
+    chain_watcher: &ChainWatchInterfaceUtil,
+    chain_broadcaster: &ChainBroadcaster<T>,
+) -> Result<(), String> {
+    let old_block = cur_block.lock().unwrap().clone();
+    for step in find_fork(chain_source, new_block, &old_block).await? {
+        match step {
+            ForkStep::DisconnectBlock(header, height) => {
+                info!("Disconnecting block {}", header.bitcoin_hash().to_hex());
+                chain_watcher.block_disconnected(&header, height);
+                chain_broadcaster.block_disconnected(height);
+                *cur_block.lock().unwrap() = header.prev_blockhash.to_hex();
             }
-            Err(e) => {
-                debug!("try send failed, {}", e);
-                return;
-            }
-        }
-    } else {
-        // is_some == True 1 2 3 4
-        let target_header = target_header_opt.unwrap().1;
-        // Everything below needs to disconnect target, so go ahead and do that now
-        let c_header = target_header.clone();
-        let c_height = target_header.clone().height;
-        let send_res = block_on(
-            steps_tx.send(ForkStep::DisconnectBlock(c_header.into(), c_height))
-        );
-        if let Ok(_) = send_res {
-            // send err match
-            if target_header.previousblockhash == current_header.previousblockhash {
-                // Found the fork, also connect current and finish!
-                let _ = steps_tx.try_send(ForkStep::ConnectBlock((
-                    current_header.previousblockhash.clone(),
-                    current_header.height - 1,
-                )));
-                return;
-            } else if target_header.height > current_header.height {
-                // Target is higher, walk it back and recurse
-                thread::spawn(move || {
-                    let mut rt = Runtime::new().unwrap();
-                    rt.block_on(async move { 
-                        let new_target_header = rpc_client.get_block_header(&target_header.previousblockhash).await;
-                        find_fork_step(
-                            steps_tx,
-                            current_header,
-                            Some((
-                                target_header.previousblockhash,
-                                new_target_header.unwrap(),
-                            )),
-                            rpc_client
-                        );
-                    });
-                });
-                return;
-            } else {
-                // Target and current are at the same height, but we're not at fork yet, walk
-                // both back and recurse
-                let send_res = steps_tx.try_send(ForkStep::ConnectBlock((
-                    current_header.previousblockhash.clone(),
-                    current_header.height - 1,
-                )));
-                if let Ok(_) = send_res {
-                    thread::spawn(move || {
-                        let mut rt = Runtime::new().unwrap();
-                        rt.block_on(async move { 
-                            let new_cur_header = rpc_client.get_block_header(&current_header.previousblockhash).await;
-                            let new_target_header = rpc_client.get_block_header(&target_header.previousblockhash).await;
-                            find_fork_step(
-                                steps_tx,
-                                new_cur_header.unwrap(),
-                                Some((
-                                    target_header.previousblockhash,
-                                    new_target_header.unwrap(),
-                                )),
-                                rpc_client,
-                            );
-                        });
-                    });
-                    return;
-                } else {
-                    // Caller droped the receiver, we should give up now
-                    return;
-                }
+            ForkStep::ConnectBlock(hash, height) => {
+                let block = chain_source.get_block(&hash).await?;
+                chain_watcher.block_connected_with_filtering(&block, height);
+                chain_broadcaster.block_connected(&block, height);
+                info!("Connecting block {}, Height: {}", hash, height);
+                *cur_block.lock().unwrap() = hash;
             }
-        } else {
-            // Caller droped the receiver, we should give up now
-            return;
         }
     }
+    Ok(())
 }
-/// Walks backwards from current_hash and target_hash finding the fork and sending ForkStep events
-/// into the steps_tx Sender. There is no ordering guarantee between different ForkStep types, but
-/// DisconnectBlock and ConnectBlock events are each in reverse, height-descending order.
-
-async fn find_fork(
-    mut steps_tx: mpsc::Sender<ForkStep>,
-    current_hash: String,
-    target_hash: String,
-    rpc_client: Arc<RPCClient>,
-) {
-    if current_hash == target_hash {
-        return;
-    }
-    let current_resp = rpc_client.get_block_header(&current_hash).await;
-    let current_header = current_resp.unwrap();
-    if let Ok(_) = steps_tx.start_send(ForkStep::ConnectBlock((
-        current_hash.clone(),
-        current_header.height
-    ))) {
-        if current_header.previousblockhash == target_hash || current_header.height == 1 {
-            // Fastpath one-new-block-connected or reached block 1
-            info!("New block discovered.."); 
-            info!("New Height: {}", &current_header.height); 
-            info!("New Hash: {}", &current_hash); 
-            return;
-        } else {
-            if let Ok(target_header) = rpc_client.get_block_header(&target_hash).await {
-                // Skipped few blocks rescan
-                info!("Found non consecutive blocks ... scanning ...");
-                find_fork_step(
-                    steps_tx,
-                    current_header,
-                    Some((target_hash, target_header)),
-                    rpc_client,
-                )
-            } else {
-                warn!("Unknown block hash Found ...");
-                // fork
-                assert_eq!(target_hash, "");
-                find_fork_step(
-                    steps_tx,
-                    current_header,
-                    None,
-                    rpc_client,
-                )
+
+/// Replays to `listener` the blocks it missed while we were down, from `last_block` to `tip`,
+/// disconnecting first what a reorg meanwhile undid. Every transaction of a block is handed over,
+/// listeners skip those they don't watch. Returns how many blocks were replayed.
+pub async fn catch_up(
+    chain_source: &dyn ChainSource,
+    listener: &dyn ChainListener,
+    last_block: &Sha256dHash,
+    tip: &str,
+) -> Result<usize, String> {
+    // Written before the first block connected
+    if *last_block == Sha256dHash::default() {
+        return Ok(0);
+    }
+    let steps = find_fork(chain_source, tip, &last_block.to_hex()).await?;
+    let replayed = steps.len();
+    for step in steps {
+        match step {
+            ForkStep::DisconnectBlock(header, height) => listener.block_disconnected(&header, height),
+            ForkStep::ConnectBlock(hash, height) => {
+                let block = chain_source.get_block(&hash).await?;
+                let txn: Vec<&Transaction> = block.txdata.iter().collect();
+                let indexes: Vec<u32> = (0..txn.len() as u32).collect();
+                listener.block_connected(&block.header, height, &txn, &indexes);
             }
         }
     }
+    Ok(replayed)
 }
 
 pub async fn spawn_chain_monitor(
     fee_estimator: Arc<FeeEstimator>,
-    rpc_client: Arc<RPCClient>,
+    chain_source: Arc<dyn ChainSource>,
     chain_watcher: Arc<ChainWatchInterfaceUtil>,
     chain_broadcaster: Arc<ChainBroadcaster<impl Larva>>,
+    sweeper: Arc<Sweeper>,
     event_notify: mpsc::Sender<()>,
+    start_block: String,
+    poll_interval: Duration,
+    block_notifications: mpsc::UnboundedReceiver<()>,
     larva: impl Larva,
 ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let _ = larva.clone().spawn_task(async { 
-        FeeEstimator::update_values(
-            fee_estimator.clone(),
-            rpc_client.clone()
-        )}.await
+    let _ = larva.clone().spawn_task(
+        FeeEstimator::update_values(fee_estimator.clone(), chain_source.clone())
     );
-    let cur_block = Arc::new(Mutex::new(String::from("")));
-    Interval::new(Duration::from_secs(1))
+    let cur_block = Arc::new(Mutex::new(start_block));
+    // Polling catches whatever notifications missed. Each sync finishes before the next one
+    // starts, blocks are never fed twice
+    stream::select(Interval::new(poll_interval), block_notifications)
         .for_each(|_| { async {
             let cur_block = cur_block.clone();
             let fee_estimator = fee_estimator.clone();
-            let rpc_client = rpc_client.clone();
+            let chain_source = chain_source.clone();
             let chain_watcher = chain_watcher.clone();
             let chain_broadcaster = chain_broadcaster.clone();
+            let sweeper = sweeper.clone();
             let mut event_notify = event_notify.clone();
-            let larva = larva.clone();
-            let _ = larva.spawn_task(async move {
-                let v = rpc_client.make_rpc_call("getblockchaininfo", &[], false).await?;
-                let new_block = v["bestblockhash"].as_str().unwrap().to_string();
-                let old_block = cur_block.lock().unwrap().clone();
-
-                if new_block == old_block {
-                    return Ok(());
-                }
 
-                *cur_block.lock().unwrap() = new_block.clone();
-                if old_block == "" {
-                    return Ok(()); 
+            let (new_block, tip) = match chain_source.best_block().await {
+                Ok(best) => best,
+                Err(e) => {
+                    debug!("Could not get the best block: {}", e);
+                    return;
                 }
+            };
+            if new_block == *cur_block.lock().unwrap() {
+                return;
+            }
 
-                let (events_tx, events_rx): (mpsc::Sender<ForkStep>, mpsc::Receiver<ForkStep>) = mpsc::channel(1);
-
-                find_fork(
-                    events_tx,
-                    new_block,
-                    old_block,
-                    rpc_client.clone(),
-                ).await;
-
-                let events: Vec<ForkStep> = events_rx.collect().await;
-
-                let actions = events.into_iter().rev().map(|event| {
-                    let client = rpc_client.clone();
-                    let watcher = chain_watcher.clone();
-                    async move {
-                        match event {
-                            ForkStep::DisconnectBlock(ref header, height) => {
-                                info!("Disconnecting block {}", header.bitcoin_hash().to_hex());
-                                watcher.block_disconnected(header, height);
-                            }
-                            ForkStep::ConnectBlock((ref hash, height)) => {
-                                let block_height = height;
-                                let param = &[&("\"".to_string() + hash + "\""), "0"];
-                                let block_hex = client.make_rpc_call("getblock", param, false).await;
-                                let block: Block = encode::deserialize(
-                                    &hex_to_vec(block_hex.unwrap().as_str().unwrap()).unwrap()
-                                ).unwrap();
-                                watcher.block_connected_with_filtering(&block, block_height);
-                                info!("Connecting block {}, Height: {}", block.bitcoin_hash().to_hex(), &block_height);
-                            }
-                        }
-                    }
-                });
-                
-                let _ = future::join_all(actions).await;
-                let _ = FeeEstimator::update_values(fee_estimator, rpc_client).await;
-                let _ = event_notify.try_send(());
-                chain_broadcaster.rebroadcast_txn().await;
-                Ok(())
-            });
+            if let Err(e) = sync_chain(&*chain_source, &cur_block, &new_block, &*chain_watcher, &*chain_broadcaster).await {
+                error!("Could not follow the chain to {}: {}", new_block, e);
+                return;
+            }
+
+            let _ = FeeEstimator::update_values(fee_estimator, chain_source).await;
+            let _ = event_notify.try_send(());
+            chain_broadcaster.rebroadcast_txn(tip).await;
+            sweeper.sweep().await;
         }
     }).await;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ln_bridge::chain_source::mock::MockChain;
+    use crate::ln_bridge::channel_monitor::ChannelMonitor;
+    use crate::ln_bridge::log_printer::LogPrinter;
+    use bitcoin::blockdata::script::Script;
+    use bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use bitcoin::network::constants::Network;
+    use bitcoin_hashes::hex::FromHex;
+    use futures::executor::block_on;
+    use futures::task::SpawnError;
+    use lightning::util::logger::Level;
+
+    // Nothing here goes through `broadcast_transaction`, the only caller of `spawn_task`
+    #[derive(Clone)]
+    struct NoLarva;
+
+    impl Larva for NoLarva {
+        fn spawn_task(
+            &self,
+            _task: impl Future<Output = Result<(), ()>> + Send + 'static,
+        ) -> Result<(), SpawnError> {
+            Ok(())
+        }
+    }
+
+    fn data_path(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("rustbolt-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    fn spend(txid: Sha256dHash, value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint { txid, vout: 0 },
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value, script_pubkey: Script::new() }],
+        }
+    }
+
+    fn setup(test: &str) -> (Arc<MockChain>, ChainWatchInterfaceUtil, ChainBroadcaster<NoLarva>, Arc<EventSubscribers>) {
+        let chain = Arc::new(MockChain::new());
+        let logger = Arc::new(LogPrinter { level: Level::Debug });
+        let subscribers = Arc::new(EventSubscribers::default());
+        let broadcaster = ChainBroadcaster::load(&data_path(test), 6, chain.clone(), subscribers.clone(), NoLarva);
+        (chain, ChainWatchInterfaceUtil::new(Network::Regtest, logger), broadcaster, subscribers)
+    }
+
+    #[test]
+    fn find_fork_connects_new_blocks() {
+        let chain = MockChain::new();
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let b2 = chain.mine(&b1, vec![]);
+        let b3 = chain.mine(&b2, vec![]);
+        let steps = block_on(find_fork(&chain, &b3, &b1)).unwrap();
+        assert_eq!(steps, vec![ForkStep::ConnectBlock(b2, 2), ForkStep::ConnectBlock(b3, 3)]);
+        assert_eq!(block_on(find_fork(&chain, &b1, &b1)).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn find_fork_walks_back_to_the_fork() {
+        let chain = MockChain::new();
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let a2 = chain.mine(&b1, vec![]);
+        let a3 = chain.mine(&a2, vec![]);
+        let b2 = chain.mine(&b1, vec![]);
+        let b3 = chain.mine(&b2, vec![]);
+        let b4 = chain.mine(&b3, vec![]);
+        assert_eq!(chain.tip(), b4);
+
+        let header = |hash: &str| block_on(chain.get_header(hash)).unwrap().header;
+        let steps = block_on(find_fork(&chain, &b4, &a3)).unwrap();
+        assert_eq!(steps, vec![
+            ForkStep::DisconnectBlock(header(&a3), 3),
+            ForkStep::DisconnectBlock(header(&a2), 2),
+            ForkStep::ConnectBlock(b2, 2),
+            ForkStep::ConnectBlock(b3, 3),
+            ForkStep::ConnectBlock(b4, 4),
+        ]);
+        assert!(block_on(find_fork(&chain, &b4, "00")).is_err());
+    }
+
+    #[test]
+    fn reorg_resends_unconfirmed_broadcast() {
+        let (chain, watcher, broadcaster, _) = setup("reorg");
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let cur_block = Mutex::new(b1.clone());
+
+        let tx = spend(Sha256dHash::default(), 1000);
+        block_on(broadcaster.send(&tx)).unwrap();
+        assert_eq!(chain.broadcasts(), vec![tx.clone()]);
+
+        let a2 = chain.mine(&b1, vec![tx.clone()]);
+        block_on(sync_chain(&*chain, &cur_block, &a2, &watcher, &broadcaster)).unwrap();
+        assert_eq!(*cur_block.lock().unwrap(), a2);
+        block_on(broadcaster.rebroadcast_txn(2));
+        assert_eq!(chain.broadcasts().len(), 1);
+
+        // The block confirming it is reorged out
+        let b2 = chain.mine(&b1, vec![]);
+        let b3 = chain.mine(&b2, vec![]);
+        block_on(sync_chain(&*chain, &cur_block, &b3, &watcher, &broadcaster)).unwrap();
+        assert_eq!(*cur_block.lock().unwrap(), b3);
+        block_on(broadcaster.rebroadcast_txn(3));
+        assert_eq!(chain.broadcasts(), vec![tx.clone(), tx.clone()]);
+
+        // Buried again, then forgotten once deep enough
+        let b4 = chain.mine(&b3, vec![tx.clone()]);
+        block_on(sync_chain(&*chain, &cur_block, &b4, &watcher, &broadcaster)).unwrap();
+        block_on(broadcaster.rebroadcast_txn(9));
+        assert!(broadcaster.sent(&tx.txid().to_string()).is_none());
+    }
+
+    #[test]
+    fn double_spend_is_published() {
+        let (chain, watcher, broadcaster, subscribers) = setup("double-spend");
+        let mut events = subscribers.subscribe();
+        let genesis = chain.tip();
+        let cur_block = Mutex::new(genesis.clone());
+
+        chain.refuse(Some("insufficient fee"));
+        let tx = spend(Sha256dHash::default(), 1000);
+        assert!(block_on(broadcaster.send(&tx)).is_err());
+        match events.try_next() {
+            Ok(Some(NodeEvent::BroadcastFailed { txid, error })) => {
+                assert_eq!(txid, tx.txid().to_string());
+                assert_eq!(error, "insufficient fee");
+            }
+            _ => panic!("Expected broadcast_failed"),
+        }
+
+        let conflict = spend(Sha256dHash::default(), 900);
+        let b1 = chain.mine(&genesis, vec![conflict.clone()]);
+        block_on(sync_chain(&*chain, &cur_block, &b1, &watcher, &broadcaster)).unwrap();
+        match events.try_next() {
+            Ok(Some(NodeEvent::BroadcastFailed { txid, error })) => {
+                assert_eq!(txid, tx.txid().to_string());
+                assert!(error.contains(&conflict.txid().to_string()));
+            }
+            _ => panic!("Expected broadcast_failed"),
+        }
+    }
+
+    // Height, transaction count, and whether it was connected, of every block it was told about
+    #[derive(Default)]
+    struct Recorder {
+        steps: Mutex<Vec<(u32, usize, bool)>>,
+    }
+
+    impl ChainListener for Recorder {
+        fn block_connected(&self, _header: &BlockHeader, height: u32, txn_matched: &[&Transaction], _indexes_of_txn_matched: &[u32]) {
+            self.steps.lock().unwrap().push((height, txn_matched.len(), true));
+        }
+
+        fn block_disconnected(&self, _header: &BlockHeader, disconnected_height: u32) {
+            self.steps.lock().unwrap().push((disconnected_height, 0, false));
+        }
+    }
+
+    // Hands the blocks on to a channel's monitor and keeps the block it ends on
+    struct Tracker<'a> {
+        monitor: &'a dyn ChainListener,
+        recorder: Recorder,
+        last_block: Mutex<Option<(Sha256dHash, u32)>>,
+    }
+
+    impl<'a> Tracker<'a> {
+        fn new(monitor: &'a dyn ChainListener) -> Self {
+            Tracker { monitor, recorder: Recorder::default(), last_block: Mutex::new(None) }
+        }
+    }
+
+    impl<'a> ChainListener for Tracker<'a> {
+        fn block_connected(&self, header: &BlockHeader, height: u32, txn_matched: &[&Transaction], indexes_of_txn_matched: &[u32]) {
+            self.monitor.block_connected(header, height, txn_matched, indexes_of_txn_matched);
+            self.recorder.block_connected(header, height, txn_matched, indexes_of_txn_matched);
+            *self.last_block.lock().unwrap() = Some((header.bitcoin_hash(), height));
+        }
+
+        fn block_disconnected(&self, header: &BlockHeader, disconnected_height: u32) {
+            self.monitor.block_disconnected(header, disconnected_height);
+            self.recorder.block_disconnected(header, disconnected_height);
+            *self.last_block.lock().unwrap() = Some((header.prev_blockhash, disconnected_height - 1));
+        }
+    }
+
+    #[test]
+    fn catch_up_from_the_last_block() {
+        let chain = MockChain::new();
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let b2 = chain.mine(&b1, vec![spend(Sha256dHash::default(), 1000), spend(Sha256dHash::default(), 900)]);
+        let b3 = chain.mine(&b2, vec![]);
+        let header = |hex: &str| block_on(chain.get_header(hex)).unwrap().header.bitcoin_hash();
+
+        let recorder = Recorder::default();
+        assert_eq!(block_on(catch_up(&chain, &recorder, &header(&b1), &b3)), Ok(2));
+        assert_eq!(*recorder.steps.lock().unwrap(), vec![(2, 2, true), (3, 0, true)]);
+
+        // Up to date, or never saw a block
+        let recorder = Recorder::default();
+        assert_eq!(block_on(catch_up(&chain, &recorder, &header(&b3), &b3)), Ok(0));
+        assert_eq!(block_on(catch_up(&chain, &recorder, &Sha256dHash::default(), &b3)), Ok(0));
+        assert!(recorder.steps.lock().unwrap().is_empty());
+
+        // A block the chain source doesn't know, we can't tell what was missed
+        let unknown = Sha256dHash::from_hex(&"11".repeat(32)).unwrap();
+        assert!(block_on(catch_up(&chain, &recorder, &unknown, &b3)).is_err());
+    }
+
+    #[test]
+    fn catch_up_across_a_reorg() {
+        let chain = MockChain::new();
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let a2 = chain.mine(&b1, vec![]);
+        let a3 = chain.mine(&a2, vec![]);
+        let b2 = chain.mine(&b1, vec![]);
+        let b3 = chain.mine(&b2, vec![]);
+        let b4 = chain.mine(&b3, vec![]);
+        let last_block = block_on(chain.get_header(&a3)).unwrap().header.bitcoin_hash();
+
+        let recorder = Recorder::default();
+        assert_eq!(block_on(catch_up(&chain, &recorder, &last_block, &b4)), Ok(5));
+        assert_eq!(*recorder.steps.lock().unwrap(), vec![
+            (3, 0, false),
+            (2, 0, false),
+            (2, 0, true),
+            (3, 0, true),
+            (4, 0, true),
+        ]);
+    }
+
+    #[test]
+    fn catch_up_monitors_on_different_branches() {
+        let (chain, watcher, broadcaster, _) = setup("monitor-branches");
+        let genesis = chain.tip();
+        let b1 = chain.mine(&genesis, vec![]);
+        let a2 = chain.mine(&b1, vec![]);
+        let a3 = chain.mine(&a2, vec![]);
+        let b2 = chain.mine(&b1, vec![]);
+        let b3 = chain.mine(&b2, vec![]);
+        let b4 = chain.mine(&b3, vec![]);
+        let header = |hex: &str| block_on(chain.get_header(hex)).unwrap().header.bitcoin_hash();
+
+        // Every channel gets a listener of its own
+        let monitor = ChannelMonitor::new(
+            Arc::new(watcher),
+            Arc::new(broadcaster),
+            Arc::new(LogPrinter { level: Level::Debug }),
+            Arc::new(FeeEstimator::new()),
+            String::new(),
+            None,
+        );
+        let funding_a = lightning::chain::transaction::OutPoint { txid: Sha256dHash::default(), index: 0 };
+        let funding_b = lightning::chain::transaction::OutPoint { txid: Sha256dHash::default(), index: 1 };
+        assert!(Arc::ptr_eq(&monitor.channel(&funding_a), &monitor.channel(&funding_a)));
+        assert!(!Arc::ptr_eq(&monitor.channel(&funding_a), &monitor.channel(&funding_b)));
+
+        // One saw the branch a reorg undid, the other stopped on the chain before it
+        let channel_a = monitor.channel(&funding_a);
+        let channel_b = monitor.channel(&funding_b);
+        let on_stale = Tracker::new(&*channel_a);
+        let on_chain = Tracker::new(&*channel_b);
+        assert_eq!(block_on(catch_up(&*chain, &on_stale, &header(&a3), &b4)), Ok(5));
+        assert_eq!(*on_stale.recorder.steps.lock().unwrap(), vec![
+            (3, 0, false),
+            (2, 0, false),
+            (2, 0, true),
+            (3, 0, true),
+            (4, 0, true),
+        ]);
+        assert_eq!(block_on(catch_up(&*chain, &on_chain, &header(&b2), &b4)), Ok(2));
+        assert_eq!(*on_chain.recorder.steps.lock().unwrap(), vec![(3, 0, true), (4, 0, true)]);
+
+        // Both end on the tip
+        assert_eq!(*on_stale.last_block.lock().unwrap(), Some((header(&b4), 4)));
+        assert_eq!(*on_chain.last_block.lock().unwrap(), Some((header(&b4), 4)));
+    }
+
+    #[test]
+    fn already_confirmed_broadcast_is_not_resent() {
+        let (chain, _, broadcaster, _) = setup("already-confirmed");
+        let genesis = chain.tip();
+        chain.mine(&genesis, vec![]);
+
+        // Mined while we were down, the chain source knows it but we never saw its block
+        chain.refuse(Some("Transaction already in block chain"));
+        let tx = spend(Sha256dHash::default(), 1000);
+        block_on(broadcaster.send(&tx)).unwrap();
+        chain.refuse(None);
+        block_on(broadcaster.rebroadcast_txn(5));
+        assert!(chain.broadcasts().is_empty());
+        assert!(broadcaster.sent(&tx.txid().to_string()).is_some());
+
+        // Forgotten once deep enough counting from the tip it was found at
+        block_on(broadcaster.rebroadcast_txn(6));
+        assert!(broadcaster.sent(&tx.txid().to_string()).is_none());
+    }
+
+    #[test]
+    fn fee_estimates_come_from_the_chain_source() {
+        let chain = Arc::new(MockChain::new());
+        let fee_estimator = Arc::new(FeeEstimator::new());
+        block_on(FeeEstimator::update_values(fee_estimator.clone(), chain.clone())).unwrap();
+        let normal = chaininterface::FeeEstimator::get_est_sat_per_1000_weight(
+            &*fee_estimator,
+            chaininterface::ConfirmationTarget::Normal,
+        );
+        assert_eq!(normal, 253);
+
+        chain.set_fee(100.0);
+        block_on(FeeEstimator::update_values(fee_estimator.clone(), chain.clone())).unwrap();
+        let normal = chaininterface::FeeEstimator::get_est_sat_per_1000_weight(
+            &*fee_estimator,
+            chaininterface::ConfirmationTarget::Normal,
+        );
+        assert_eq!(normal, 25_003);
+
+        // Anything under 1 sat/vB is raised to the relay minimum
+        chain.set_fee(0.5);
+        block_on(FeeEstimator::update_values(fee_estimator.clone(), chain.clone())).unwrap();
+        let normal = chaininterface::FeeEstimator::get_est_sat_per_1000_weight(
+            &*fee_estimator,
+            chaininterface::ConfirmationTarget::Normal,
+        );
+        assert_eq!(normal, 253);
+    }
+}
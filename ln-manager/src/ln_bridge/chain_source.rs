@@ -0,0 +1,171 @@
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin_hashes::hex::ToHex;
+
+use futures::future::BoxFuture;
+
+/// A block header along with the hash and height the backend knows it by.
+#[derive(Clone, Debug)]
+pub struct HeaderInfo {
+    pub hash: String,
+    pub height: u32,
+    pub header: BlockHeader,
+}
+
+impl HeaderInfo {
+    /// Hash of the parent block, as bitcoind prints it.
+    pub fn prev_hash(&self) -> String {
+        self.header.prev_blockhash.to_hex()
+    }
+}
+
+/// Where the chain monitor, the fee estimator and the broadcaster get blocks and fee estimates
+/// from, and where every transaction of the node is sent. Wallet calls (funding, signing sweeps,
+/// key import) stay on `RPCClient`, this is only the view of the chain.
+pub trait ChainSource: Send + Sync {
+    /// Hash and height of the best block.
+    fn best_block(&self) -> BoxFuture<'_, Result<(String, u32), String>>;
+
+    fn get_header<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HeaderInfo, String>>;
+
+    fn get_block<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Block, String>>;
+
+    /// Feerate in sat/vB to confirm within `conf_target` blocks, None until the backend has seen
+    /// enough transactions to tell (regtest).
+    fn estimate_fee(&self, conf_target: u16) -> BoxFuture<'_, Result<Option<f64>, String>>;
+
+    /// Returns the txid, or why the transaction was refused.
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> BoxFuture<'a, Result<String, String>>;
+
+    /// Whether the transaction is in the mempool or the chain. Only Ok(false) when the backend
+    /// is sure it isn't, an error otherwise.
+    fn has_transaction<'a>(&'a self, txid: &'a str) -> BoxFuture<'a, Result<bool, String>>;
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::network::constants::Network;
+    use bitcoin::util::hash::BitcoinHash;
+    use futures::future::{self, FutureExt};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    struct State {
+        blocks: HashMap<String, (Block, u32)>,
+        tip: String,
+        sat_per_vbyte: Option<f64>,
+        refusal: Option<String>,
+        broadcasts: Vec<Transaction>,
+    }
+
+    /// A chain in memory: blocks get mined on top of any block it knows, the first block to
+    /// reach a new height becomes the tip like in bitcoind.
+    pub struct MockChain {
+        state: Mutex<State>,
+    }
+
+    impl MockChain {
+        /// Starts at the regtest genesis block.
+        pub fn new() -> Self {
+            let genesis = genesis_block(Network::Regtest);
+            let tip = genesis.bitcoin_hash().to_hex();
+            let mut blocks = HashMap::new();
+            blocks.insert(tip.clone(), (genesis, 0));
+            MockChain {
+                state: Mutex::new(State { blocks, tip, sat_per_vbyte: None, refusal: None, broadcasts: vec![] }),
+            }
+        }
+
+        pub fn tip(&self) -> String {
+            self.state.lock().unwrap().tip.clone()
+        }
+
+        /// Mines a block holding `txdata` on top of `parent`, returns its hash.
+        pub fn mine(&self, parent: &str, txdata: Vec<Transaction>) -> String {
+            let mut state = self.state.lock().unwrap();
+            let (prev_blockhash, height) = {
+                let (parent_block, parent_height) = &state.blocks[parent];
+                (parent_block.bitcoin_hash(), parent_height + 1)
+            };
+            let header = BlockHeader {
+                version: 1,
+                prev_blockhash,
+                merkle_root: prev_blockhash,
+                time: 1_296_688_602 + height * 600,
+                bits: 0x207f_ffff,
+                // Siblings must not share a hash
+                nonce: state.blocks.len() as u32,
+            };
+            let block = Block { header, txdata };
+            let hash = block.bitcoin_hash().to_hex();
+            if height > state.blocks[&state.tip].1 {
+                state.tip = hash.clone();
+            }
+            state.blocks.insert(hash.clone(), (block, height));
+            hash
+        }
+
+        pub fn set_fee(&self, sat_per_vbyte: f64) {
+            self.state.lock().unwrap().sat_per_vbyte = Some(sat_per_vbyte);
+        }
+
+        /// Makes every broadcast fail with `refusal`, or succeed again with None.
+        pub fn refuse(&self, refusal: Option<&str>) {
+            self.state.lock().unwrap().refusal = refusal.map(|r| r.to_string());
+        }
+
+        /// Every transaction accepted so far, in order.
+        pub fn broadcasts(&self) -> Vec<Transaction> {
+            self.state.lock().unwrap().broadcasts.clone()
+        }
+    }
+
+    impl ChainSource for MockChain {
+        fn best_block(&self) -> BoxFuture<'_, Result<(String, u32), String>> {
+            let state = self.state.lock().unwrap();
+            let best = (state.tip.clone(), state.blocks[&state.tip].1);
+            future::ready(Ok(best)).boxed()
+        }
+
+        fn get_header<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HeaderInfo, String>> {
+            let res = match self.state.lock().unwrap().blocks.get(hash) {
+                Some((block, height)) => Ok(HeaderInfo { hash: hash.to_string(), height: *height, header: block.header }),
+                None => Err(format!("Unknown block {}", hash)),
+            };
+            future::ready(res).boxed()
+        }
+
+        fn get_block<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Block, String>> {
+            let res = match self.state.lock().unwrap().blocks.get(hash) {
+                Some((block, _)) => Ok(block.clone()),
+                None => Err(format!("Unknown block {}", hash)),
+            };
+            future::ready(res).boxed()
+        }
+
+        fn estimate_fee(&self, _conf_target: u16) -> BoxFuture<'_, Result<Option<f64>, String>> {
+            future::ready(Ok(self.state.lock().unwrap().sat_per_vbyte)).boxed()
+        }
+
+        fn broadcast<'a>(&'a self, tx: &'a Transaction) -> BoxFuture<'a, Result<String, String>> {
+            let mut state = self.state.lock().unwrap();
+            let res = match state.refusal.clone() {
+                Some(refusal) => Err(refusal),
+                None => {
+                    state.broadcasts.push(tx.clone());
+                    Ok(tx.txid().to_string())
+                }
+            };
+            future::ready(res).boxed()
+        }
+
+        fn has_transaction<'a>(&'a self, txid: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+            let state = self.state.lock().unwrap();
+            let known = state.broadcasts.iter().chain(state.blocks.values().flat_map(|(block, _)| block.txdata.iter()))
+                .any(|tx| tx.txid().to_string() == txid);
+            future::ready(Ok(known)).boxed()
+        }
+    }
+}
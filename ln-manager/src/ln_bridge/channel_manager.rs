@@ -2,7 +2,8 @@ use std::sync::Arc;
 use std::fs;
 use std::collections::HashMap;
 
-use bitcoin::network::constants::Network;
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::util::hash::BitcoinHash;
 use bitcoin_hashes::sha256d::Hash;
 
 use lightning::chain::keysinterface::{KeysInterface};
@@ -10,11 +11,13 @@ use lightning::chain::chaininterface::{FeeEstimator, ChainWatchInterface, Broadc
 use lightning::chain::transaction::OutPoint;
 use lightning::ln::channelmanager::{ChannelManager, ChannelManagerReadArgs};
 use lightning::ln::channelmonitor::{ChannelMonitor, ManyChannelMonitor};
-use lightning::util::ser::ReadableArgs;
+use lightning::util::ser::{ReadableArgs, Writeable};
 use lightning::util::config::UserConfig;
 use lightning::util::logger::{Logger};
 
+use super::signet;
 use super::Restorable;
+use crate::utils::{to_network, Chain};
 
 const FEE_PROPORTIONAL_MILLIONTHS: u32 = 10;
 const ANNOUNCE_CHANNELS: bool = true;
@@ -22,7 +25,7 @@ const ANNOUNCE_CHANNELS: bool = true;
 pub struct RestoreArgs {
     data_path: String,
     monitors_loaded: Vec<(OutPoint, ChannelMonitor)>,
-    network: Network,
+    chain: Chain,
     fee_estimator: Arc<dyn FeeEstimator>,
     monitor: Arc<dyn ManyChannelMonitor>,
     chain_watcher: Arc<dyn ChainWatchInterface>,
@@ -35,7 +38,7 @@ impl RestoreArgs {
     pub fn new(
         data_path: String,
         monitors_loaded: Vec<(OutPoint, ChannelMonitor)>,
-        network: Network,
+        chain: Chain,
         fee_estimator: Arc<dyn FeeEstimator>,
         monitor: Arc<dyn ManyChannelMonitor>,
         chain_watcher: Arc<dyn ChainWatchInterface>,
@@ -44,36 +47,42 @@ impl RestoreArgs {
         keys_manager: Arc<dyn KeysInterface>,
     ) -> Self {
         RestoreArgs {
-            data_path, monitors_loaded, network, fee_estimator,
+            data_path, monitors_loaded, chain, fee_estimator,
             monitor, chain_watcher, tx_broadcaster,
             logger, keys_manager,
         }
     }
 }
 
-impl Restorable<RestoreArgs, Arc<ChannelManager>> for ChannelManager {
-    fn try_restore(args: RestoreArgs) -> Arc<ChannelManager> {
-        let mut config = UserConfig::new();
-        config.channel_options.fee_proportional_millionths = FEE_PROPORTIONAL_MILLIONTHS;
-        config.channel_options.announced_channel = ANNOUNCE_CHANNELS;
+fn user_config() -> UserConfig {
+    let mut config = UserConfig::new();
+    config.channel_options.fee_proportional_millionths = FEE_PROPORTIONAL_MILLIONTHS;
+    config.channel_options.announced_channel = ANNOUNCE_CHANNELS;
+    config
+}
 
-        if let Ok(mut f) = fs::File::open(args.data_path + "/manager_data") {
-            let (_last_block_hash, manager) = {
-                let mut monitors_refs = HashMap::new();
-                for (outpoint, monitor) in args.monitors_loaded.iter() {
-                    monitors_refs.insert(*outpoint, monitor);
-                }
-                <(Hash, ChannelManager)>::read(&mut f, ChannelManagerReadArgs {
-                    keys_manager: args.keys_manager,
-                    fee_estimator: args.fee_estimator,
-                    monitor: args.monitor.clone(),
-                    chain_monitor: args.chain_watcher.clone(),
-                    tx_broadcaster: args.tx_broadcaster,
-                    logger: args.logger,
-                    default_config: config,
-                    channel_monitors: &monitors_refs,
-                }).expect("Failed to deserialize channel manager")
-            };
+fn read_manager<R: std::io::Read>(content: &mut R, args: &RestoreArgs) -> (Hash, ChannelManager) {
+    let mut monitors_refs = HashMap::new();
+    for (outpoint, monitor) in args.monitors_loaded.iter() {
+        monitors_refs.insert(*outpoint, monitor);
+    }
+    <(Hash, ChannelManager)>::read(content, ChannelManagerReadArgs {
+        keys_manager: args.keys_manager.clone(),
+        fee_estimator: args.fee_estimator.clone(),
+        monitor: args.monitor.clone(),
+        chain_monitor: args.chain_watcher.clone(),
+        tx_broadcaster: args.tx_broadcaster.clone(),
+        logger: args.logger.clone(),
+        default_config: user_config(),
+        channel_monitors: &monitors_refs,
+    }).expect("Failed to deserialize channel manager")
+}
+
+/// Restores the manager along with the last block it saw, all zeroes for a new one.
+impl Restorable<RestoreArgs, (Arc<ChannelManager>, Hash)> for ChannelManager {
+    fn try_restore(args: RestoreArgs) -> (Arc<ChannelManager>, Hash) {
+        if let Ok(mut f) = fs::File::open(args.data_path.clone() + "/manager_data") {
+            let (last_block_hash, manager) = read_manager(&mut f, &args);
 
             let mut mut_monitors_loaded = args.monitors_loaded;
             for (outpoint, drain_monitor) in mut_monitors_loaded.drain(..) {
@@ -81,25 +90,41 @@ impl Restorable<RestoreArgs, Arc<ChannelManager>> for ChannelManager {
                     panic!("Failed to load monitor that deserialized");
                 }
             }
-            //TODO: Rescan
+            // Caught up at startup, see chain_monitor::catch_up
             let manager = Arc::new(manager);
             let manager_as_listener: Arc<dyn ChainListener> = manager.clone();
             args.chain_watcher.register_listener(Arc::downgrade(&manager_as_listener));
-            manager
+            (manager, last_block_hash)
         } else {
             if !args.monitors_loaded.is_empty() {
                 panic!("Found some channel monitors but no channel state!");
             }
-            ChannelManager::new(
-                args.network,
-                args.fee_estimator,
-                args.monitor,
-                args.chain_watcher,
-                args.tx_broadcaster,
-                args.logger, 
-                args.keys_manager, 
-                config
-            ).unwrap()
+            let network = to_network(args.chain);
+            let manager = ChannelManager::new(
+                network,
+                args.fee_estimator.clone(),
+                args.monitor.clone(),
+                args.chain_watcher.clone(),
+                args.tx_broadcaster.clone(),
+                args.logger.clone(),
+                args.keys_manager.clone(),
+                user_config()
+            ).unwrap();
+            let network_genesis_hash = genesis_block(network).bitcoin_hash();
+            if args.chain.genesis_hash() == network_genesis_hash {
+                return (manager, Hash::default());
+            }
+
+            // Signet: open_channel, announcements and updates carry the genesis hash of the manager
+            let mut content = Vec::new();
+            manager.write(&mut content).expect("Failed to serialize channel manager");
+            signet::set_genesis_hash(&mut content, &network_genesis_hash, &args.chain.genesis_hash())
+                .unwrap_or_else(|e| panic!("Can't run on {}: {}", args.chain, e));
+            let (_, manager) = read_manager(&mut &content[..], &args);
+            let manager = Arc::new(manager);
+            let manager_as_listener: Arc<dyn ChainListener> = manager.clone();
+            args.chain_watcher.register_listener(Arc::downgrade(&manager_as_listener));
+            (manager, Hash::default())
         }
     }
 }
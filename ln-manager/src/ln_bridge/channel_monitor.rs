@@ -1,27 +1,85 @@
 use std::fs;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::io::Cursor;
 
 use bitcoin_hashes::hex::{ToHex, FromHex};
 use bitcoin_hashes::sha256d::Hash as Sha256dHash;
 
 use lightning::chain;
+use lightning::chain::chaininterface::{BroadcasterInterface, ChainWatchInterface, FeeEstimator};
 use lightning::ln::channelmonitor;
+use lightning::util::events::{Event, EventsProvider};
 use lightning::util::ser::ReadableArgs;
-use lightning::util::logger::Level;
+use lightning::util::logger::{Level, Logger};
 
 use super::log_printer::LogPrinter;
 
+type ChannelMonitors = channelmonitor::SimpleManyChannelMonitor<chain::transaction::OutPoint>;
+
+/// Stores the channel monitors rust-lightning hands us. Every channel gets its own
+/// `SimpleManyChannelMonitor`, which hands blocks to all of its monitors at once, so that each
+/// monitor can be caught up from the last block it saw at startup.
 pub struct ChannelMonitor {
-    pub monitor: Arc<channelmonitor::SimpleManyChannelMonitor<chain::transaction::OutPoint>>,
+    monitors: Mutex<HashMap<chain::transaction::OutPoint, Arc<ChannelMonitors>>>,
+    chain_watcher: Arc<dyn ChainWatchInterface>,
+    broadcaster: Arc<dyn BroadcasterInterface>,
+    logger: Arc<dyn Logger>,
+    fee_estimator: Arc<dyn FeeEstimator>,
     pub file_prefix: String,
+    pub backup_prefix: Option<String>,
 }
 
 impl ChannelMonitor {
+    pub fn new(
+        chain_watcher: Arc<dyn ChainWatchInterface>,
+        broadcaster: Arc<dyn BroadcasterInterface>,
+        logger: Arc<dyn Logger>,
+        fee_estimator: Arc<dyn FeeEstimator>,
+        file_prefix: String,
+        backup_prefix: Option<String>,
+    ) -> Self {
+        ChannelMonitor {
+            monitors: Mutex::new(HashMap::new()),
+            chain_watcher,
+            broadcaster,
+            logger,
+            fee_estimator,
+            file_prefix,
+            backup_prefix,
+        }
+    }
+
+    /// Where the monitor of that channel lives, it listens to the chain watcher on its own.
+    pub fn channel(&self, funding_txo: &chain::transaction::OutPoint) -> Arc<ChannelMonitors> {
+        self.monitors
+            .lock()
+            .unwrap()
+            .entry(*funding_txo)
+            .or_insert_with(|| {
+                channelmonitor::SimpleManyChannelMonitor::new(
+                    self.chain_watcher.clone(),
+                    self.broadcaster.clone(),
+                    self.logger.clone(),
+                    self.fee_estimator.clone(),
+                )
+            })
+            .clone()
+    }
+
+    fn channels(&self) -> Vec<Arc<ChannelMonitors>> {
+        self.monitors.lock().unwrap().values().cloned().collect()
+    }
+
+    /// The monitors stored under `file_prefix`, and the last block each of them saw.
     pub fn load_from_disk(
         file_prefix: &String,
-    ) -> Vec<(chain::transaction::OutPoint, channelmonitor::ChannelMonitor)> {
+    ) -> (
+        Vec<(chain::transaction::OutPoint, channelmonitor::ChannelMonitor)>,
+        Vec<(chain::transaction::OutPoint, Sha256dHash)>,
+    ) {
         let mut res = Vec::new();
+        let mut last_block_hashes = Vec::new();
         for file_option in fs::read_dir(file_prefix).unwrap() {
             let mut loaded = false;
             let file = file_option.unwrap();
@@ -37,17 +95,16 @@ impl ChannelMonitor {
                             .parse()
                         {
                             if let Ok(contents) = fs::read(&file.path()) {
-                                if let Ok((_last_block_hash, loaded_monitor)) =
+                                if let Ok((last_block_hash, loaded_monitor)) =
                                     <(Sha256dHash, channelmonitor::ChannelMonitor)>::read(
                                         &mut Cursor::new(&contents),
                                         Arc::new(LogPrinter { level: Level::Debug }),
                                     )
                                 {
-                                    // TODO: Rescan from last_block_hash
-                                    res.push((
-                                        chain::transaction::OutPoint { txid, index },
-                                        loaded_monitor,
-                                    ));
+                                    // Caught up at startup, see chain_monitor::catch_up
+                                    let funding_txo = chain::transaction::OutPoint { txid, index };
+                                    last_block_hashes.push((funding_txo, last_block_hash));
+                                    res.push((funding_txo, loaded_monitor));
                                     loaded = true;
                                 }
                             }
@@ -61,7 +118,31 @@ impl ChannelMonitor {
                 );
             }
         }
-        res
+        (res, last_block_hashes)
+    }
+
+    /// Copies every monitor on disk into the backup directory. Updates only mirror the monitor
+    /// they write, so without this a new backup directory misses the channels that stay quiet.
+    pub fn mirror_all(&self) {
+        let backup_prefix = match &self.backup_prefix {
+            Some(backup_prefix) => backup_prefix,
+            None => return,
+        };
+        let files = match fs::read_dir(&self.file_prefix) {
+            Ok(files) => files,
+            Err(e) => return error!("Could not list the channel monitors to back up: {}", e),
+        };
+        for file in files.filter_map(|file| file.ok()) {
+            let filename = file.file_name().to_string_lossy().into_owned();
+            // Leftovers of an interrupted update, the monitor itself is next to them
+            if filename.ends_with(".tmp") || filename.ends_with(".bk") {
+                continue;
+            }
+            let backup_filename = format!("{}/{}", backup_prefix, filename);
+            if let Err(e) = mirror(&file.path().to_string_lossy(), &backup_filename) {
+                error!("Could not back up channel monitor to {}: {}", backup_filename, e);
+            }
+        }
     }
 }
 #[cfg(any(target_os = "macos", target_os = "ios"))]
@@ -133,10 +214,31 @@ impl channelmonitor::ManyChannelMonitor for ChannelMonitor {
         if need_bk {
             try_fs!(fs::remove_file(&bk_filename));
         }
-        self.monitor.add_update_monitor(funding_txo, monitor)
+        if let Some(backup_prefix) = &self.backup_prefix {
+            // The update is safe on disk already, a failing mirror only makes the backup stale
+            let backup_filename = format!("{}/{}_{}", backup_prefix, funding_txo.txid.to_hex(), funding_txo.index);
+            if let Err(e) = mirror(&filename, &backup_filename) {
+                error!("Could not back up channel monitor to {}: {}", backup_filename, e);
+            }
+        }
+        self.channel(&funding_txo).add_update_monitor(funding_txo, monitor)
     }
 
     fn fetch_pending_htlc_updated(&self) -> Vec<channelmonitor::HTLCUpdate> {
-        self.monitor.fetch_pending_htlc_updated()
+        self.channels().iter().flat_map(|monitor| monitor.fetch_pending_htlc_updated()).collect()
     }
 }
+
+impl EventsProvider for ChannelMonitor {
+    fn get_and_clear_pending_events(&self) -> Vec<Event> {
+        self.channels().iter().flat_map(|monitor| monitor.get_and_clear_pending_events()).collect()
+    }
+}
+
+// Copy then rename, so the backup directory never holds a half written monitor
+fn mirror(filename: &str, backup_filename: &str) -> std::io::Result<()> {
+    let tmp_filename = backup_filename.to_string() + ".tmp";
+    fs::copy(filename, &tmp_filename)?;
+    fs::File::open(&tmp_filename)?.sync_all()?;
+    fs::rename(&tmp_filename, backup_filename)
+}
@@ -0,0 +1,330 @@
+use hyper;
+use serde_json;
+
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::util::hash::BitcoinHash;
+use bitcoin_hashes::hex::{FromHex, ToHex};
+use bitcoin_hashes::sha256d::Hash as Sha256dHash;
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, TryStreamExt};
+
+use super::chain_source::{ChainSource, HeaderInfo};
+use crate::utils::Chain;
+
+/// Follows the chain through an Esplora REST API (Blockstream's electrs, mempool.space) instead
+/// of bitcoind. Only plain http is supported, put a TLS proxy in front of a remote instance.
+pub struct EsploraClient {
+    base_url: String,
+    client: hyper::Client<hyper::client::HttpConnector, hyper::Body>,
+}
+
+impl EsploraClient {
+    pub fn new(base_url: &str) -> Self {
+        if !base_url.starts_with("http://") {
+            panic!("Bad Esplora URL provided, it must start with http://");
+        }
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: hyper::Client::new(),
+        }
+    }
+
+    // Status and body of a request to `path`
+    async fn request(&self, method: hyper::Method, path: &str, body: String) -> Result<(hyper::StatusCode, Vec<u8>), String> {
+        let uri = format!("{}{}", self.base_url, path);
+        let request = hyper::Request::builder()
+            .method(method)
+            .uri(&uri[..])
+            .body(hyper::Body::from(body))
+            .map_err(|e| e.to_string())?;
+        let res = self.client.request(request).await.map_err(|e| format!("{}: {}", uri, e))?;
+        let status = res.status();
+        let body = res.into_body().try_concat().await.map_err(|e| format!("{}: {}", uri, e))?;
+        Ok((status, body.to_vec()))
+    }
+
+    async fn get(&self, path: &str) -> Result<Vec<u8>, String> {
+        match self.request(hyper::Method::GET, path, String::new()).await? {
+            (status, body) if status == hyper::StatusCode::OK => Ok(body),
+            (status, body) => Err(format!("Esplora answered {} to {}: {}", status, path, String::from_utf8_lossy(&body))),
+        }
+    }
+
+    async fn get_text(&self, path: &str) -> Result<String, String> {
+        let body = self.get(path).await?;
+        String::from_utf8(body)
+            .map(|text| text.trim().to_string())
+            .map_err(|_| format!("Esplora sent invalid text for {}", path))
+    }
+
+    async fn get_json(&self, path: &str) -> Result<serde_json::Value, String> {
+        let body = self.get(path).await?;
+        serde_json::from_slice(&body).map_err(|_| format!("Esplora sent invalid JSON for {}", path))
+    }
+
+    /// Fails unless the Esplora instance follows the chain bitcoind is on.
+    pub async fn check_network(&self, chain: Chain) -> Result<(), String> {
+        let genesis = self.get_text("/block-height/0").await?;
+        if genesis != chain.genesis_hash().to_hex() {
+            return Err(format!("Esplora is not on {}, its genesis block is {}", chain, genesis));
+        }
+        Ok(())
+    }
+
+    async fn header(&self, hash: &str) -> Result<HeaderInfo, String> {
+        let v = self.get_json(&format!("/block/{}", hash)).await?;
+        let parse_hash = |field: &str| Sha256dHash::from_hex(v[field].as_str().unwrap_or_default()).ok();
+        let header = BlockHeader {
+            version: v["version"].as_u64().unwrap_or_default() as u32,
+            // Missing for the genesis block
+            prev_blockhash: match v.get("previousblockhash") {
+                Some(serde_json::Value::Null) | None => Sha256dHash::default(),
+                Some(_) => parse_hash("previousblockhash").ok_or_else(|| format!("Invalid header {}", hash))?,
+            },
+            merkle_root: parse_hash("merkle_root").ok_or_else(|| format!("Invalid header {}", hash))?,
+            time: v["timestamp"].as_u64().unwrap_or_default() as u32,
+            bits: v["bits"].as_u64().unwrap_or_default() as u32,
+            nonce: v["nonce"].as_u64().unwrap_or_default() as u32,
+        };
+        // Whatever Esplora sends has to hash to what we asked for
+        if header.bitcoin_hash().to_hex() != hash {
+            return Err(format!("Esplora sent a header not matching {}", hash));
+        }
+        let height = v["height"].as_u64().ok_or_else(|| format!("Invalid header {}", hash))?;
+        Ok(HeaderInfo { hash: hash.to_string(), height: height as u32, header })
+    }
+}
+
+// The estimate for the largest target not above `conf_target`, or the shortest one there is
+fn pick_estimate(estimates: &serde_json::Value, conf_target: u16) -> Option<f64> {
+    let mut estimates: Vec<(u16, f64)> = estimates
+        .as_object()?
+        .iter()
+        .filter_map(|(target, feerate)| Some((target.parse().ok()?, feerate.as_f64()?)))
+        .collect();
+    estimates.sort_by_key(|(target, _)| *target);
+    estimates
+        .iter()
+        .rev()
+        .find(|(target, _)| *target <= conf_target)
+        .or_else(|| estimates.first())
+        .map(|(_, feerate)| *feerate)
+}
+
+impl ChainSource for EsploraClient {
+    fn best_block(&self) -> BoxFuture<'_, Result<(String, u32), String>> {
+        async move {
+            let hash = self.get_text("/blocks/tip/hash").await?;
+            let header = self.header(&hash).await?;
+            Ok((hash, header.height))
+        }.boxed()
+    }
+
+    fn get_header<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HeaderInfo, String>> {
+        self.header(hash).boxed()
+    }
+
+    fn get_block<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Block, String>> {
+        async move {
+            let raw = self.get(&format!("/block/{}/raw", hash)).await?;
+            let block: Block = encode::deserialize(&raw).map_err(|_| format!("Esplora sent an invalid block {}", hash))?;
+            if block.bitcoin_hash().to_hex() != hash {
+                return Err(format!("Esplora sent a block not matching {}", hash));
+            }
+            Ok(block)
+        }.boxed()
+    }
+
+    fn estimate_fee(&self, conf_target: u16) -> BoxFuture<'_, Result<Option<f64>, String>> {
+        async move {
+            // sat/vB by confirmation target, empty on regtest
+            let estimates = self.get_json("/fee-estimates").await?;
+            Ok(pick_estimate(&estimates, conf_target))
+        }.boxed()
+    }
+
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> BoxFuture<'a, Result<String, String>> {
+        async move {
+            // Refusals come back with bitcoind's reason as the body
+            match self.request(hyper::Method::POST, "/tx", encode::serialize_hex(tx)).await? {
+                (status, body) if status == hyper::StatusCode::OK => Ok(String::from_utf8_lossy(&body).trim().to_string()),
+                (_, body) => Err(String::from_utf8_lossy(&body).trim().to_string()),
+            }
+        }.boxed()
+    }
+
+    fn has_transaction<'a>(&'a self, txid: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+        async move {
+            let path = format!("/tx/{}/status", txid);
+            match self.request(hyper::Method::GET, &path, String::new()).await? {
+                (status, _) if status == hyper::StatusCode::OK => Ok(true),
+                (status, _) if status == hyper::StatusCode::NOT_FOUND => Ok(false),
+                (status, body) => Err(format!("Esplora answered {} to {}: {}", status, path, String::from_utf8_lossy(&body))),
+            }
+        }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::constants::genesis_block;
+    use bitcoin::network::constants::Network;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio::runtime::current_thread::Runtime;
+
+    // Answers "METHOD /path" with a canned status and body, like an Esplora instance would
+    fn serve(routes: Vec<(&str, u16, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let routes: HashMap<String, (u16, Vec<u8>)> = routes
+            .into_iter()
+            .map(|(route, status, body)| (route.to_string(), (status, body)))
+            .collect();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                // Headers, then as much body as they announce
+                let mut request = vec![];
+                let mut buf = [0; 4096];
+                let head_end = loop {
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    request.extend_from_slice(&buf[..n]);
+                    if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    if n == 0 {
+                        break request.len();
+                    }
+                };
+                let head = String::from_utf8_lossy(&request[..head_end]).to_lowercase();
+                let body_len: usize = head
+                    .lines()
+                    .find(|line| line.starts_with("content-length:"))
+                    .and_then(|line| line["content-length:".len()..].trim().parse().ok())
+                    .unwrap_or(0);
+                while request.len() < head_end + body_len {
+                    let n = stream.read(&mut buf).unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let route = head.split(' ').take(2).collect::<Vec<&str>>().join(" ").to_uppercase();
+                let route = routes.keys().find(|r| r.to_uppercase() == route).cloned().unwrap_or_default();
+                let (status, body) = routes.get(&route).cloned().unwrap_or((404, b"not found".to_vec()));
+                let _ = write!(stream, "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+                let _ = stream.write_all(&body);
+            }
+        });
+        url
+    }
+
+    fn block_json(block: &Block, height: u32) -> Vec<u8> {
+        let header = &block.header;
+        json!({
+            "id": block.bitcoin_hash().to_hex(),
+            "height": height,
+            "version": header.version,
+            "timestamp": header.time,
+            "bits": header.bits,
+            "nonce": header.nonce,
+            "merkle_root": header.merkle_root.to_hex(),
+            "previousblockhash": header.prev_blockhash.to_hex(),
+        }).to_string().into_bytes()
+    }
+
+    fn next_block(prev: &Block) -> Block {
+        let mut block = prev.clone();
+        block.header.prev_blockhash = prev.bitcoin_hash();
+        block.header.time += 600;
+        block
+    }
+
+    #[test]
+    fn follows_the_chain() {
+        let genesis = genesis_block(Network::Regtest);
+        let block = next_block(&genesis);
+        let hash = block.bitcoin_hash().to_hex();
+        let url = serve(vec![
+            ("GET /blocks/tip/hash", 200, hash.clone().into_bytes()),
+            (&format!("GET /block/{}", hash)[..], 200, block_json(&block, 1)),
+            (&format!("GET /block/{}/raw", hash)[..], 200, encode::serialize(&block)),
+            ("GET /block-height/0", 200, genesis.bitcoin_hash().to_hex().into_bytes()),
+        ]);
+        let esplora = EsploraClient::new(&url);
+        let mut rt = Runtime::new().unwrap();
+
+        assert_eq!(rt.block_on(esplora.best_block()), Ok((hash.clone(), 1)));
+        let header = rt.block_on(esplora.get_header(&hash)).unwrap();
+        assert_eq!(header.header, block.header);
+        assert_eq!(header.prev_hash(), genesis.bitcoin_hash().to_hex());
+        assert_eq!(rt.block_on(esplora.get_block(&hash)).unwrap(), block);
+        assert!(rt.block_on(esplora.get_block(&genesis.bitcoin_hash().to_hex())).is_err());
+        assert!(rt.block_on(esplora.check_network(Chain::Regtest)).is_ok());
+        assert!(rt.block_on(esplora.check_network(Chain::Testnet)).is_err());
+        assert!(rt.block_on(esplora.check_network(Chain::Signet)).is_err());
+    }
+
+    #[test]
+    fn rejects_mismatching_header() {
+        let genesis = genesis_block(Network::Regtest);
+        let block = next_block(&genesis);
+        let hash = genesis.bitcoin_hash().to_hex();
+        let url = serve(vec![(&format!("GET /block/{}", hash)[..], 200, block_json(&block, 0))]);
+        let mut rt = Runtime::new().unwrap();
+        assert!(rt.block_on(EsploraClient::new(&url).get_header(&hash)).is_err());
+    }
+
+    #[test]
+    fn fee_estimates() {
+        let estimates = json!({ "1": 20.0, "6": 10.5, "144": 1.0 });
+        assert_eq!(pick_estimate(&estimates, 1), Some(20.0));
+        assert_eq!(pick_estimate(&estimates, 18), Some(10.5));
+        assert_eq!(pick_estimate(&estimates, 1008), Some(1.0));
+        assert_eq!(pick_estimate(&json!({ "6": 10.5 }), 2), Some(10.5));
+        assert_eq!(pick_estimate(&json!({}), 6), None);
+
+        let url = serve(vec![("GET /fee-estimates", 200, estimates.to_string().into_bytes())]);
+        let mut rt = Runtime::new().unwrap();
+        assert_eq!(rt.block_on(EsploraClient::new(&url).estimate_fee(18)), Ok(Some(10.5)));
+    }
+
+    #[test]
+    fn broadcast() {
+        let tx = genesis_block(Network::Regtest).txdata[0].clone();
+        let txid = tx.txid().to_string();
+        let url = serve(vec![("POST /tx", 200, txid.clone().into_bytes())]);
+        let mut rt = Runtime::new().unwrap();
+        assert_eq!(rt.block_on(EsploraClient::new(&url).broadcast(&tx)), Ok(txid));
+
+        let refusal = "sendrawtransaction RPC error: {\"code\":-27,\"message\":\"Transaction already in block chain\"}";
+        let url = serve(vec![("POST /tx", 400, refusal.as_bytes().to_vec())]);
+        assert_eq!(rt.block_on(EsploraClient::new(&url).broadcast(&tx)), Err(refusal.to_string()));
+    }
+
+    #[test]
+    fn has_transaction() {
+        let txid = genesis_block(Network::Regtest).txdata[0].txid().to_string();
+        let url = serve(vec![
+            (&format!("GET /tx/{}/status", txid)[..], 200, br#"{"confirmed":true}"#.to_vec()),
+            ("GET /tx/00/status", 400, b"Invalid hex string".to_vec()),
+        ]);
+        let esplora = EsploraClient::new(&url);
+        let mut rt = Runtime::new().unwrap();
+        assert_eq!(rt.block_on(esplora.has_transaction(&txid)), Ok(true));
+        // Unknown routes are 404s
+        assert_eq!(rt.block_on(esplora.has_transaction(&"11".repeat(32))), Ok(false));
+        assert!(rt.block_on(esplora.has_transaction("00")).is_err());
+    }
+}
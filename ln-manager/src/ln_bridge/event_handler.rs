@@ -1,5 +1,4 @@
-use std::collections::HashMap;
-use std::fs;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::time::{Instant};
 
@@ -9,65 +8,211 @@ use futures::{StreamExt, FutureExt};
 
 use bitcoin::blockdata;
 use bitcoin::consensus::encode;
-use bitcoin::network::constants;
 
 use lightning::chain;
 use lightning::chain::keysinterface::SpendableOutputDescriptor;
 use lightning::ln::channelmanager;
 use lightning::ln::channelmanager::{PaymentHash, PaymentPreimage};
-use lightning::ln::channelmonitor;
 use lightning::ln::peer_handler;
 use lightning::util::events::{Event, EventsProvider};
 use lightning::util::ser::Writeable; 
 use super::connection::SocketDescriptor;
 
-use super::utils::{hex_to_vec, hex_str};
+use super::utils::{hex_to_vec, hex_str, write_atomically};
+use super::chain_source::ChainSource;
+use super::channel_monitor::ChannelMonitor;
 use super::rpc_client::RPCClient;
+use super::sweeper::Sweeper;
+use super::funding::{Fundings, PsbtFunding};
 use crate::executor::Larva;
-use crate::utils::{compact_btc_to_bech32};
-use log::{info};
+use crate::utils::{compact_btc_to_bech32, Chain};
+use log::{info, warn, error};
+use protocol::{ChannelCreateRequest, FeeTarget, FundingMode, NodeEvent};
+use serde_json::json;
 
+// Events a subscriber may fall behind before it is dropped
+const EVENT_BUFFER: usize = 256;
+
+/// Clients listening for `NodeEvent`s, each gets every event `handle_events` processes after it
+/// subscribed. A subscriber that falls `EVENT_BUFFER` events behind is dropped, ending its
+/// stream, and ones whose receiver went away are forgotten.
+#[derive(Default)]
+pub struct EventSubscribers {
+    senders: Mutex<Vec<mpsc::Sender<NodeEvent>>>,
+}
+
+impl EventSubscribers {
+    pub fn subscribe(&self) -> mpsc::Receiver<NodeEvent> {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|s| !s.is_closed());
+        senders.push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, event: NodeEvent) {
+        let mut senders = self.senders.lock().unwrap();
+        let mut alive = Vec::with_capacity(senders.len());
+        for mut sender in senders.drain(..) {
+            match sender.try_send(event.clone()) {
+                Ok(()) => alive.push(sender),
+                Err(ref e) if e.is_full() => warn!("Dropping an event subscriber {} events behind", EVENT_BUFFER),
+                Err(_) => {}
+            }
+        }
+        *senders = alive;
+    }
+}
+
+// Errors of the call come back as null, see `RPCClient::make_rpc_call`
+async fn fund_call(rpc_client: &RPCClient, wallet: Option<&str>, method: &str, params: &[&str]) -> Result<serde_json::Value, String> {
+    match rpc_client.make_wallet_rpc_call(wallet, method, params, false).await {
+        Ok(serde_json::Value::Null) | Err(_) => Err(format!("bitcoind refused {}", method)),
+        Ok(v) => Ok(v),
+    }
+}
+
+// Funds and signs the funding transaction with the bitcoind wallet, following the coin control
+// of the request that opened the channel if we have it
 async fn handle_fund_tx<T: Larva>(
     mut self_sender: mpsc::Sender<()>,
     &temporary_channel_id: &[u8; 32],
     this: Arc<EventHandler<T>>,
-    value: &[&str; 2]
-) {
-    let tx_hex = this.rpc_client.make_rpc_call(
-        "createrawtransaction",
-        value,
-        false
-    ).await.unwrap();
-
-    let funded_tx_args = &[&format!("\"{}\"", tx_hex.as_str().unwrap())[..]];
-    let funded_tx = this.rpc_client.make_rpc_call(
-        "fundrawtransaction",
-        funded_tx_args,
-        false
-    ).await.unwrap();
-    
+    addr: &str,
+    channel_value_satoshis: u64,
+    req: Option<ChannelCreateRequest>,
+) -> Result<(), String> {
+    let (utxos, fee, wallet) = match req {
+        Some(req) => (req.utxos, req.fee, req.wallet),
+        None => (vec![], None, None),
+    };
+    let wallet = wallet.as_ref().map(|w| w.as_str());
+
+    let inputs: Vec<serde_json::Value> = utxos
+        .iter()
+        .map(|utxo| {
+            let (txid, vout) = utxo.split_at(64);
+            json!({ "txid": txid, "vout": vout[1..].parse::<u32>().unwrap_or_default() })
+        })
+        .collect();
+    let inputs = serde_json::Value::Array(inputs).to_string();
+    let outputs = format!("{{\"{}\": {}}}", addr, channel_value_satoshis as f64 / 1_000_000_00.0);
+    let tx_hex = fund_call(&this.rpc_client, wallet, "createrawtransaction", &[&inputs[..], &outputs[..]]).await?;
+
+    // bitcoind wants BTC/kvB
+    let options = match fee {
+        Some(FeeTarget::SatPerVbyte(rate)) => json!({ "feeRate": rate as f64 / 100_000.0 }),
+        Some(FeeTarget::Blocks(blocks)) => json!({ "conf_target": blocks }),
+        None => json!({}),
+    }
+    .to_string();
+    let tx_hex = format!("\"{}\"", tx_hex.as_str().unwrap_or_default());
+    let funded_tx = fund_call(&this.rpc_client, wallet, "fundrawtransaction", &[&tx_hex[..], &options[..]]).await?;
+
     info!("funded_tx: {}", &funded_tx);
-    let changepos = funded_tx["changepos"].as_i64().unwrap();
+    let changepos = funded_tx["changepos"].as_i64().unwrap_or(-2);
     info!("change pos: {}", &changepos);
-    assert!(changepos == 0 || changepos == 1);
+    if changepos < -1 || changepos > 1 {
+        return Err(format!("Unexpected change position {}", changepos));
+    }
+
+    // fundrawtransaction adds inputs when the selected ones don't cover the channel and fee
+    let funded_hex = funded_tx["hex"].as_str().unwrap_or_default();
+    if !utxos.is_empty() {
+        let funded: blockdata::transaction::Transaction = hex_to_vec(funded_hex)
+            .and_then(|tx| encode::deserialize(&tx).ok())
+            .ok_or_else(|| "bitcoind returned an invalid transaction".to_string())?;
+        let foreign = funded
+            .input
+            .iter()
+            .map(|input| format!("{}:{}", input.previous_output.txid, input.previous_output.vout))
+            .find(|outpoint| !utxos.contains(outpoint));
+        if let Some(outpoint) = foreign {
+            return Err(format!("The selected utxos don't cover the channel and fee, bitcoind added {}", outpoint));
+        }
+    }
 
-    let signed_tx_args = &[&format!("\"{}\"", funded_tx["hex"].as_str().unwrap())[..]];
-    let signed_tx = this.rpc_client.make_rpc_call(
-        "signrawtransactionwithwallet",
-        signed_tx_args,
-        false
-    ).await.unwrap();
+    let funded_hex = format!("\"{}\"", funded_hex);
+    let signed_tx = fund_call(&this.rpc_client, wallet, "signrawtransactionwithwallet", &[&funded_hex[..]]).await?;
 
-    assert_eq!(signed_tx["complete"].as_bool().unwrap(), true);
-    let tx: blockdata::transaction::Transaction = encode::deserialize(&hex_to_vec(&signed_tx["hex"].as_str().unwrap()).unwrap()).unwrap();
+    if signed_tx["complete"].as_bool() != Some(true) {
+        return Err("bitcoind could not sign every input".to_string());
+    }
+    let tx: blockdata::transaction::Transaction = signed_tx["hex"]
+        .as_str()
+        .and_then(hex_to_vec)
+        .and_then(|tx| encode::deserialize(&tx).ok())
+        .ok_or_else(|| "bitcoind returned an invalid transaction".to_string())?;
     let outpoint = chain::transaction::OutPoint {
         txid: tx.txid(),
         index: if changepos == 0 { 1 } else { 0 },
     };
-    this.channel_manager.funding_transaction_generated(&temporary_channel_id, outpoint);
-    this.txn_to_broadcast.lock().unwrap().insert(outpoint, tx);
+    this.fundings.funding_generated(&temporary_channel_id, outpoint, tx, || {
+        this.channel_manager.funding_transaction_generated(&temporary_channel_id, outpoint)
+    });
     let _ = self_sender.try_send(());
     info!("Generated funding tx!");
+    Ok(())
+}
+
+// The funding transaction is gone (a restart before it was persisted): fine if it is out
+// already, e.g. a PSBT the client broadcast itself, otherwise nothing can fund the channel
+async fn recover_funding_tx<T: Larva>(this: &Arc<EventHandler<T>>, funding_txo: chain::transaction::OutPoint) {
+    let txid = funding_txo.txid.to_string();
+    let known = match this.chain_source.has_transaction(&txid).await {
+        Ok(known) => known,
+        // A force close can't be undone, and the transaction may well be out
+        Err(e) => {
+            error!("Could not look up funding tx {}, keeping channel {}: {}", txid, hex_str(&funding_txo.to_channel_id()), e);
+            return;
+        }
+    };
+    if known {
+        info!("Funding tx {} is already known to the chain source", txid);
+        this.subscribers.publish(NodeEvent::FundingBroadcast { txid });
+        return;
+    }
+    error!("Lost funding tx {}, closing channel {}", txid, hex_str(&funding_txo.to_channel_id()));
+    this.subscribers.publish(NodeEvent::BroadcastFailed {
+        txid,
+        error: "funding transaction lost, the channel is closed".to_string(),
+    });
+    this.channel_manager.force_close_channel(&funding_txo.to_channel_id());
+}
+
+// Hands an unsigned PSBT paying the funding output to the clients, the channel waits until
+// one comes back signed through `ln_cmd::channel::fund_psbt`
+async fn handle_psbt_funding<T: Larva>(
+    temporary_channel_id: [u8; 32],
+    this: Arc<EventHandler<T>>,
+    addr: String,
+    channel_value_satoshis: u64,
+    output_script: blockdata::script::Script,
+) {
+    let outputs = format!("[{{\"{}\": {}}}]", addr, channel_value_satoshis as f64 / 1_000_000_00.0);
+    let psbt = match this.rpc_client.make_rpc_call("createpsbt", &["[]", &outputs], false).await {
+        Ok(serde_json::Value::String(psbt)) => psbt,
+        _ => {
+            // Without a PSBT the client has nothing to sign, nothing else would ever fund it
+            error!("Could not create the funding PSBT of channel {}", hex_str(&temporary_channel_id));
+            this.channel_manager.force_close_channel(&temporary_channel_id);
+            return;
+        }
+    };
+    let funding = PsbtFunding {
+        output_script,
+        value_sat: channel_value_satoshis,
+        address: addr.clone(),
+        psbt: psbt.clone(),
+    };
+    this.fundings.await_psbt(temporary_channel_id, funding);
+    info!("Channel {} waits for a signed funding PSBT", hex_str(&temporary_channel_id));
+    this.subscribers.publish(NodeEvent::FundingPsbt {
+        temporary_channel_id: hex_str(&temporary_channel_id),
+        address: addr,
+        value_sat: channel_value_satoshis,
+        psbt,
+    });
 }
 
 async fn handle_events<T: Larva>(
@@ -80,23 +225,47 @@ async fn handle_events<T: Larva>(
     events.append(&mut this.monitor.get_and_clear_pending_events());
     for event in events {
         match event {
-            Event::FundingGenerationReady { temporary_channel_id, channel_value_satoshis, output_script, .. } => {
-                let bech_32_network = compact_btc_to_bech32(this.network);
+            Event::FundingGenerationReady { temporary_channel_id, channel_value_satoshis, output_script, user_channel_id } => {
+                let bech_32_network = compact_btc_to_bech32(this.chain);
                 let addr = bitcoin_bech32::WitnessProgram::from_scriptpubkey(&output_script[..], bech_32_network)
                     .expect("LN funding tx should always be to a SegWit output").to_address();
-                let handle_fund_tx_args = &["[]", &format!("{{\"{}\": {}}}", addr, channel_value_satoshis as f64 / 1_000_000_00.0)];
-                let _ = handle_fund_tx(
-                    self_sender.clone(),
-                    &temporary_channel_id,
-                    this.clone(),
-                    handle_fund_tx_args 
-                ).await;
+                let req = this.fundings.take_request(user_channel_id);
+                match req.as_ref().map(|r| r.funding).unwrap_or(FundingMode::Wallet) {
+                    FundingMode::Wallet => {
+                        let funded = handle_fund_tx(
+                            self_sender.clone(),
+                            &temporary_channel_id,
+                            this.clone(),
+                            &addr,
+                            channel_value_satoshis,
+                            req,
+                        ).await;
+                        if let Err(e) = funded {
+                            // Nothing else would ever fund it
+                            error!("Could not fund channel {}: {}", hex_str(&temporary_channel_id), e);
+                            this.channel_manager.force_close_channel(&temporary_channel_id);
+                        }
+                    }
+                    FundingMode::Psbt => {
+                        handle_psbt_funding(
+                            temporary_channel_id,
+                            this.clone(),
+                            addr,
+                            channel_value_satoshis,
+                            output_script,
+                        ).await;
+                    }
+                }
             },
             Event::PaymentReceived { payment_hash, amt } => {
                 let images = this.payment_preimages.lock().unwrap();
                 if let Some(payment_preimage) = images.get(&payment_hash) {
                     if this.channel_manager.claim_funds(payment_preimage.clone()) {
                         info!("Payment received: {} msat id {}", amt, hex_str(&payment_hash.0));
+                        this.subscribers.publish(NodeEvent::PaymentReceived {
+                            payment_hash: hex_str(&payment_hash.0),
+                            amount_msat: amt,
+                        });
                     } else {
                         info!("Failed to claim money we were told we had?");
                     }
@@ -117,82 +286,112 @@ async fn handle_events<T: Larva>(
                     future::ok(())
                 })));
             },
-            Event::FundingBroadcastSafe { funding_txo, .. } => {
-                let mut txn = this.txn_to_broadcast.lock().unwrap();
-                let tx = txn.remove(&funding_txo).unwrap();
-                this.broadcaster.broadcast_transaction(&tx);
-                info!("Broadcast funding tx {}!", tx.txid());
+            Event::FundingBroadcastSafe { funding_txo, .. } => match this.fundings.take_tx(&funding_txo) {
+                Some(tx) => {
+                    this.broadcaster.broadcast_transaction(&tx);
+                    info!("Broadcast funding tx {}!", tx.txid());
+                    this.subscribers.publish(NodeEvent::FundingBroadcast { txid: tx.txid().to_string() });
+                }
+                None => recover_funding_tx(&this, funding_txo).await,
             },
             Event::PaymentSent { payment_preimage } => {
                 info!("Payment Sent, proof: {}", hex_str(&payment_preimage.0));
+                this.subscribers.publish(NodeEvent::PaymentSent { payment_preimage: hex_str(&payment_preimage.0) });
             },
             Event::PaymentFailed { payment_hash, rejected_by_dest } => {
                 info!("{} failed id {}!", if rejected_by_dest { "Send" } else { "Route" }, hex_str(&payment_hash.0));
+                this.subscribers.publish(NodeEvent::PaymentFailed {
+                    payment_hash: hex_str(&payment_hash.0),
+                    rejected_by_dest,
+                });
             },
             Event::SpendableOutputs { mut outputs } => {
                 for output in outputs.drain(..) {
-                    match output {
-                        SpendableOutputDescriptor:: StaticOutput { outpoint, .. } => {
+                    let (outpoint, descriptor) = match &output {
+                        SpendableOutputDescriptor::StaticOutput { outpoint, .. } => {
                             info!("Got on-chain output Bitcoin Core should know how to claim at {}:{}", hex_str(&outpoint.txid[..]), outpoint.vout);
+                            (outpoint, "static")
                         },
-                        SpendableOutputDescriptor::DynamicOutputP2WSH { .. } => {
-                            info!("Got on-chain output we should claim...");
-                            //TODO: Send back to Bitcoin Core!
+                        SpendableOutputDescriptor::DynamicOutputP2WSH { outpoint, .. } => {
+                            info!("Got on-chain output we should claim, sweeping {}:{}", outpoint.txid, outpoint.vout);
+                            (outpoint, "p2wsh")
                         },
-                        SpendableOutputDescriptor::DynamicOutputP2WPKH { .. } => {
-                            info!("Got on-chain output we should claim...");
-                            //TODO: Send back to Bitcoin Core!
+                        SpendableOutputDescriptor::DynamicOutputP2WPKH { outpoint, .. } => {
+                            info!("Got on-chain output we should claim, sweeping {}:{}", outpoint.txid, outpoint.vout);
+                            (outpoint, "p2wpkh")
                         },
-                    }
+                    };
+                    this.subscribers.publish(NodeEvent::SpendableOutput {
+                        txid: outpoint.txid.to_string(),
+                        vout: outpoint.vout,
+                        descriptor: descriptor.to_string(),
+                    });
+                    this.sweeper.track(&output);
                 }
+                this.sweeper.sweep().await;
             }
         }
     }
+    let open_channels: HashSet<[u8; 32]> = this.channel_manager.list_channels().iter().map(|c| c.channel_id).collect();
+    this.fundings.forget_closed(&open_channels);
 
+    // Kept private like the sweeps, the channel state holds per channel secrets
     let filename = format!("{}/manager_data", this.file_prefix);
-    let tmp_filename = filename.clone() + ".tmp";
-
-    {
-        let mut f = fs::File::create(&tmp_filename).unwrap();
-        this.channel_manager.write(&mut f).unwrap();
+    let mut content = Vec::new();
+    let res = this
+        .channel_manager
+        .write(&mut content)
+        .map_err(|e| e.to_string())
+        .and_then(|_| write_atomically(&filename, &content, 0o600));
+    if let Err(e) = res {
+        error!("Could not persist the channel manager to {}: {}", filename, e);
     }
-    fs::rename(&tmp_filename, &filename).unwrap();
 }
 
 pub struct EventHandler<T: Larva> {
-    network: constants::Network,
+    chain: Chain,
     file_prefix: String,
     rpc_client: Arc<RPCClient>,
+    chain_source: Arc<dyn ChainSource>,
     peer_manager: Arc<peer_handler::PeerManager<SocketDescriptor<T>>>,
     channel_manager: Arc<channelmanager::ChannelManager>,
-    monitor: Arc<channelmonitor::SimpleManyChannelMonitor<chain::transaction::OutPoint>>,
+    monitor: Arc<ChannelMonitor>,
     broadcaster: Arc<dyn chain::chaininterface::BroadcasterInterface>,
-    txn_to_broadcast: Mutex<HashMap<chain::transaction::OutPoint, blockdata::transaction::Transaction>>,
+    fundings: Arc<Fundings>,
     payment_preimages: Arc<Mutex<HashMap<PaymentHash, PaymentPreimage>>>,
+    subscribers: Arc<EventSubscribers>,
+    sweeper: Arc<Sweeper>,
 }
 
 impl<T: Larva> EventHandler<T> {
     pub fn setup(
-        network: constants::Network,
+        chain: Chain,
         file_prefix: String,
         rpc_client: Arc<RPCClient>,
+        chain_source: Arc<dyn ChainSource>,
         peer_manager: Arc<peer_handler::PeerManager<SocketDescriptor<T>>>,
-        monitor: Arc<channelmonitor::SimpleManyChannelMonitor<chain::transaction::OutPoint>>,
+        monitor: Arc<ChannelMonitor>,
         channel_manager: Arc<channelmanager::ChannelManager>,
         broadcaster: Arc<dyn chain::chaininterface::BroadcasterInterface>,
         payment_preimages: Arc<Mutex<HashMap<PaymentHash, PaymentPreimage>>>,
+        fundings: Arc<Fundings>,
+        subscribers: Arc<EventSubscribers>,
+        sweeper: Arc<Sweeper>,
         larva: impl Larva,
     ) -> mpsc::Sender<()> {
         let this = Arc::new(Self {
-            network,
+            chain,
             file_prefix,
             rpc_client,
+            chain_source,
             peer_manager,
             channel_manager,
             monitor,
             broadcaster,
-            txn_to_broadcast: Mutex::new(HashMap::new()),
+            fundings,
             payment_preimages,
+            subscribers,
+            sweeper,
         });
         let (sender, receiver) = mpsc::channel(2);
         let self_sender = sender.clone();
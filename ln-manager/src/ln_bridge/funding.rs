@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use lightning::chain::transaction::OutPoint;
+use protocol::ChannelCreateRequest;
+use rand::{thread_rng, Rng};
+
+use super::utils::{hex_str, hex_to_vec, write_atomically};
+
+/// A channel waiting for its funding transaction from outside the node.
+#[derive(Clone)]
+pub struct PsbtFunding {
+    pub output_script: Script,
+    pub value_sat: u64,
+    pub address: String,
+    /// The unsigned PSBT paying the funding output, as handed to the client.
+    pub psbt: String,
+}
+
+// A signed funding transaction waiting for `Event::FundingBroadcastSafe`
+#[derive(Serialize, Deserialize)]
+struct PendingFunding {
+    // consensus encoded
+    tx: String,
+    index: u16,
+}
+
+/// Book keeping of channel funding, from `ChannelManager::create_channel` to
+/// `Event::FundingBroadcastSafe`. Requests are keyed by the user id rust-lightning hands back in
+/// `Event::FundingGenerationReady`, channels by their temporary id and funding transactions by
+/// the outpoint of the funding output. Funding transactions are kept in `<lndata>/funding_txn`
+/// as rust-lightning only asks for their broadcast once the peer signed, maybe after a restart.
+pub struct Fundings {
+    file: String,
+    requests: Mutex<HashMap<u64, ChannelCreateRequest>>,
+    awaiting_psbt: Mutex<HashMap<[u8; 32], PsbtFunding>>,
+    txn_to_broadcast: Mutex<HashMap<OutPoint, Transaction>>,
+}
+
+impl Fundings {
+    pub fn load(data_path: &str) -> Self {
+        let file = format!("{}/funding_txn", data_path);
+        let pending: Vec<PendingFunding> = match fs::read(&file) {
+            // Starting without them would leave the channels unfunded
+            Ok(content) => serde_json::from_slice(&content)
+                .unwrap_or_else(|e| panic!("Could not read funding transactions from {}: {}", file, e)),
+            Err(_) => vec![],
+        };
+        let txn_to_broadcast: HashMap<OutPoint, Transaction> = pending
+            .into_iter()
+            .filter_map(|p| {
+                let tx: Transaction = hex_to_vec(&p.tx).and_then(|tx| encode::deserialize(&tx).ok())?;
+                Some((OutPoint { txid: tx.txid(), index: p.index }, tx))
+            })
+            .collect();
+        if !txn_to_broadcast.is_empty() {
+            info!("Loaded {} funding transactions waiting for their peer", txn_to_broadcast.len());
+        }
+        Fundings {
+            file,
+            requests: Mutex::new(HashMap::new()),
+            awaiting_psbt: Mutex::new(HashMap::new()),
+            txn_to_broadcast: Mutex::new(txn_to_broadcast),
+        }
+    }
+
+    fn persist(&self, txn: &HashMap<OutPoint, Transaction>) {
+        let pending: Vec<PendingFunding> = txn
+            .iter()
+            .map(|(outpoint, tx)| PendingFunding { tx: encode::serialize_hex(tx), index: outpoint.index })
+            .collect();
+        let res = serde_json::to_vec(&pending)
+            .map_err(|e| e.to_string())
+            .and_then(|content| write_atomically(&self.file, &content, 0o644));
+        if let Err(e) = res {
+            error!("Could not persist funding transactions to {}: {}", self.file, e);
+        }
+    }
+
+    /// Remembers how a channel we are about to open gets funded, returns the user id to pass
+    /// to `create_channel`.
+    pub fn register(&self, req: &ChannelCreateRequest) -> u64 {
+        let mut requests = self.requests.lock().unwrap();
+        let mut user_id: u64 = thread_rng().gen();
+        while user_id == 0 || requests.contains_key(&user_id) {
+            user_id = thread_rng().gen();
+        }
+        requests.insert(user_id, req.clone());
+        user_id
+    }
+
+    // None for channels opened before a restart or by the peer, they are funded by the wallet
+    pub fn take_request(&self, user_id: u64) -> Option<ChannelCreateRequest> {
+        self.requests.lock().unwrap().remove(&user_id)
+    }
+
+    pub fn await_psbt(&self, temporary_channel_id: [u8; 32], funding: PsbtFunding) {
+        self.awaiting_psbt.lock().unwrap().insert(temporary_channel_id, funding);
+    }
+
+    /// The channels waiting for a signed PSBT, for clients that missed `NodeEvent::FundingPsbt`.
+    pub fn pending_psbts(&self) -> Vec<([u8; 32], PsbtFunding)> {
+        self.awaiting_psbt.lock().unwrap().iter().map(|(id, funding)| (*id, funding.clone())).collect()
+    }
+
+    /// Only one caller gets the funding, put it back with `await_psbt` if its PSBT doesn't do.
+    pub fn take_psbt_funding(&self, temporary_channel_id: &[u8; 32]) -> Option<PsbtFunding> {
+        self.awaiting_psbt.lock().unwrap().remove(temporary_channel_id)
+    }
+
+    /// Stores the transaction until it is safe to broadcast, the channel stops waiting for a PSBT.
+    /// `generated` hands the outpoint to the channel (`ChannelManager::funding_transaction_generated`)
+    /// once the transaction is on disk, before the peer can sign for it, and before
+    /// `forget_closed` could take the channel for a closed one.
+    pub fn funding_generated(&self, temporary_channel_id: &[u8; 32], outpoint: OutPoint, tx: Transaction, generated: impl FnOnce()) {
+        self.awaiting_psbt.lock().unwrap().remove(temporary_channel_id);
+        let mut txn = self.txn_to_broadcast.lock().unwrap();
+        txn.insert(outpoint, tx);
+        self.persist(&txn);
+        generated();
+    }
+
+    /// Forgets what funds channels that are not in `channel_ids` anymore: they closed before
+    /// `Event::FundingBroadcastSafe`, the peer went away or the funding failed.
+    pub fn forget_closed(&self, channel_ids: &HashSet<[u8; 32]>) {
+        self.awaiting_psbt.lock().unwrap().retain(|id, _| channel_ids.contains(id));
+        let mut txn = self.txn_to_broadcast.lock().unwrap();
+        let count = txn.len();
+        txn.retain(|outpoint, tx| {
+            let open = channel_ids.contains(&outpoint.to_channel_id());
+            if !open {
+                info!("Forgetting funding tx {} of closed channel {}", tx.txid(), hex_str(&outpoint.to_channel_id()));
+            }
+            open
+        });
+        if txn.len() != count {
+            self.persist(&txn);
+        }
+    }
+
+    pub fn take_tx(&self, outpoint: &OutPoint) -> Option<Transaction> {
+        let mut txn = self.txn_to_broadcast.lock().unwrap();
+        let tx = txn.remove(outpoint);
+        if tx.is_some() {
+            self.persist(&txn);
+        }
+        tx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::transaction::{OutPoint as TxOutPoint, TxIn, TxOut};
+    use bitcoin_hashes::sha256d::Hash as Sha256dHash;
+
+    fn funding_tx(value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: TxOutPoint { txid: Sha256dHash::default(), vout: 0 },
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut { value, script_pubkey: Script::new() }],
+        }
+    }
+
+    #[test]
+    fn closed_channels_lose_their_funding_tx() {
+        let dir = std::env::temp_dir().join(format!("rustbolt-fundings-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let data_path = dir.to_str().unwrap();
+
+        let fundings = Fundings::load(data_path);
+        let (open_tx, closed_tx) = (funding_tx(1000), funding_tx(2000));
+        let open = OutPoint { txid: open_tx.txid(), index: 0 };
+        let closed = OutPoint { txid: closed_tx.txid(), index: 0 };
+        let mut generated = 0;
+        fundings.funding_generated(&[1; 32], open, open_tx, || generated += 1);
+        fundings.funding_generated(&[2; 32], closed, closed_tx, || generated += 1);
+        assert_eq!(generated, 2);
+        let psbt_funding = PsbtFunding {
+            output_script: Script::new(),
+            value_sat: 1000,
+            address: "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".to_string(),
+            psbt: "cHNidP8BAHECAAAAAQ==".to_string(),
+        };
+        fundings.await_psbt([3; 32], psbt_funding.clone());
+        fundings.await_psbt([4; 32], psbt_funding);
+        assert_eq!(fundings.pending_psbts().len(), 2);
+
+        let mut channel_ids = HashSet::new();
+        channel_ids.insert(open.to_channel_id());
+        fundings.forget_closed(&channel_ids);
+        assert!(fundings.take_psbt_funding(&[3; 32]).is_none());
+        assert!(fundings.pending_psbts().is_empty());
+
+        // Still gone after a restart
+        let fundings = Fundings::load(data_path);
+        assert!(fundings.take_tx(&closed).is_none());
+        assert!(fundings.take_tx(&open).is_some());
+    }
+}
@@ -2,9 +2,12 @@ use std::fs;
 use lightning::util::ser::Writer;
 use rand::{thread_rng, Rng};
 use bitcoin::network::constants;
+use bitcoin::util::address::Address;
 use bitcoin::util::{bip32, key};
 use secp256k1::{self, Secp256k1};
 
+use super::utils::write_atomically;
+
 
 // pub struct Key {
 //   seed: [u8; 32]
@@ -61,6 +64,16 @@ pub fn get_key_seed(data_path: String) -> [u8; 32] {
     }
 }
 
+/// Copies the seed into `backup_dir`, or checks the copy already there is the same seed.
+pub fn backup_key_seed(seed: &[u8; 32], backup_dir: &str) {
+    let backup_path = format!("{}/key_seed", backup_dir);
+    if let Ok(backup) = fs::read(&backup_path) {
+        assert!(&backup[..] == &seed[..], "{} holds another node's seed", backup_path);
+    } else if let Err(e) = write_atomically(&backup_path, seed, 0o600) {
+        panic!("Failed to write seed backup to {}: {}", backup_path, e);
+    }
+}
+
 pub fn get_import_secret_keys(network: constants::Network, node_seed: &[u8; 32]) -> (secp256k1::SecretKey, secp256k1::SecretKey) {
     let secp_ctx = Secp256k1::new();
     bip32::ExtendedPrivKey::new_master(network, node_seed)
@@ -90,6 +103,16 @@ pub fn get_pub_from_secret(network: constants::Network, secret_key: secp256k1::S
     .public_key(&Secp256k1::new())
 }
 
+/// Bech32 addresses of the claim and cooperative close keys, the outputs rust-lightning pays
+/// to them are p2wpkh.
+pub fn get_import_addresses(network: constants::Network, node_seed: &[u8; 32]) -> Vec<String> {
+    let (claim_key, close_key) = get_import_secret_keys(network, node_seed);
+    vec![
+        Address::p2wpkh(&get_pub_from_secret(network, claim_key), network).to_string(),
+        Address::p2wpkh(&get_pub_from_secret(network, close_key), network).to_string(),
+    ]
+}
+
 // hex_str(&PublicKey::from_secret_key(&secp_ctx, &keys.get_node_secret()).serialize()));
 
 // bitcoin version
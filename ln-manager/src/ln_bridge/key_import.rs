@@ -0,0 +1,110 @@
+use bitcoin::network::constants::Network;
+use bitcoin::util::key::PrivateKey;
+use protocol::{ImportMode, KeyImport};
+use secp256k1::SecretKey;
+use serde_json::{json, Value};
+
+use super::key;
+use super::rpc_client::RPCClient;
+
+const LABELS: [&str; 2] = ["rust-lightning ChannelMonitor claim", "rust-lightning cooperative close"];
+
+/// Imports the claim and cooperative close keys into the bitcoind wallet so it sees what
+/// rust-lightning pays to them, with `importdescriptors` or `importprivkey` depending on what
+/// `getwalletinfo` says the wallet is.
+pub async fn import_keys(rpc_client: &RPCClient, network: Network, node_seed: &[u8; 32]) -> KeyImport {
+    let (claim_key, close_key) = key::get_import_secret_keys(network, node_seed);
+    let mut status = KeyImport {
+        addresses: key::get_import_addresses(network, node_seed),
+        ..Default::default()
+    };
+
+    let info = rpc_client
+        .make_rpc_call("getwalletinfo", &[], true)
+        .await
+        .unwrap_or(Value::Null);
+    if info.is_null() {
+        status.error = Some("bitcoind has no wallet loaded".to_string());
+        return status;
+    }
+    if info["private_keys_enabled"].as_bool() == Some(false) {
+        status.error = Some("the bitcoind wallet has private keys disabled".to_string());
+        return status;
+    }
+    // Only descriptor wallets have the field set
+    let mode = match info["descriptors"].as_bool() {
+        Some(true) => ImportMode::Descriptor,
+        _ => ImportMode::Legacy,
+    };
+    status.mode = Some(mode);
+
+    for (secret_key, label) in [claim_key, close_key].iter().zip(LABELS.iter()) {
+        let imported = match mode {
+            ImportMode::Legacy => import_privkey(rpc_client, network, secret_key, label).await,
+            ImportMode::Descriptor => import_descriptor(rpc_client, network, secret_key, label).await,
+        };
+        if let Err(e) = imported {
+            status.error = Some(e);
+            return status;
+        }
+    }
+
+    // importprivkey answers null either way, the wallet tells whether it worked
+    for address in &status.addresses {
+        let param = format!("\"{}\"", address);
+        let info = rpc_client
+            .make_rpc_call("getaddressinfo", &[&param[..]], true)
+            .await
+            .unwrap_or(Value::Null);
+        if info["ismine"].as_bool() != Some(true) {
+            status.error = Some(format!("the wallet doesn't own {} after the import", address));
+            return status;
+        }
+    }
+    status.imported = true;
+    status
+}
+
+fn to_wif(network: Network, secret_key: &SecretKey) -> String {
+    PrivateKey { key: *secret_key, compressed: true, network }.to_wif()
+}
+
+async fn import_privkey(rpc_client: &RPCClient, network: Network, secret_key: &SecretKey, label: &str) -> Result<(), String> {
+    let wif = format!("\"{}\"", to_wif(network, secret_key));
+    let label = format!("\"{}\"", label);
+    rpc_client
+        .make_rpc_call("importprivkey", &[&wif[..], &label[..], "false"], true)
+        .await
+        .map(|_| ())
+        .map_err(|_| "bitcoind refused importprivkey".to_string())
+}
+
+async fn import_descriptor(rpc_client: &RPCClient, network: Network, secret_key: &SecretKey, label: &str) -> Result<(), String> {
+    let desc = format!("wpkh({})", to_wif(network, secret_key));
+    let param = format!("\"{}\"", desc);
+    let info = rpc_client
+        .make_rpc_call("getdescriptorinfo", &[&param[..]], true)
+        .await
+        .unwrap_or(Value::Null);
+    let checksum = info["checksum"]
+        .as_str()
+        .ok_or_else(|| "bitcoind refused getdescriptorinfo".to_string())?;
+    // The keys are derived from the node seed, nothing was paid to them before the first start
+    let requests = json!([{
+        "desc": format!("{}#{}", desc, checksum),
+        "timestamp": "now",
+        "label": label,
+    }])
+    .to_string();
+    let res = rpc_client
+        .make_rpc_call("importdescriptors", &[&requests[..]], true)
+        .await
+        .unwrap_or(Value::Null);
+    match res[0]["success"].as_bool() {
+        Some(true) => Ok(()),
+        _ => Err(res[0]["error"]["message"]
+            .as_str()
+            .unwrap_or("bitcoind refused importdescriptors")
+            .to_string()),
+    }
+}
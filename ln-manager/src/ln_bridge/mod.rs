@@ -1,13 +1,20 @@
 pub mod key;
+pub mod key_import;
 pub mod rpc_client;
 pub mod connection;
 pub mod chain_monitor;
+pub mod chain_source;
 pub mod channel_manager;
 pub mod channel_monitor;
 pub mod event_handler;
+pub mod esplora;
+pub mod funding;
+pub mod sweeper;
 pub mod utils;
 pub mod log_printer;
 pub mod settings;
+pub mod signet;
+pub mod zmq;
 
 pub trait Restorable<R, T> {
   fn try_restore(args: R) -> T;
@@ -4,13 +4,22 @@ use serde_json;
 
 use bitcoin_hashes::hex::FromHex;
 use bitcoin_hashes::sha256d::Hash as Sha256dHash;
-use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::block::{Block, BlockHeader};
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
 
-use futures::{TryFutureExt, TryStreamExt};
+use futures::future::BoxFuture;
+use futures::{FutureExt, TryFutureExt, TryStreamExt};
+
+use super::chain_source::{ChainSource, HeaderInfo};
+use super::utils::hex_to_vec;
 
 use log::{info, error};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+// bitcoind's error for a transaction, block or address it doesn't know
+const RPC_INVALID_ADDRESS_OR_KEY: i64 = -5;
+
 #[derive(Deserialize, Clone)]
 pub struct GetHeaderResponse {
     pub hash: String,
@@ -68,7 +77,11 @@ impl RPCClient {
         params: &[&str],
         may_fail: bool,
     ) -> Result<serde_json::Value, ()> {
-        let mut request = hyper::Request::post(&self.uri);
+        self.make_wallet_rpc_call(None, method, params, may_fail).await
+    }
+
+    fn request(&self, uri: &str, method: &str, params: &[&str]) -> hyper::Request<hyper::Body> {
+        let mut request = hyper::Request::post(uri);
         let auth: &str = &self.basic_auth;
         request.header("Authorization", auth);
         let mut param_str = String::new();
@@ -78,18 +91,61 @@ impl RPCClient {
                 param_str += ",";
             }
         }
-        let raw_res = self.client.request(
-            request
-                .body(hyper::Body::from(
-                    "{\"method\":\"".to_string()
-                        + method
-                        + "\",\"params\":["
-                        + &param_str
-                        + "],\"id\":"
-                        + &self.id.fetch_add(1, Ordering::AcqRel).to_string()
-                        + "}",
-                )).unwrap()
-        ).await;
+        request
+            .body(hyper::Body::from(
+                "{\"method\":\"".to_string()
+                    + method
+                    + "\",\"params\":["
+                    + &param_str
+                    + "],\"id\":"
+                    + &self.id.fetch_add(1, Ordering::AcqRel).to_string()
+                    + "}",
+            )).unwrap()
+    }
+
+    /// Result of `method`, or the code and message of bitcoind's error (bitcoind answers those
+    /// with an error status). The code is None when bitcoind couldn't be reached or didn't
+    /// answer in JSON-RPC, e.g. on bad auth.
+    async fn call(&self, method: &str, params: &[&str]) -> Result<serde_json::Value, (Option<i64>, String)> {
+        let res = self
+            .client
+            .request(self.request(&self.uri, method, params))
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+        let body = res.into_body().try_concat().await.map_err(|e| (None, e.to_string()))?;
+        let v: serde_json::Value = serde_json::from_slice(&body)
+            .map_err(|_| (None, "Failed to parse RPC server response".to_string()))?;
+        match (v.get("result"), v["error"]["message"].as_str()) {
+            (_, Some(e)) => Err((v["error"]["code"].as_i64(), e.to_string())),
+            (Some(result), None) => Ok(result.clone()),
+            _ => Err((None, "Failed to parse RPC server response".to_string())),
+        }
+    }
+
+    /// `sendrawtransaction` of a hex encoded transaction, with the reason bitcoind gives when
+    /// it refuses it
+    pub async fn send_raw_transaction(&self, tx_hex: &str) -> Result<String, String> {
+        let param = format!("\"{}\"", tx_hex);
+        match self.call("sendrawtransaction", &[&param[..]]).await {
+            Ok(serde_json::Value::String(txid)) => Ok(txid),
+            Ok(_) => Err("Failed to parse RPC server response".to_string()),
+            Err((_, e)) => Err(e),
+        }
+    }
+
+    /// Same as `make_rpc_call`, against one wallet of a multiwallet bitcoind
+    pub async fn make_wallet_rpc_call(
+        &self,
+        wallet: Option<&str>,
+        method: &str,
+        params: &[&str],
+        may_fail: bool,
+    ) -> Result<serde_json::Value, ()> {
+        let uri = match wallet {
+            Some(wallet) => format!("{}/wallet/{}", self.uri, wallet),
+            None => self.uri.clone(),
+        };
+        let raw_res = self.client.request(self.request(&uri, method, params)).await;
         let res = raw_res.unwrap();
         if res.status() != hyper::StatusCode::OK {
             debug!("status wrong");
@@ -136,14 +192,13 @@ impl RPCClient {
     ) -> Result<GetHeaderResponse, ()> {
         let param = "\"".to_string() + header_hash + "\"";
         let p = &[&param[..]];
-        let v = self.make_rpc_call("getblockheader", p, false).await;
-        let mut v = v.unwrap();
+        let mut v = self.make_rpc_call("getblockheader", p, false).await?;
         if v.is_object() {
             if let None = v.get("previousblockhash") {
                 // Got a request for genesis block, add a dummy previousblockhash
                 v.as_object_mut().unwrap().insert(
                     "previousblockhash".to_string(),
-                    serde_json::Value::String("".to_string()),
+                    serde_json::Value::String("0".repeat(64)),
                 );
             }
         }
@@ -157,3 +212,85 @@ impl RPCClient {
         }
     }
 }
+
+impl ChainSource for RPCClient {
+    fn best_block(&self) -> BoxFuture<'_, Result<(String, u32), String>> {
+        async move {
+            let v = self
+                .make_rpc_call("getblockchaininfo", &[], false)
+                .await
+                .map_err(|_| "bitcoind refused getblockchaininfo".to_string())?;
+            match (v["bestblockhash"].as_str(), v["blocks"].as_u64()) {
+                (Some(hash), Some(height)) => Ok((hash.to_string(), height as u32)),
+                _ => Err("Invalid getblockchaininfo response".to_string()),
+            }
+        }.boxed()
+    }
+
+    fn get_header<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<HeaderInfo, String>> {
+        async move {
+            let header = self
+                .get_block_header(hash)
+                .await
+                .map_err(|_| format!("bitcoind has no header {}", hash))?;
+            Ok(HeaderInfo { hash: header.hash.clone(), height: header.height, header: header.into() })
+        }.boxed()
+    }
+
+    fn get_block<'a>(&'a self, hash: &'a str) -> BoxFuture<'a, Result<Block, String>> {
+        async move {
+            let param = format!("\"{}\"", hash);
+            let params = [&param[..], "0"];
+            let v = self
+                .make_rpc_call("getblock", &params, false)
+                .await
+                .map_err(|_| format!("bitcoind refused getblock {}", hash))?;
+            v.as_str()
+                .and_then(hex_to_vec)
+                .and_then(|block| encode::deserialize(&block).ok())
+                .ok_or_else(|| format!("bitcoind has no block {}", hash))
+        }.boxed()
+    }
+
+    fn estimate_fee(&self, conf_target: u16) -> BoxFuture<'_, Result<Option<f64>, String>> {
+        async move {
+            // Short targets are for transactions that must confirm in time
+            let mode = if conf_target <= 6 { "\"CONSERVATIVE\"" } else { "\"ECONOMICAL\"" };
+            let target = conf_target.to_string();
+            let params = [&target[..], mode];
+            let v = self
+                .make_rpc_call("estimatesmartfee", &params, false)
+                .await
+                .map_err(|_| "bitcoind refused estimatesmartfee".to_string())?;
+            // BTC/kvB
+            Ok(v["feerate"].as_f64().map(|feerate| feerate * 100_000.0))
+        }.boxed()
+    }
+
+    fn broadcast<'a>(&'a self, tx: &'a Transaction) -> BoxFuture<'a, Result<String, String>> {
+        async move {
+            let tx_hex = encode::serialize_hex(tx);
+            self.send_raw_transaction(&tx_hex).await
+        }.boxed()
+    }
+
+    fn has_transaction<'a>(&'a self, txid: &'a str) -> BoxFuture<'a, Result<bool, String>> {
+        async move {
+            let param = format!("\"{}\"", txid);
+            // The wallet knows its own transactions, confirmed ones included
+            match self.call("gettransaction", &[&param[..]]).await {
+                Ok(_) => return Ok(true),
+                Err((None, e)) => return Err(e),
+                // Not a wallet transaction, or no wallet at all
+                Err(_) => {}
+            }
+            match self.call("getrawtransaction", &[&param[..]]).await {
+                Ok(_) => Ok(true),
+                // Without txindex only the mempool is searched, a confirmed transaction looks
+                // just as unknown
+                Err((Some(RPC_INVALID_ADDRESS_OR_KEY), ref e)) if !e.contains("txindex") => Ok(false),
+                Err((_, e)) => Err(format!("bitcoind can't tell whether {} exists: {}", txid, e)),
+            }
+        }.boxed()
+    }
+}
@@ -4,17 +4,39 @@ use config::{Config, ConfigError, File}; // clap-rs
 pub struct Lightning {
     pub port: u16,
     pub lndata: String,
+    /// Depth at which our transactions stop being rebroadcast, 6 if unset.
+    pub rebroadcast_confirmations: Option<u32>,
+    /// Refuse to run against a mainnet bitcoind unless set to true.
+    pub allow_mainnet: Option<bool>,
+    /// Directory the key seed and channel monitors are mirrored to, required on mainnet.
+    pub backup_dir: Option<String>,
+    /// Largest channel we fund, 1000000 sat on mainnet if unset.
+    pub max_channel_sat: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Bitcoind {
     pub rpc_url: String,
+    /// bitcoind's `zmqpubhashblock` address, new blocks get picked up as soon as announced.
+    pub zmqpubhashblock: Option<String>,
+    /// Same with `zmqpubrawblock`, for a bitcoind publishing only that.
+    pub zmqpubrawblock: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Chain {
+    /// Blocks, fee estimates and broadcasts come from this Esplora API instead of bitcoind.
+    pub esplora_url: Option<String>,
+    /// Seconds between checks for a new block, 60 with ZMQ notifications, 30 with Esplora, 1
+    /// otherwise.
+    pub poll_interval: Option<u64>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
     pub lightning: Lightning,
-    pub bitcoind: Bitcoind
+    pub bitcoind: Bitcoind,
+    pub chain: Option<Chain>,
 }
 
 impl Settings {
@@ -0,0 +1,199 @@
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+
+use bech32::{u5, Bech32, FromBase32, ToBase32};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::hash::BitcoinHash;
+use bitcoin_hashes::sha256d::Hash as Sha256dHash;
+use bitcoin_hashes::Hash;
+use lightning::chain::chaininterface::{ChainError, ChainListener, ChainWatchInterface, ChainWatchInterfaceUtil};
+use lightning_invoice::{RawInvoice, SignedRawInvoice};
+use secp256k1::key::PublicKey;
+use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1, Verification};
+
+use crate::utils::{to_network, Chain};
+
+/// Prefix of signet invoices, lightning-invoice only knows testnet's `lntb`.
+pub const INVOICE_PREFIX: &str = "lntbs";
+const TESTNET_INVOICE_PREFIX: &str = "lntb";
+// 64 byte compact signature and recovery id, in 5 bit words
+const SIGNATURE_LEN: usize = 104;
+
+// What the invoice signature commits to, padded to whole bytes like lightning-invoice does
+fn invoice_hash(hrp: &str, data: &[u5]) -> Result<Message, String> {
+    let mut data = data.to_vec();
+    let overhang = (data.len() * 5) % 8;
+    if overhang > 0 {
+        data.push(u5::try_from_u8(0).unwrap());
+        if overhang < 3 {
+            data.push(u5::try_from_u8(0).unwrap());
+        }
+    }
+    let mut preimage = hrp.as_bytes().to_vec();
+    preimage.extend(Vec::<u8>::from_base32(&data).map_err(|e| e.to_string())?);
+    Message::from_slice(&bitcoin_hashes::sha256::Hash::hash(&preimage).into_inner()).map_err(|e| e.to_string())
+}
+
+/// Signs an invoice built for testnet over the signet prefix, the prefix is covered by the
+/// signature so it can't be swapped in afterwards.
+pub fn sign_invoice(raw: RawInvoice, sign: impl FnOnce(&Message) -> RecoverableSignature) -> Result<String, String> {
+    let hrp = raw.hrp.to_string();
+    if !hrp.starts_with(TESTNET_INVOICE_PREFIX) {
+        return Err(format!("{} is not a testnet invoice prefix", hrp));
+    }
+    let hrp = hrp.replacen(TESTNET_INVOICE_PREFIX, INVOICE_PREFIX, 1);
+    let mut data = raw.data.to_base32();
+    let (recovery_id, signature) = sign(&invoice_hash(&hrp, &data)?).serialize_compact();
+    let mut signature = signature.to_vec();
+    signature.push(recovery_id.to_i32() as u8);
+    data.extend(signature.to_base32());
+    Bech32::new(hrp, data).map(|invoice| invoice.to_string()).map_err(|e| e.to_string())
+}
+
+/// Checks the signature of a signet invoice, returns its fields, parsed as testnet ones, and
+/// its payee.
+pub fn parse_invoice<C: Verification>(invoice: &str, secp_ctx: &Secp256k1<C>) -> Result<(RawInvoice, PublicKey), String> {
+    let invoice = invoice.to_lowercase();
+    let bech32 = Bech32::from_str(&invoice).map_err(|e| e.to_string())?;
+    if !bech32.hrp().starts_with(INVOICE_PREFIX) || bech32.data().len() < SIGNATURE_LEN {
+        return Err("Not a signet invoice".to_string());
+    }
+    let (data, signature) = bech32.data().split_at(bech32.data().len() - SIGNATURE_LEN);
+    let signature = Vec::<u8>::from_base32(signature).map_err(|e| e.to_string())?;
+    let recovery_id = RecoveryId::from_i32(signature[64] as i32).map_err(|e| e.to_string())?;
+    let signature = RecoverableSignature::from_compact(&signature[..64], recovery_id).map_err(|e| e.to_string())?;
+    let payee = secp_ctx
+        .recover(&invoice_hash(bech32.hrp(), data)?, &signature)
+        .map_err(|e| e.to_string())?;
+
+    // lightning-invoice checks the signature over the prefix it parsed, only take the fields
+    let signed = SignedRawInvoice::from_str(&invoice.replacen(INVOICE_PREFIX, TESTNET_INVOICE_PREFIX, 1))
+        .map_err(|e| format!("{:?}", e))?;
+    let (raw, _, _) = signed.into_parts();
+    if let Some(pubkey) = raw.payee_pub_key() {
+        if pubkey.0 != payee {
+            return Err("Invoice is not signed by its payee".to_string());
+        }
+    }
+    Ok((raw, payee))
+}
+
+/// rust-lightning takes the genesis hash of a new channel manager from `Network`, which has no
+/// signet, but reads it back from a serialized manager, where it follows the two version bytes.
+pub fn set_genesis_hash(manager: &mut [u8], from: &Sha256dHash, to: &Sha256dHash) -> Result<(), String> {
+    match manager.get_mut(2..34) {
+        Some(genesis_hash) if genesis_hash[..] == from[..] => {
+            genesis_hash.copy_from_slice(&to[..]);
+            Ok(())
+        }
+        _ => Err("The serialized channel manager doesn't carry the expected genesis hash".to_string()),
+    }
+}
+
+/// The chain watcher of the router. `ChainWatchInterfaceUtil` only knows the genesis hash of
+/// its `Network`, testnet on signet, and would refuse every signet channel announcement.
+pub struct GenesisWatch {
+    chain_watcher: Arc<ChainWatchInterfaceUtil>,
+    genesis_hash: Sha256dHash,
+    network_genesis_hash: Sha256dHash,
+}
+
+impl GenesisWatch {
+    pub fn new(chain_watcher: Arc<ChainWatchInterfaceUtil>, chain: Chain) -> Self {
+        GenesisWatch {
+            chain_watcher,
+            genesis_hash: chain.genesis_hash(),
+            network_genesis_hash: genesis_block(to_network(chain)).bitcoin_hash(),
+        }
+    }
+}
+
+impl ChainWatchInterface for GenesisWatch {
+    fn install_watch_tx(&self, txid: &Sha256dHash, script_pub_key: &Script) {
+        self.chain_watcher.install_watch_tx(txid, script_pub_key)
+    }
+
+    fn install_watch_outpoint(&self, outpoint: (Sha256dHash, u32), out_script: &Script) {
+        self.chain_watcher.install_watch_outpoint(outpoint, out_script)
+    }
+
+    fn watch_all_txn(&self) {
+        self.chain_watcher.watch_all_txn()
+    }
+
+    fn register_listener(&self, listener: Weak<dyn ChainListener>) {
+        self.chain_watcher.register_listener(listener)
+    }
+
+    fn get_chain_utxo(&self, genesis_hash: Sha256dHash, unspent_tx_output_identifier: u64) -> Result<(Script, u64), ChainError> {
+        if genesis_hash != self.genesis_hash {
+            return Err(ChainError::NotWatched);
+        }
+        self.chain_watcher.get_chain_utxo(self.network_genesis_hash, unspent_tx_output_identifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::network::constants::Network;
+    use bitcoin_hashes::hex::ToHex;
+    use lightning_invoice::{Currency, Invoice, InvoiceBuilder};
+    use secp256k1::key::SecretKey;
+
+    fn raw_invoice(amount_pico_btc: u64) -> RawInvoice {
+        InvoiceBuilder::new(Currency::BitcoinTestnet)
+            .payment_hash(bitcoin_hashes::sha256::Hash::hash(&[1; 32]))
+            .description("signet".to_string())
+            .amount_pico_btc(amount_pico_btc)
+            .current_timestamp()
+            .build_raw()
+            .unwrap()
+    }
+
+    // The same data and signature under another prefix
+    fn with_hrp(invoice: &str, hrp: &str) -> String {
+        Bech32::new(hrp.to_string(), Bech32::from_str(invoice).unwrap().data().to_vec()).unwrap().to_string()
+    }
+
+    #[test]
+    fn signet_invoices() {
+        let secp_ctx = Secp256k1::new();
+        let key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let node_id = PublicKey::from_secret_key(&secp_ctx, &key);
+        let invoice = sign_invoice(raw_invoice(10_000), |msg| secp_ctx.sign_recoverable(msg, &key)).unwrap();
+        assert!(invoice.starts_with("lntbs10n1"));
+        // Testnet nodes don't take it, and under testnet's prefix it recovers another node
+        assert!(Invoice::from_str(&invoice).is_err());
+        let testnet = with_hrp(&invoice, "lntb10n");
+        assert_ne!(Invoice::from_str(&testnet).ok().map(|i| i.recover_payee_pub_key()), Some(node_id));
+        assert!(parse_invoice(&testnet, &secp_ctx).is_err());
+
+        let (raw, payee) = parse_invoice(&invoice.to_uppercase(), &secp_ctx).unwrap();
+        assert_eq!(payee, node_id);
+        assert_eq!(raw.amount_pico_btc(), Some(10_000));
+        assert!(raw.payment_hash().is_some());
+
+        // Another amount under the same signature recovers another payee
+        let other_payee = parse_invoice(&with_hrp(&invoice, "lntbs20n"), &secp_ctx).map(|(_, payee)| payee);
+        assert!(other_payee.is_ok());
+        assert_ne!(other_payee, Ok(payee));
+    }
+
+    #[test]
+    fn replaces_the_genesis_hash() {
+        let testnet = genesis_block(Network::Testnet).bitcoin_hash();
+        let signet = Chain::Signet.genesis_hash();
+        let mut manager = vec![1, 1];
+        manager.extend_from_slice(&testnet[..]);
+        manager.extend_from_slice(&[0; 8]);
+        set_genesis_hash(&mut manager, &testnet, &signet).unwrap();
+        assert_eq!(&manager[2..34], &signet[..]);
+        assert_eq!(&manager[34..], &[0; 8]);
+        assert!(set_genesis_hash(&mut manager, &testnet, &signet).is_err());
+        assert!(set_genesis_hash(&mut [1, 1], &signet, &testnet).is_err());
+        assert_eq!(signet.to_hex(), "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6");
+    }
+}
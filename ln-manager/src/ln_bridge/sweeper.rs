@@ -0,0 +1,415 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bitcoin::blockdata::opcodes;
+use bitcoin::blockdata::script::{Builder, Script};
+use bitcoin::blockdata::transaction::{OutPoint, SigHashType, Transaction, TxIn, TxOut};
+use bitcoin::consensus::encode;
+use bitcoin::util::address::Address;
+use bitcoin::util::bip143;
+use bitcoin_hashes::{hash160, Hash};
+
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::chain::keysinterface::SpendableOutputDescriptor;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+
+use super::chain_source::ChainSource;
+use super::rpc_client::RPCClient;
+use super::utils::{hex_str, hex_to_vec, write_atomically};
+
+// A sweep is forgotten once it is this deep, or once a conflicting spend is
+const SWEEP_CONFIRMATIONS: i64 = 6;
+// Below this bitcoind won't relay the sweep, not worth a transaction
+const DUST_LIMIT_SATOSHIS: u64 = 546;
+
+/// An output of ours only we can spend (to_local of a force close, or our output of a closing
+/// transaction paying to a derived key), with the sweep transaction once it was signed.
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingSweep {
+    // consensus encoded `OutPoint` and `TxOut`
+    outpoint: String,
+    output: String,
+    key: String,
+    // only set for to_local outputs, which are behind a CSV delay
+    witness_script: Option<String>,
+    to_self_delay: Option<u16>,
+    sweep_tx: Option<String>,
+}
+
+impl PendingSweep {
+    fn from_descriptor(descriptor: &SpendableOutputDescriptor) -> Option<Self> {
+        match descriptor {
+            SpendableOutputDescriptor::StaticOutput { .. } => None,
+            SpendableOutputDescriptor::DynamicOutputP2WSH { outpoint, key, witness_script, to_self_delay, output } => {
+                Some(PendingSweep {
+                    outpoint: encode::serialize_hex(outpoint),
+                    output: encode::serialize_hex(output),
+                    key: hex_str(&key[..]),
+                    witness_script: Some(hex_str(witness_script.as_bytes())),
+                    to_self_delay: Some(*to_self_delay),
+                    sweep_tx: None,
+                })
+            }
+            SpendableOutputDescriptor::DynamicOutputP2WPKH { outpoint, key, output } => Some(PendingSweep {
+                outpoint: encode::serialize_hex(outpoint),
+                output: encode::serialize_hex(output),
+                key: hex_str(&key[..]),
+                witness_script: None,
+                to_self_delay: None,
+                sweep_tx: None,
+            }),
+        }
+    }
+
+    fn outpoint(&self) -> Result<OutPoint, String> {
+        decode_hex(&self.outpoint)
+    }
+
+    // Spends the output to `destination` at `sat_per_1000_weight`, signed with the descriptor key
+    fn sign(&self, destination: Script, sat_per_1000_weight: u64) -> Result<Transaction, String> {
+        let secp_ctx = Secp256k1::signing_only();
+        let output: TxOut = decode_hex(&self.output)?;
+        let key = hex_to_vec(&self.key)
+            .and_then(|k| SecretKey::from_slice(&k).ok())
+            .ok_or_else(|| "Invalid sweep key".to_string())?;
+        let pubkey = PublicKey::from_secret_key(&secp_ctx, &key);
+
+        let (script_code, sequence, witness_weight) = match (&self.witness_script, self.to_self_delay) {
+            (Some(script), Some(to_self_delay)) => {
+                let script = Script::from(hex_to_vec(script).ok_or_else(|| "Invalid witness script".to_string())?);
+                // items count, signature, empty push to take the delayed branch, script
+                let weight = 1 + 73 + 1 + 1 + script.len() as u64;
+                (script, to_self_delay as u32, weight)
+            }
+            _ => {
+                let script = Builder::new()
+                    .push_opcode(opcodes::all::OP_DUP)
+                    .push_opcode(opcodes::all::OP_HASH160)
+                    .push_slice(&hash160::Hash::hash(&pubkey.serialize())[..])
+                    .push_opcode(opcodes::all::OP_EQUALVERIFY)
+                    .push_opcode(opcodes::all::OP_CHECKSIG)
+                    .into_script();
+                // items count, signature, pubkey, the sequence signals RBF for `Sweeper::bump`
+                (script, 0xffff_fffd, 1 + 73 + 1 + 33)
+            }
+        };
+
+        let mut tx = Transaction {
+            // BIP 68 relative locktimes need version 2
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: self.outpoint()?,
+                script_sig: Script::new(),
+                sequence,
+                witness: vec![],
+            }],
+            output: vec![TxOut { script_pubkey: destination, value: 0 }],
+        };
+        // segwit marker and flag plus the witness
+        let weight = tx.get_weight() as u64 + 2 + witness_weight;
+        let fee = weight * sat_per_1000_weight / 1000;
+        if output.value < fee + DUST_LIMIT_SATOSHIS {
+            return Err(format!("Output of {} sat is not worth sweeping at {} sat fee", output.value, fee));
+        }
+        tx.output[0].value = output.value - fee;
+
+        let sighash = bip143::SighashComponents::new(&tx).sighash_all(&tx.input[0], &script_code, output.value);
+        let sig = secp_ctx.sign(&Message::from_slice(&sighash[..]).unwrap(), &key);
+        let mut sig = sig.serialize_der().to_vec();
+        sig.push(SigHashType::All as u8);
+        tx.input[0].witness = match self.to_self_delay {
+            Some(_) => vec![sig, vec![], script_code.into_bytes()],
+            None => vec![sig, pubkey.serialize().to_vec()],
+        };
+        Ok(tx)
+    }
+
+    // Signs again to the address `old_tx` pays, if `sat_per_1000_weight` pays enough more than
+    // it for a BIP 125 replacement: its own relay on top of the old fee
+    fn resign(&self, old_tx: &Transaction, sat_per_1000_weight: u64) -> Result<Transaction, String> {
+        let output: TxOut = decode_hex(&self.output)?;
+        let old_fee = output.value - old_tx.output[0].value;
+        let tx = self.sign(old_tx.output[0].script_pubkey.clone(), sat_per_1000_weight)?;
+        let new_fee = output.value - tx.output[0].value;
+        let vsize = (tx.get_weight() as u64 + 3) / 4;
+        if new_fee < old_fee + vsize {
+            return Err(format!("The sweep already pays {} sat, bump it by at least 1 sat/vB", old_fee));
+        }
+        Ok(tx)
+    }
+}
+
+fn decode_hex<T: encode::Decodable>(hex: &str) -> Result<T, String> {
+    hex_to_vec(hex)
+        .ok_or_else(|| "Invalid hex".to_string())
+        .and_then(|bytes| encode::deserialize(&bytes).map_err(|e| e.to_string()))
+}
+
+// Lets the next `Sweeper::sweep` run however this one ends, cancelled included
+struct SweepingGuard<'a>(&'a AtomicBool);
+
+impl<'a> Drop for SweepingGuard<'a> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Sends the outputs rust-lightning hands us in `Event::SpendableOutputs` back to the bitcoind
+/// wallet. Pending sweeps live in `<lndata>/sweeps.json` until they are confirmed, `sweep` is
+/// called on every new block and at startup so they survive restarts, and to_local outputs get
+/// broadcast once their CSV delay expired.
+pub struct Sweeper {
+    file: String,
+    rpc_client: Arc<RPCClient>,
+    chain_source: Arc<dyn ChainSource>,
+    fee_estimator: Arc<dyn FeeEstimator>,
+    pending: Mutex<Vec<PendingSweep>>,
+    sweeping: AtomicBool,
+    // Set by calls that found a sweep running, so that it goes over the pending sweeps again
+    rerun: AtomicBool,
+}
+
+impl Sweeper {
+    pub fn load(
+        data_path: &str,
+        rpc_client: Arc<RPCClient>,
+        chain_source: Arc<dyn ChainSource>,
+        fee_estimator: Arc<dyn FeeEstimator>,
+    ) -> Self {
+        let file = format!("{}/sweeps.json", data_path);
+        let pending: Vec<PendingSweep> = match fs::read(&file) {
+            // Starting without them would overwrite the keys of unclaimed outputs
+            Ok(content) => serde_json::from_slice(&content)
+                .unwrap_or_else(|e| panic!("Could not read pending sweeps from {}: {}", file, e)),
+            Err(_) => vec![],
+        };
+        if !pending.is_empty() {
+            info!("Loaded {} pending sweeps", pending.len());
+        }
+        Sweeper {
+            file,
+            rpc_client,
+            chain_source,
+            fee_estimator,
+            pending: Mutex::new(pending),
+            sweeping: AtomicBool::new(false),
+            rerun: AtomicBool::new(false),
+        }
+    }
+
+    // The file holds private keys
+    fn persist(&self, pending: &[PendingSweep]) {
+        let res = serde_json::to_vec(pending)
+            .map_err(|e| e.to_string())
+            .and_then(|content| write_atomically(&self.file, &content, 0o600));
+        if let Err(e) = res {
+            error!("Could not persist pending sweeps to {}: {}", self.file, e);
+        }
+    }
+
+    /// Remembers the outputs we have to claim ourselves, static ones already belong to the
+    /// bitcoind wallet through the imported keys.
+    pub fn track(&self, descriptor: &SpendableOutputDescriptor) -> bool {
+        let sweep = match PendingSweep::from_descriptor(descriptor) {
+            Some(sweep) => sweep,
+            None => return false,
+        };
+        let mut pending = self.pending.lock().unwrap();
+        if pending.iter().all(|p| p.outpoint != sweep.outpoint) {
+            pending.push(sweep);
+            self.persist(&pending);
+        }
+        true
+    }
+
+    /// What the pending sweeps are worth before fees.
+    pub fn claimable_sat(&self) -> u64 {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|p| decode_hex::<TxOut>(&p.output).ok())
+            .map(|o| o.value)
+            .sum()
+    }
+
+    /// Signs, broadcasts or forgets every pending sweep depending on where it is at. Sweeps still
+    /// waiting to confirm are signed again when the fee estimate rose since. When a sweep is
+    /// running already it goes over the pending sweeps once more, instead of this call.
+    pub async fn sweep(&self) {
+        self.rerun.store(true, Ordering::Release);
+        loop {
+            if self.sweeping.swap(true, Ordering::AcqRel) {
+                return;
+            }
+            let guard = SweepingGuard(&self.sweeping);
+            while self.rerun.swap(false, Ordering::AcqRel) {
+                self.sweep_pending().await;
+            }
+            drop(guard);
+            // A call between the last check and the guard's drop returned early
+            if !self.rerun.load(Ordering::Acquire) {
+                return;
+            }
+        }
+    }
+
+    async fn sweep_pending(&self) {
+        let feerate = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        let pending = self.pending.lock().unwrap().clone();
+        for sweep in pending {
+            let outpoint = match sweep.outpoint() {
+                Ok(outpoint) => outpoint,
+                Err(e) => {
+                    error!("Dropping corrupted pending sweep: {}", e);
+                    self.update(&sweep.outpoint, None);
+                    continue;
+                }
+            };
+            let tx = match &sweep.sweep_tx {
+                Some(tx) => tx.clone(),
+                None => match self.sign(&sweep).await {
+                    Ok(tx) => {
+                        let mut signed = sweep.clone();
+                        signed.sweep_tx = Some(tx.clone());
+                        self.update(&sweep.outpoint, Some(signed));
+                        tx
+                    }
+                    Err(e) => {
+                        warn!("Could not sweep {}:{}: {}", outpoint.txid, outpoint.vout, e);
+                        continue;
+                    }
+                },
+            };
+            let mut tx = match decode_hex::<Transaction>(&tx) {
+                Ok(tx) => tx,
+                Err(_) => continue,
+            };
+
+            // Sweeps pay to the wallet, so it follows them even when bitcoind isn't the chain source
+            let param = format!("\"{}\"", tx.txid());
+            let confirmations = self
+                .rpc_client
+                .make_rpc_call("gettransaction", &[&param[..]], true)
+                .await
+                .ok()
+                .and_then(|v| v["confirmations"].as_i64());
+            match confirmations {
+                Some(c) if c >= SWEEP_CONFIRMATIONS => {
+                    info!("Sweep {} of {}:{} confirmed", tx.txid(), outpoint.txid, outpoint.vout);
+                    self.update(&sweep.outpoint, None);
+                }
+                Some(c) if c <= -SWEEP_CONFIRMATIONS => {
+                    warn!("Sweep {} of {}:{} conflicts with a confirmed spend, giving up", tx.txid(), outpoint.txid, outpoint.vout);
+                    self.update(&sweep.outpoint, None);
+                }
+                Some(c) if c > 0 => {}
+                _ => {
+                    if let Ok(replacement) = sweep.resign(&tx, feerate) {
+                        info!("Fee estimate rose, replacing sweep {} by {}", tx.txid(), replacement.txid());
+                        let mut replaced = sweep.clone();
+                        replaced.sweep_tx = Some(encode::serialize_hex(&replacement));
+                        self.update(&sweep.outpoint, Some(replaced));
+                        tx = replacement;
+                    }
+                    // Fails until a to_local output is past its CSV delay, we'll try on the next block
+                    match self.chain_source.broadcast(&tx).await {
+                        Ok(_) => info!("Broadcast sweep {} of {}:{}", tx.txid(), outpoint.txid, outpoint.vout),
+                        Err(e) => debug!("Sweep {} not accepted yet: {}", tx.txid(), e),
+                    }
+                }
+            }
+        }
+    }
+
+    async fn sign(&self, sweep: &PendingSweep) -> Result<String, String> {
+        let address = self
+            .rpc_client
+            .make_rpc_call("getnewaddress", &["\"rust-lightning sweep\"", "\"bech32\""], false)
+            .await
+            .map_err(|_| "Could not get an address from bitcoind".to_string())?;
+        let address: Address = address
+            .as_str()
+            .ok_or_else(|| "Could not get an address from bitcoind".to_string())?
+            .parse()
+            .map_err(|e| format!("Invalid address from bitcoind: {}", e))?;
+        let feerate = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::Normal);
+        let tx = sweep.sign(address.script_pubkey(), feerate)?;
+        Ok(encode::serialize_hex(&tx))
+    }
+
+    /// Re-signs the sweep with that txid at `sat_per_vbyte`, paying to the same address. None if
+    /// it isn't one of our sweeps, the replacement goes out now or on the next block if the
+    /// output is still behind its CSV delay.
+    pub async fn bump(&self, txid: &str, sat_per_vbyte: u64) -> Option<Result<String, String>> {
+        let (sweep, old_tx) = self.pending.lock().unwrap().iter().find_map(|p| {
+            let tx = p.sweep_tx.as_ref().and_then(|tx| decode_hex::<Transaction>(tx).ok())?;
+            if tx.txid().to_string() == txid {
+                Some((p.clone(), tx))
+            } else {
+                None
+            }
+        })?;
+        Some(self.replace(sweep, old_tx, sat_per_vbyte).await)
+    }
+
+    async fn replace(&self, sweep: PendingSweep, old_tx: Transaction, sat_per_vbyte: u64) -> Result<String, String> {
+        // 4 weight units per vbyte
+        let tx = sweep.resign(&old_tx, sat_per_vbyte * 250)?;
+
+        let txid = tx.txid();
+        let mut replaced = sweep.clone();
+        replaced.sweep_tx = Some(encode::serialize_hex(&tx));
+        self.update(&sweep.outpoint, Some(replaced));
+
+        match self.chain_source.broadcast(&tx).await {
+            Ok(_) => info!("Replaced sweep {} by {}", old_tx.txid(), txid),
+            Err(e) => info!("Replaced sweep {} by {}, broadcast on the next block: {}", old_tx.txid(), txid, e),
+        }
+        Ok(txid.to_string())
+    }
+
+    // Replaces the sweep of that outpoint, or forgets it
+    fn update(&self, outpoint: &str, sweep: Option<PendingSweep>) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|p| p.outpoint != outpoint);
+        if let Some(sweep) = sweep {
+            pending.push(sweep);
+        }
+        self.persist(&pending);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_hashes::sha256d::Hash as Sha256dHash;
+
+    fn p2wpkh_sweep(value: u64) -> PendingSweep {
+        PendingSweep {
+            outpoint: encode::serialize_hex(&OutPoint { txid: Sha256dHash::default(), vout: 1 }),
+            output: encode::serialize_hex(&TxOut { script_pubkey: Script::new(), value }),
+            key: "01".repeat(32),
+            witness_script: None,
+            to_self_delay: None,
+            sweep_tx: None,
+        }
+    }
+
+    #[test]
+    fn resign_only_when_the_fee_rises_enough() {
+        let sweep = p2wpkh_sweep(100_000);
+        let tx = sweep.sign(Script::new(), 253).unwrap();
+        assert!(sweep.resign(&tx, 253).is_err());
+        // The replacement pays its own relay, 1 sat/vB, on top of the old fee
+        let replacement = sweep.resign(&tx, 253 + 500).unwrap();
+        assert!(replacement.output[0].value < tx.output[0].value);
+        assert_eq!(replacement.input[0].previous_output, tx.input[0].previous_output);
+        assert!(sweep.resign(&replacement, 253 + 500).is_err());
+
+        // Not worth sweeping at all once the fee eats the output
+        assert!(p2wpkh_sweep(1_000).sign(Script::new(), 25_003).is_err());
+    }
+}
@@ -1,5 +1,9 @@
 use secp256k1::key::PublicKey;
 
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
 pub fn hex_to_vec(hex: &str) -> Option<Vec<u8>> {
 	let mut out = Vec::with_capacity(hex.len() / 2);
 
@@ -52,3 +56,18 @@ pub fn slice_to_be64(v: &[u8]) -> u64 {
 	((v[6] as u64) << 8*1) |
 	((v[7] as u64) << 8*0)
 }
+
+/// Writes `content` aside, syncs it and renames it over `path` so a crash leaves either the old
+/// file or the new one, never half of it. `mode` applies to a newly created file.
+pub fn write_atomically(path: &str, content: &[u8], mode: u32) -> Result<(), String> {
+	let tmp_path = format!("{}.tmp", path);
+	let mut f = fs::OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.mode(mode)
+		.open(&tmp_path)
+		.map_err(|e| format!("{}: {}", tmp_path, e))?;
+	f.write_all(content).and_then(|_| f.sync_all()).map_err(|e| format!("{}: {}", tmp_path, e))?;
+	fs::rename(&tmp_path, path).map_err(|e| format!("{}: {}", path, e))
+}
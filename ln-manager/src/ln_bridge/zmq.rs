@@ -0,0 +1,162 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+
+// Frame flags of ZMTP 3.0 (RFC 23)
+const MORE: u8 = 0x01;
+const LONG: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+// rawblock bodies are whole blocks
+const MAX_FRAME_LEN: u64 = 16 * 1024 * 1024;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Signature, version 3.0, NULL mechanism, as client
+fn greeting() -> [u8; 64] {
+    let mut greeting = [0; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+fn ready() -> Vec<u8> {
+    let mut body = vec![5];
+    body.extend_from_slice(b"READY");
+    body.push(11);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&3u32.to_be_bytes());
+    body.extend_from_slice(b"SUB");
+    let mut frame = vec![COMMAND, body.len() as u8];
+    frame.extend(body);
+    frame
+}
+
+fn invalid(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+// Flags and body of the next frame
+fn read_frame(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0; 1];
+    stream.read_exact(&mut flags)?;
+    let len = if flags[0] & LONG != 0 {
+        let mut len = [0; 8];
+        stream.read_exact(&mut len)?;
+        u64::from_be_bytes(len)
+    } else {
+        let mut len = [0; 1];
+        stream.read_exact(&mut len)?;
+        len[0] as u64
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(invalid("frame too large"));
+    }
+    let mut body = vec![0; len as usize];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+// Sends () for every message on `topic` until the connection drops, or the receiver is gone
+fn listen(endpoint: &str, topic: &str, notify: &mpsc::UnboundedSender<()>) -> io::Result<()> {
+    let mut stream = TcpStream::connect(endpoint.trim_start_matches("tcp://"))?;
+    stream.write_all(&greeting())?;
+    let mut peer_greeting = [0; 64];
+    stream.read_exact(&mut peer_greeting)?;
+    if peer_greeting[0] != 0xff || peer_greeting[9] != 0x7f || peer_greeting[10] < 3 {
+        return Err(invalid("not a ZMTP 3 publisher"));
+    }
+    stream.write_all(&ready())?;
+    let (flags, _) = read_frame(&mut stream)?;
+    if flags & COMMAND == 0 {
+        return Err(invalid("expected READY"));
+    }
+    // ZMTP 3.0 subscriptions are messages starting with 1
+    let mut subscribe = vec![0, topic.len() as u8 + 1, 1];
+    subscribe.extend_from_slice(topic.as_bytes());
+    stream.write_all(&subscribe)?;
+    info!("Listening to {} notifications on {}", topic, endpoint);
+
+    // The first part of a message is its topic, the block and sequence number follow
+    let mut first_part = true;
+    loop {
+        let (flags, body) = read_frame(&mut stream)?;
+        if flags & COMMAND != 0 {
+            continue;
+        }
+        if first_part && body == topic.as_bytes() && notify.unbounded_send(()).is_err() {
+            return Ok(());
+        }
+        first_part = flags & MORE == 0;
+    }
+}
+
+/// Sends () for every block bitcoind announces on `endpoint`, its `zmqpubhashblock` address
+/// with the `hashblock` topic or `zmqpubrawblock` with `rawblock`. Reconnects when bitcoind
+/// restarts.
+pub fn subscribe(endpoint: String, topic: &'static str, notify: mpsc::UnboundedSender<()>) {
+    thread::spawn(move || loop {
+        match listen(&endpoint, topic, &notify) {
+            Ok(()) => return,
+            Err(e) => warn!("{} notifications from {} stopped: {}", topic, endpoint, e),
+        }
+        // Blocks missed while disconnected are picked up on the next sync
+        if notify.unbounded_send(()).is_err() {
+            return;
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn frame(flags: u8, body: &[u8]) -> Vec<u8> {
+        let mut frame = vec![flags, body.len() as u8];
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    #[test]
+    fn notifies_on_topic() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("tcp://{}", listener.local_addr().unwrap());
+        let publisher = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut greeting = [0; 64];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(&greeting[12..16], b"NULL");
+            stream.write_all(&super::greeting()).unwrap();
+            let (flags, ready) = read_frame(&mut stream).unwrap();
+            assert_eq!(flags, COMMAND);
+            assert!(ready.ends_with(b"Socket-Type\x00\x00\x00\x03SUB"));
+            stream.write_all(&super::ready()).unwrap();
+            assert_eq!(read_frame(&mut stream).unwrap(), (0, b"\x01hashblock".to_vec()));
+
+            stream.write_all(&frame(MORE, b"rawtx")).unwrap();
+            stream.write_all(&frame(MORE, b"hashblock")).unwrap();
+            stream.write_all(&frame(0, &[0; 4])).unwrap();
+            stream.write_all(&frame(MORE, b"hashblock")).unwrap();
+            // The block hash as a long frame
+            stream.write_all(&[MORE | LONG, 0, 0, 0, 0, 0, 0, 0, 32]).unwrap();
+            stream.write_all(&[0; 32]).unwrap();
+            stream.write_all(&frame(0, &[1, 0, 0, 0])).unwrap();
+        });
+
+        let (notify, mut notifications) = mpsc::unbounded();
+        let res = listen(&endpoint, "hashblock", &notify);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        publisher.join().unwrap();
+
+        let mut count = 0;
+        while let Ok(Some(())) = notifications.try_next() {
+            count += 1;
+        }
+        assert_eq!(count, 1);
+    }
+}
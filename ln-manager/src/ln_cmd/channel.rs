@@ -1,37 +1,48 @@
 use futures::channel::mpsc;
 use std::sync::Arc;
 
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use lightning::chain::transaction::OutPoint;
 use lightning::ln::channelmanager::ChannelManager;
+use protocol::{ChannelCloseRequest, ChannelCreateRequest, ChannelFundRequest, ChannelListMode};
+use crate::ln_bridge::funding::{Fundings, PsbtFunding};
+use crate::ln_bridge::rpc_client::RPCClient;
 use crate::ln_bridge::utils::{hex_str, hex_to_vec, hex_to_compressed_pubkey};
 use serde_json::json;
 
 pub trait ChannelC {
-    fn fund_channel(&self, line: Vec<String>) -> Result<String, String>;
-    fn close(&self, line: String) -> Result<String, String>;
+    fn fund_channel(&self, req: ChannelCreateRequest) -> Result<String, String>;
+    fn close(&self, req: ChannelCloseRequest) -> Result<String, String>;
     fn force_close_all(&self);
-    fn channel_list(&self, mode: &str) -> Vec<String>;
+    fn channel_list(&self, mode: ChannelListMode) -> Vec<String>;
 }
 
 // fund channel
 pub fn fund_channel (
-    args: Vec<String>,
+    req: ChannelCreateRequest,
     channel_manager: &Arc<ChannelManager>,
+    fundings: &Fundings,
+    max_channel_sat: u64,
     mut event_notify: mpsc::Sender<()>,
 ) -> Result<String, String> {
-    let pubkey_str = &args[0];
-    let value_str = &args[1];
-    let push_str = &args[2];
-    match hex_to_compressed_pubkey(&pubkey_str) {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
+    if req.value_sat > max_channel_sat {
+        return Err(format!("Channel of {} sat is above max_channel_sat ({} sat)", req.value_sat, max_channel_sat));
+    }
+    match hex_to_compressed_pubkey(&req.node_id) {
         Some(pubkey) => {
-            let value = value_str.parse().unwrap_or(100000);
-            let push = push_str.parse().unwrap_or(500000);
-            match channel_manager.create_channel(pubkey, value, push, 0) {
+            let user_id = fundings.register(&req);
+            match channel_manager.create_channel(pubkey, req.value_sat, req.push_msat, user_id) {
                 Ok(_) => { 
-                    info!("Channel created, {} sending open_channel ...", pubkey_str); 
+                    info!("Channel created, {} sending open_channel ...", &req.node_id); 
                     let _ = event_notify.try_send(());
-                    Ok(String::from(pubkey_str))
+                    Ok(req.node_id)
                 }
                 Err(e) => { 
+                    fundings.take_request(user_id);
                     let err_str = format!("Failed to open channel: {:?}!", e);
                     debug!("{}", &err_str);
                     let _ = event_notify.try_send(());
@@ -47,35 +58,113 @@ pub fn fund_channel (
     }
 }
 
+// The funding transaction of a signed PSBT and the outpoint of its funding output
+async fn finalize_psbt(psbt: &str, rpc_client: &RPCClient, funding: &PsbtFunding) -> Result<(OutPoint, Transaction), String> {
+    let psbt = format!("\"{}\"", psbt);
+    let finalized = rpc_client
+        .make_rpc_call("finalizepsbt", &[&psbt[..]], false)
+        .await
+        .map_err(|_| "bitcoind could not finalize the PSBT".to_string())?;
+    if finalized["complete"].as_bool() != Some(true) {
+        return Err("The PSBT is not fully signed".to_string());
+    }
+    let tx: Transaction = finalized["hex"]
+        .as_str()
+        .and_then(hex_to_vec)
+        .and_then(|tx| encode::deserialize(&tx).ok())
+        .ok_or_else(|| "bitcoind returned an invalid transaction".to_string())?;
+
+    if tx.input.is_empty() {
+        return Err("The PSBT has no inputs".to_string());
+    }
+    // A malleated funding txid would leave the channel unspendable
+    if tx.input.iter().any(|input| input.witness.is_empty()) {
+        return Err("Every input of a funding transaction has to be segwit".to_string());
+    }
+    let index = tx
+        .output
+        .iter()
+        .position(|o| o.script_pubkey == funding.output_script && o.value == funding.value_sat)
+        .ok_or_else(|| "The PSBT doesn't pay the funding output".to_string())?;
+    Ok((OutPoint { txid: tx.txid(), index: index as u16 }, tx))
+}
+
+// Funds a channel opened with `FundingMode::Psbt` from the PSBT the client signed, returns the
+// funding txid
+pub async fn fund_psbt(
+    req: ChannelFundRequest,
+    rpc_client: &RPCClient,
+    channel_manager: &Arc<ChannelManager>,
+    fundings: &Fundings,
+    mut event_notify: mpsc::Sender<()>,
+) -> Result<String, String> {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
+    let mut temporary_channel_id = [0; 32];
+    temporary_channel_id.copy_from_slice(&hex_to_vec(&req.temporary_channel_id).unwrap());
+    // Taken so a concurrent request for the same channel can't fund it twice
+    let funding = fundings
+        .take_psbt_funding(&temporary_channel_id)
+        .ok_or_else(|| format!("No channel waits for a PSBT with id {}", req.temporary_channel_id))?;
+    let (outpoint, tx) = match finalize_psbt(&req.psbt, rpc_client, &funding).await {
+        Ok(funded) => funded,
+        Err(e) => {
+            // The client may come back with a better PSBT
+            fundings.await_psbt(temporary_channel_id, funding);
+            return Err(e);
+        }
+    };
+
+    fundings.funding_generated(&temporary_channel_id, outpoint, tx, || {
+        channel_manager.funding_transaction_generated(&temporary_channel_id, outpoint)
+    });
+    let _ = event_notify.try_send(());
+    info!("Channel {} funded by {}", &req.temporary_channel_id, outpoint.txid);
+    Ok(outpoint.txid.to_string())
+}
+
+// The channels opened with `FundingMode::Psbt` that still wait for `fund_psbt`
+pub fn psbt_list(fundings: &Fundings) -> Vec<String> {
+    fundings.pending_psbts().into_iter().map(|(temporary_channel_id, funding)| {
+        json!({
+            "temporary_channel_id": hex_str(&temporary_channel_id),
+            "address": funding.address,
+            "value_sat": funding.value_sat,
+            "psbt": funding.psbt
+        }).to_string()
+    }).collect()
+}
+
 // Close single channel
 pub fn close(
-    ch_id: String,
+    req: ChannelCloseRequest,
     channel_manager: &Arc<ChannelManager>,
     mut event_notify: mpsc::Sender<()>,
 ) -> Result<String, String> {
-    if ch_id.len() == 64 {
-        if let Some(chan_id_vec) = hex_to_vec(&ch_id) {
-            let mut channel_id = [0; 32];
-            channel_id.copy_from_slice(&chan_id_vec);
-            debug!("called close");
-            match channel_manager.close_channel(&channel_id) {
-                Ok(()) => {
-                    let _ = event_notify.try_send(());
-                    info!("Channel closing: {}", &ch_id);
-                    Ok(ch_id.to_string())
-                }
-                Err(e) => { 
-                    debug!("Failed to close channel: {:?}", e);
-                    Err(format!("Channel Close Failure: {:?}", e).to_string())
-                }
+    if let Err(e) = req.validate() {
+        debug!("{}", e);
+        return Err(e.to_string());
+    }
+    let ch_id = req.channel_id;
+    if let Some(chan_id_vec) = hex_to_vec(&ch_id) {
+        let mut channel_id = [0; 32];
+        channel_id.copy_from_slice(&chan_id_vec);
+        debug!("called close");
+        match channel_manager.close_channel(&channel_id) {
+            Ok(()) => {
+                let _ = event_notify.try_send(());
+                info!("Channel closing: {}", &ch_id);
+                Ok(ch_id)
+            }
+            Err(e) => { 
+                debug!("Failed to close channel: {:?}", e);
+                Err(format!("Channel Close Failure: {:?}", e).to_string())
             }
-        } else {
-            debug!("Invalid channel_id ...");
-            Err(format!("Invalid channel_id"))
         }
     } else {
-        debug!("Channel id has invalid length ...");
-        Err(format!("Channel id has invalid length ..."))
+        debug!("Invalid channel_id ...");
+        Err(format!("Invalid channel_id"))
     }
 }
 
@@ -85,10 +174,10 @@ pub fn force_close_all(channel_manager: &Arc<ChannelManager>) {
 }
 
 // List existing channels
-pub fn channel_list( channel_manager: &Arc<ChannelManager>, mode: &str) -> Vec<String> {
+pub fn channel_list( channel_manager: &Arc<ChannelManager>, mode: ChannelListMode) -> Vec<String> {
     let channels = match mode {
-        "live" => { channel_manager.list_usable_channels() }
-        "all" | _ => { channel_manager.list_channels() }
+        ChannelListMode::Live => { channel_manager.list_usable_channels() }
+        ChannelListMode::All => { channel_manager.list_channels() }
     };
     channels.into_iter().map(|channel| {
         let (id, confirmed) = match channel.short_channel_id {
@@ -1,11 +1,11 @@
-use bitcoin::network::constants::Network;
 use bitcoin_hashes::Hash;
 use futures::channel::mpsc;
 use lightning::chain::keysinterface::{KeysInterface, KeysManager};
 use lightning::ln::channelmanager::{ChannelManager, PaymentHash, PaymentPreimage};
 use lightning::ln::router;
-use lightning_invoice::Invoice;
+use lightning_invoice::{Invoice, RawInvoice};
 use lightning_invoice::MinFinalCltvExpiry;
+use secp256k1::key::PublicKey;
 use secp256k1::{All, Secp256k1};
 use rand::{thread_rng, Rng};
 use std;
@@ -13,72 +13,92 @@ use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 
+use protocol::{InvoiceCreateRequest, InvoicePayRequest};
+use crate::ln_bridge::signet;
 use crate::ln_bridge::utils::{hex_str, slice_to_be64};
-use crate::utils::{to_network, to_currency};
+use crate::utils::{to_chain, to_currency, Chain};
 
 pub trait InvoiceC {
-    fn pay(&self, args: Vec<String>) -> Result<(), String>;
-    fn create_invoice(&self, line: String) -> Result<String, String>;
+    fn pay(&self, req: InvoicePayRequest) -> Result<(), String>;
+    fn create_invoice(&self, req: InvoiceCreateRequest) -> Result<String, String>;
+}
+
+// The fields of an invoice, the chain it is for and its payee, whose signature it carries
+fn parse_invoice(invoice: &str, secp_ctx: &Secp256k1<All>) -> Result<(RawInvoice, Chain, PublicKey), String> {
+    if invoice.to_lowercase().starts_with(signet::INVOICE_PREFIX) {
+        let (raw_invoice, payee) = signet::parse_invoice(invoice, secp_ctx)?;
+        return Ok((raw_invoice, Chain::Signet, payee));
+    }
+    match Invoice::from_str(invoice) {
+        Ok(invoice) => {
+            let chain = to_chain(invoice.currency());
+            let payee = invoice.recover_payee_pub_key();
+            let (raw_invoice, _, _) = invoice.into_signed_raw().into_parts();
+            Ok((raw_invoice, chain, payee))
+        }
+        Err(err) => Err(format!("{:?}", err)),
+    }
 }
 
 pub fn pay(
-    args: Vec<String>,
+    req: InvoicePayRequest,
     channel_manager: &Arc<ChannelManager>,
     mut event_notify: mpsc::Sender<()>,
-    network: &Network,
+    chain: Chain,
+    secp_ctx: &Secp256k1<All>,
     router: &Arc<router::Router>,
 ) -> Result<(), String> {
     macro_rules! fail_return {
-        () => {
-            println!(">");
-            return Ok(());
+        ($msg: expr) => {
+            warn!("{}", $msg);
+            return Err($msg.to_string());
         };
     }
-    let invoice_str = &args[0];
-    match Invoice::from_str(invoice_str) {
-        Ok(invoice) => {
-            // Raw Invoice Generated Here
-            let raw_invoice = invoice.clone().into_signed_raw();
-            let invoice_network = to_network(invoice.currency());
-            if invoice_network != *network {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
+    match parse_invoice(&req.invoice, secp_ctx) {
+        Ok((raw_invoice, invoice_chain, payee)) => {
+            if invoice_chain != chain {
                 Err("Wrong network on invoice".to_string())
             } else {
-                let amt = if let Some(amt) = invoice.amount_pico_btc().and_then(|amt| {
+                let amt = if let Some(amt) = raw_invoice.amount_pico_btc().and_then(|amt| {
                     if amt % 10 != 0 {
                         None
                     } else {
                         Some(amt / 10)
                     }
                 }) {
-                    if args.len() == 2 {
+                    if req.amount_msat.is_some() {
                         warn!("Invoice had amount, you shouldn't specify one");
                     }
                     amt
                 } else {
-                    if args.len() == 1 {
-                        warn!("Invoice didn't have an amount, you should specify one");
-                        fail_return!();
-                    }
-                    match args[1].parse() {
-                        Ok(amt) => amt,
-                        Err(_) => {
-                            warn!("Provided amount was garbage");
-                            fail_return!();
+                    match req.amount_msat {
+                        Some(amt) => amt,
+                        None => {
+                            fail_return!("Invoice didn't have an amount, you should specify one");
                         }
                     }
                 };
 
-                if let Some(pubkey) = invoice.payee_pub_key() {
-                    if *pubkey != invoice.recover_payee_pub_key() {
-                        warn!(
+                if let Some(pubkey) = raw_invoice.payee_pub_key() {
+                    if pubkey.0 != payee {
+                        fail_return!(
                             "Invoice had non-equal duplicative target node_id (ie was malformed)"
                         );
-                        fail_return!();
                     }
                 }
 
-                let mut route_hint = Vec::with_capacity(invoice.routes().len());
-                for route in invoice.routes() {
+                let payment_hash = match raw_invoice.payment_hash() {
+                    Some(payment_hash) => PaymentHash(payment_hash.0.into_inner()),
+                    None => {
+                        fail_return!("Invoice had no payment hash");
+                    }
+                };
+
+                let mut route_hint = Vec::with_capacity(raw_invoice.routes().len());
+                for route in raw_invoice.routes() {
                     if route.len() != 1 {
                         debug!("Invoice contained multi-hop non-public route, ignoring as yet unsupported");
                     } else {
@@ -92,32 +112,27 @@ pub fn pay(
                         });
                     }
                 }
-                let final_cltv = if invoice.min_final_cltv_expiry().is_none() {
+                let final_cltv = if raw_invoice.min_final_cltv_expiry().is_none() {
                     &MinFinalCltvExpiry(9)
                 } else {
                     raw_invoice.min_final_cltv_expiry().unwrap()
                 };
                 if final_cltv.0 > std::u32::MAX as u64 {
-                    debug!("Invoice had garbage final cltv");
-                    fail_return!();
+                    fail_return!("Invoice had garbage final cltv");
                 }
 
-                info!("invoice route length: {}", invoice.routes().len());
+                info!("invoice route length: {}", raw_invoice.routes().len());
                 let usable_channels_len = &channel_manager.list_usable_channels().len();
                 info!("usable channel length: {}", usable_channels_len);
 
                 match router.get_route(
-                    &invoice.recover_payee_pub_key(),
+                    &payee,
                     Some(&channel_manager.list_usable_channels()),
                     &route_hint,
                     amt,
                     final_cltv.0 as u32,
                 ) {
                     Ok(route) => {
-                        let mut payment_hash = PaymentHash([0; 32]);
-                        payment_hash
-                            .0
-                            .copy_from_slice(&invoice.payment_hash().into_inner()[..]);
                         match channel_manager.send_payment(route, payment_hash) {
                             Ok(()) => {
                                 info!("Sending {} msat", amt);
@@ -139,19 +154,22 @@ pub fn pay(
             }
         }
         Err(err) => {
-            debug!("Bad invoice {:?}", err);
+            debug!("Bad invoice {}", err);
             Err("Bad Invoice".to_string())
         }
     }
 }
 
 pub fn create_invoice(
-    value: String,
+    req: InvoiceCreateRequest,
     payment_preimages: &Arc<Mutex<HashMap<PaymentHash, PaymentPreimage>>>,
-    network: &Network,
+    chain: Chain,
     secp_ctx: &Secp256k1<All>,
     keys: &Arc<KeysManager>,
 ) -> Result<String, String> {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
     let mut payment_preimage = [0; 32];
     thread_rng().fill_bytes(&mut payment_preimage);
     let payment_hash = bitcoin_hashes::sha256::Hash::hash(&payment_preimage);
@@ -167,20 +185,26 @@ pub fn create_invoice(
 
     debug!("payment_hash: {}", hex_str(&payment_hash.into_inner()));
 
-    let currency = to_currency(*network);
+    let currency = to_currency(chain);
 
-    let invoice_res = lightning_invoice::InvoiceBuilder::new(currency)
+    let builder = lightning_invoice::InvoiceBuilder::new(currency)
         .payment_hash(payment_hash)
         .description("rust-lightning-bitcoinrpc invoice".to_string())
         //TODO: Restore routing
         //.route(chans)
-        .amount_pico_btc(value.parse::<u64>().unwrap())
-        .current_timestamp()
-        .build_signed(|msg_hash| {
-            secp_ctx.sign_recoverable(msg_hash, &keys.get_node_secret())
-        });
+        .amount_pico_btc(req.amount_pico_btc)
+        .current_timestamp();
+    let sign = |msg_hash: &secp256k1::Message| {
+        secp_ctx.sign_recoverable(msg_hash, &keys.get_node_secret())
+    };
 
-    match invoice_res {
+    if chain == Chain::Signet {
+        return match builder.build_raw() {
+            Ok(raw_invoice) => signet::sign_invoice(raw_invoice, sign),
+            Err(e) => Err(format!("Error, {:#?}", e).to_string()),
+        };
+    }
+    match builder.build_signed(sign) {
         Ok(invoice) => {
             Ok(invoice.to_string())
         }
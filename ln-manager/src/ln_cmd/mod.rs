@@ -1,36 +1,38 @@
 pub mod channel;
 pub mod invoice;
 pub mod peer;
+pub mod tx;
+pub mod wallet;
 
 #[macro_export]
 macro_rules! impl_command {
     ($item:tt) => (
         use ln_cmd::{channel, invoice, peer};
         impl<T: Larva> channel::ChannelC for $item<T> {
-            fn fund_channel(&self, args: Vec<String>) -> Result<String, String> {
-                channel::fund_channel(args, &self.channel_manager, self.event_notify.clone())
+            fn fund_channel(&self, req: protocol::ChannelCreateRequest) -> Result<String, String> {
+                channel::fund_channel(req, &self.channel_manager, &self.fundings, self.max_channel_sat, self.event_notify.clone())
             }
-            fn close(&self, line: String) -> Result<String, String> {
-                channel::close(line, &self.channel_manager, self.event_notify.clone())
+            fn close(&self, req: protocol::ChannelCloseRequest) -> Result<String, String> {
+                channel::close(req, &self.channel_manager, self.event_notify.clone())
             }
             fn force_close_all(&self) {
                 channel::force_close_all(&self.channel_manager)
             }
-            fn channel_list(&self, mode: &str) -> Vec<String> {
+            fn channel_list(&self, mode: protocol::ChannelListMode) -> Vec<String> {
                 channel::channel_list(&self.channel_manager, mode)
             }
         }
         impl<T: Larva> invoice::InvoiceC for $item<T> {
-            fn pay(&self, args: Vec<String>) -> Result<(), String> {
-                invoice::pay(args, &self.channel_manager, self.event_notify.clone(), &self.network, &self.router)
+            fn pay(&self, req: protocol::InvoicePayRequest) -> Result<(), String> {
+                invoice::pay(req, &self.channel_manager, self.event_notify.clone(), self.chain, &self.secp_ctx, &self.router)
             }
-            fn create_invoice(&self, line: String) -> Result<String, String> {
-                invoice::create_invoice(line, &self.payment_preimages, &self.network, &self.secp_ctx, &self.keys)
+            fn create_invoice(&self, req: protocol::InvoiceCreateRequest) -> Result<String, String> {
+                invoice::create_invoice(req, &self.payment_preimages, self.chain, &self.secp_ctx, &self.keys)
             }
         }
         impl<T: Larva> peer::PeerC for $item<T> {
-            fn connect(&self, node: String) {
-                peer::connect(node, &self.peer_manager, self.event_notify.clone(), self.larva.clone())
+            fn connect(&self, req: protocol::PeerConnectRequest) -> Result<(), String> {
+                peer::connect(req, &self.peer_manager, self.event_notify.clone(), self.larva.clone())
             }
             fn list(&self) -> Vec<String> {
                 peer::list(&self.peer_manager)
@@ -4,53 +4,89 @@ use crate::ln_bridge::utils::{hex_str, hex_to_compressed_pubkey};
 
 use std::sync::Arc;
 use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
 use futures::channel::mpsc;
+use futures_timer::Delay;
 use crate::executor::Larva;
+use protocol::PeerConnectRequest;
 
 pub trait PeerC {
-    fn connect(&self, node: String);
+    fn connect(&self, req: PeerConnectRequest) -> Result<(), String>;
     fn list(&self) -> Vec<String>;
 }
 
 // connect peer
 pub fn connect<T: Larva>(
-    node: String,
+    req: PeerConnectRequest,
     peer_manager: &Arc<PeerManager<SocketDescriptor<T>>>,
     event_notify: mpsc::Sender<()>,
     larva: T,
-) {
-    info!("peer do connect node: {}", node);
-    // TODO: hard code split offset
-    match hex_to_compressed_pubkey(node.split_at(0).1) {
-        Some(pk) => {
-            if node.as_bytes()[33 * 2] == '@' as u8 {
-                let str_node = node.split_at(33 * 2 + 1).1;
-                let parse_res = str_node.to_socket_addrs().unwrap().next();
-                if let Some(addr) = parse_res {
-                    info!("Attempting to connect to {}...", addr);
-                    Connection::connect_outbound(
-                        peer_manager.clone(),
-                        event_notify,
-                        pk,
-                        addr,
-                        larva
-                    );
-                } else {
-                    info!("Couldn't parse host:port into a socket address");
-                    debug!("Couldn't parse host:port into a socket address");
-                }
-            } else {
-                info!("Invalid line, should be c pubkey@host:port");
-                debug!("Invalid line, should be c pubkey@host:port");
-            }
-        },
+) -> Result<(), String> {
+    info!("peer do connect node: {}", req);
+    if let Err(e) = req.validate() {
+        debug!("{}", e);
+        return Err(e.to_string());
+    }
+    let pk = match hex_to_compressed_pubkey(&req.node_id) {
+        Some(pk) => pk,
         None => {
-            info!("Bad PubKey for remote node");
             debug!("Bad PubKey for remote node");
-        },
+            return Err("Bad PubKey for remote node".to_string());
+        }
+    };
+    match req.host.to_socket_addrs().ok().and_then(|mut addrs| addrs.next()) {
+        Some(addr) => {
+            info!("Attempting to connect to {}...", addr);
+            Connection::connect_outbound(
+                peer_manager.clone(),
+                event_notify,
+                pk,
+                addr,
+                larva
+            );
+            Ok(())
+        }
+        None => {
+            debug!("Couldn't parse host:port into a socket address");
+            Err(format!("Couldn't resolve {} into a socket address", req.host))
+        }
     }
 }
 
+// How long a channel request waits for the handshake with a peer it had to connect to first,
+// covers the 10s connect timeout of `Connection::connect_outbound`
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+const HANDSHAKE_POLL: Duration = Duration::from_millis(100);
+
+// Connects to the peer named in a channel request unless we are connected already, and waits
+// for the handshake: an open_channel sent before it would be dropped.
+pub async fn ensure_connected<T: Larva>(
+    node_id: &str,
+    host: Option<&String>,
+    peer_manager: &Arc<PeerManager<SocketDescriptor<T>>>,
+    event_notify: mpsc::Sender<()>,
+    larva: T,
+) -> Result<(), String> {
+    let connected = || list(peer_manager).iter().any(|peer| peer == node_id);
+    let host = match host {
+        Some(host) if !connected() => host,
+        _ => return Ok(()),
+    };
+    connect(
+        PeerConnectRequest { node_id: node_id.to_string(), host: host.clone() },
+        peer_manager,
+        event_notify,
+        larva,
+    )?;
+    let started = Instant::now();
+    while !connected() {
+        if started.elapsed() > HANDSHAKE_TIMEOUT {
+            return Err(format!("Could not connect to {} at {}", node_id, host));
+        }
+        let _ = Delay::new(HANDSHAKE_POLL).await;
+    }
+    Ok(())
+}
 
 pub fn list<T: Larva>(peer_manager: &Arc<PeerManager<SocketDescriptor<T>>>) -> Vec<String>{
     peer_manager
@@ -0,0 +1,119 @@
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::network::constants::Network;
+use bitcoin::util::address::Address;
+use protocol::TxBumpRequest;
+use serde_json::{json, Value};
+
+use super::wallet::{btc_to_sat, call, sat_to_btc};
+use crate::ln_bridge::chain_monitor::ChainBroadcaster;
+use crate::ln_bridge::rpc_client::RPCClient;
+use crate::ln_bridge::sweeper::Sweeper;
+use crate::ln_bridge::utils::hex_to_vec;
+
+// One P2WPKH input and output, the shape of every child we build
+const CHILD_VSIZE: u64 = 110;
+// Below this bitcoind won't relay the child
+const DUST_LIMIT_SATOSHIS: u64 = 546;
+
+/// Raises the feerate of a transaction the node broadcast, returns the txid of the replacement
+/// or of the child paying for it.
+///
+/// Sweeps are signed by us so they get replaced (RBF). Anything else `ChainBroadcaster` sent gets
+/// a child spending one of its outputs back to the bitcoind wallet (CPFP): funding transactions
+/// through their change, closing transactions through the output paying to the imported
+/// cooperative close or claim key. Funding transactions are never replaced, the commitment
+/// transactions both sides signed spend their txid. rust-lightning has no anchor outputs yet.
+pub async fn bump<T>(
+    req: TxBumpRequest,
+    rpc_client: &RPCClient,
+    network: Network,
+    chain_broadcaster: &ChainBroadcaster<T>,
+    sweeper: &Sweeper,
+) -> Result<String, String> {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
+    if let Some(replaced) = sweeper.bump(&req.txid, req.sat_per_vbyte).await {
+        return replaced;
+    }
+    let parent = chain_broadcaster
+        .sent(&req.txid)
+        .ok_or_else(|| format!("{} was not broadcast by this node", req.txid))?;
+
+    // Broadcasts go through the chain source, bitcoind still hears of them from its peers
+    let txid = format!("\"{}\"", req.txid);
+    let entry = rpc_client
+        .make_rpc_call("getmempoolentry", &[&txid[..]], true)
+        .await
+        .unwrap_or(Value::Null);
+    if entry.is_null() {
+        return Err(format!("{} is not in the mempool, it confirmed or was never accepted", req.txid));
+    }
+    let parent_vsize = entry["vsize"].as_u64().unwrap_or(1);
+    let parent_fee = btc_to_sat(&entry["fees"]["base"]);
+    if parent_fee >= req.sat_per_vbyte * parent_vsize {
+        return Err(format!("{} already pays {} sat/vB", req.txid, parent_fee / parent_vsize));
+    }
+    // The child pays for both at the requested feerate
+    let child_fee = req.sat_per_vbyte * (parent_vsize + CHILD_VSIZE) - parent_fee;
+
+    let vout = spendable_output(rpc_client, network, &parent, &txid, child_fee)
+        .await
+        .ok_or_else(|| format!("No unspent output of {} worth over {} sat belongs to the bitcoind wallet", req.txid, child_fee))?;
+    let value = parent.output[vout].value;
+
+    let address = call(rpc_client, "getnewaddress", &["\"rust-lightning cpfp\"", "\"bech32\""]).await?;
+    let inputs = json!([{ "txid": req.txid, "vout": vout }]).to_string();
+    let outputs = format!("{{\"{}\": {}}}", address.as_str().unwrap_or_default(), sat_to_btc(value - child_fee));
+    let child = call(rpc_client, "createrawtransaction", &[&inputs[..], &outputs[..]]).await?;
+    let child = format!("\"{}\"", child.as_str().unwrap_or_default());
+    let signed = call(rpc_client, "signrawtransactionwithwallet", &[&child[..]]).await?;
+    if signed["complete"].as_bool() != Some(true) {
+        return Err("bitcoind could not sign the child transaction".to_string());
+    }
+    let child: Transaction = signed["hex"]
+        .as_str()
+        .and_then(hex_to_vec)
+        .and_then(|tx| encode::deserialize(&tx).ok())
+        .ok_or_else(|| "bitcoind returned an invalid transaction".to_string())?;
+
+    chain_broadcaster.send(&child).await?;
+    info!("Bumped {} to {} sat/vB with child {}", req.txid, req.sat_per_vbyte, child.txid());
+    Ok(child.txid().to_string())
+}
+
+// An unspent output of the parent the bitcoind wallet can sign for, large enough for the fee
+async fn spendable_output(
+    rpc_client: &RPCClient,
+    network: Network,
+    parent: &Transaction,
+    txid: &str,
+    child_fee: u64,
+) -> Option<usize> {
+    for (vout, output) in parent.output.iter().enumerate() {
+        if output.value < child_fee + DUST_LIMIT_SATOSHIS {
+            continue;
+        }
+        let address = match Address::from_script(&output.script_pubkey, network) {
+            Some(address) => format!("\"{}\"", address),
+            None => continue,
+        };
+        let info = rpc_client
+            .make_rpc_call("getaddressinfo", &[&address[..]], true)
+            .await
+            .unwrap_or(Value::Null);
+        if info["ismine"].as_bool() != Some(true) {
+            continue;
+        }
+        let vout_param = vout.to_string();
+        let unspent = rpc_client
+            .make_rpc_call("gettxout", &[txid, &vout_param[..], "true"], true)
+            .await
+            .unwrap_or(Value::Null);
+        if !unspent.is_null() {
+            return Some(vout);
+        }
+    }
+    None
+}
@@ -0,0 +1,96 @@
+use std::cmp;
+use std::sync::Arc;
+
+use lightning::ln::channelmanager::{ChannelDetails, ChannelManager};
+use protocol::{WalletBalance, WalletSendRequest};
+use serde_json::{json, Value};
+
+use crate::ln_bridge::rpc_client::RPCClient;
+use crate::ln_bridge::sweeper::Sweeper;
+
+// bitcoind speaks BTC as floats
+pub(crate) fn btc_to_sat(btc: &Value) -> u64 {
+    (btc.as_f64().unwrap_or(0.0) * 100_000_000.0).round() as u64
+}
+
+pub(crate) fn sat_to_btc(sat: u64) -> String {
+    format!("{}.{:08}", sat / 100_000_000, sat % 100_000_000)
+}
+
+// Errors of the call come back as null, see `RPCClient::make_rpc_call`
+pub(crate) async fn call(rpc_client: &RPCClient, method: &str, params: &[&str]) -> Result<Value, String> {
+    match rpc_client.make_rpc_call(method, params, false).await {
+        Ok(Value::Null) | Err(_) => Err(format!("bitcoind refused {}", method)),
+        Ok(v) => Ok(v),
+    }
+}
+
+// Our side of a channel: what we can send plus the reserve the peer makes us keep, which
+// rust-lightning sets at 1% of the channel value and 1000 sat at least. Never more than what
+// the peer can't send us.
+fn our_balance_sat(channel: &ChannelDetails) -> u64 {
+    let reserve = cmp::max(channel.channel_value_satoshis / 100, 1000);
+    let theirs = channel.inbound_capacity_msat / 1000;
+    cmp::min(
+        channel.outbound_capacity_msat / 1000 + reserve,
+        channel.channel_value_satoshis.saturating_sub(theirs),
+    )
+}
+
+// Wallet funds from bitcoind plus what is in channels or waiting to be swept
+pub async fn balance(
+    rpc_client: &RPCClient,
+    channel_manager: &Arc<ChannelManager>,
+    sweeper: &Sweeper,
+) -> Result<WalletBalance, String> {
+    let confirmed = call(rpc_client, "getbalance", &[]).await?;
+    let unconfirmed = call(rpc_client, "getunconfirmedbalance", &[]).await?;
+    Ok(WalletBalance {
+        confirmed_sat: btc_to_sat(&confirmed),
+        unconfirmed_sat: btc_to_sat(&unconfirmed),
+        channels_sat: channel_manager.list_channels().iter().map(our_balance_sat).sum(),
+        claimable_sat: sweeper.claimable_sat(),
+    })
+}
+
+pub async fn new_address(rpc_client: &RPCClient) -> Result<String, String> {
+    let address = call(rpc_client, "getnewaddress", &["\"\"", "\"bech32\""]).await?;
+    address
+        .as_str()
+        .map(|a| a.to_string())
+        .ok_or_else(|| "bitcoind returned no address".to_string())
+}
+
+// Sends from the bitcoind wallet, returns the txid
+pub async fn send(rpc_client: &RPCClient, req: WalletSendRequest) -> Result<String, String> {
+    if let Err(e) = req.validate() {
+        return Err(e.to_string());
+    }
+    let address = format!("\"{}\"", req.address);
+    let amount = sat_to_btc(req.amount_sat);
+    let txid = call(rpc_client, "sendtoaddress", &[&address[..], &amount[..]]).await?;
+    info!("Sent {} sat to {}", req.amount_sat, req.address);
+    txid.as_str()
+        .map(|t| t.to_string())
+        .ok_or_else(|| "bitcoind returned no txid".to_string())
+}
+
+pub async fn list_unspent(rpc_client: &RPCClient) -> Result<Vec<String>, String> {
+    let utxos = call(rpc_client, "listunspent", &[]).await?;
+    Ok(utxos
+        .as_array()
+        .map(|utxos| utxos.as_slice())
+        .unwrap_or(&[])
+        .iter()
+        .map(|u| {
+            json!({
+                "txid": u["txid"],
+                "vout": u["vout"],
+                "address": u["address"],
+                "amount_sats": btc_to_sat(&u["amount"]),
+                "confirmations": u["confirmations"],
+            })
+            .to_string()
+        })
+        .collect())
+}
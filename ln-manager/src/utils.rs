@@ -1,30 +1,80 @@
+use std::fmt;
+
+use bitcoin::blockdata::constants::genesis_block;
 use bitcoin::network::constants::Network;
+use bitcoin::util::hash::BitcoinHash;
+use bitcoin_hashes::hex::FromHex;
+use bitcoin_hashes::sha256d::Hash as Sha256dHash;
 use lightning_invoice::Currency;
 
-// convert currency to network
-#[allow(dead_code)]
-pub fn to_network(currency: Currency) -> Network {
+const SIGNET_GENESIS_HASH: &str = "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6";
+
+/// The chain bitcoind is on. bitcoin 0.20 and lightning-invoice have no signet: it shares
+/// testnet's keys, address prefixes and `tb` bech32 HRP, so keys and addresses use
+/// `Network::Testnet`, its genesis block and `lntbs` invoice prefix are handled in
+/// `ln_bridge::signet`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Chain {
+    Bitcoin,
+    Testnet,
+    Signet,
+    Regtest,
+}
+
+impl Chain {
+    /// The hash rust-lightning puts in `open_channel` and gossip as `chain_hash`.
+    pub fn genesis_hash(self) -> Sha256dHash {
+        match self {
+            Chain::Signet => Sha256dHash::from_hex(SIGNET_GENESIS_HASH).unwrap(),
+            chain => genesis_block(to_network(chain)).bitcoin_hash(),
+        }
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chain::Signet => write!(f, "signet"),
+            chain => write!(f, "{}", to_network(*chain)),
+        }
+    }
+}
+
+// convert chain to the network of keys and addresses
+pub fn to_network(chain: Chain) -> Network {
+    match chain {
+        Chain::Bitcoin => Network::Bitcoin,
+        Chain::Testnet => Network::Testnet,
+        Chain::Signet => Network::Testnet,
+        Chain::Regtest => Network::Regtest,
+    }
+}
+
+// convert currency to chain, lntbs invoices are parsed by ln_bridge::signet and never get here
+pub fn to_chain(currency: Currency) -> Chain {
     match currency {
-        Currency::Bitcoin => Network::Bitcoin,
-        Currency::BitcoinTestnet => Network::Testnet,
-        Currency::Regtest => Network::Regtest,
+        Currency::Bitcoin => Chain::Bitcoin,
+        Currency::BitcoinTestnet => Chain::Testnet,
+        Currency::Regtest => Chain::Regtest,
     }
 }
 
-#[allow(dead_code)]
-pub fn to_currency(network: Network) -> Currency {
-    match network {
-        Network::Bitcoin => Currency::Bitcoin,
-        Network::Testnet => Currency::BitcoinTestnet,
-        Network::Regtest => Currency::Regtest,
+// convert chain to currency, signet invoices are built as testnet ones and signed over lntbs
+pub fn to_currency(chain: Chain) -> Currency {
+    match chain {
+        Chain::Bitcoin => Currency::Bitcoin,
+        Chain::Testnet => Currency::BitcoinTestnet,
+        Chain::Signet => Currency::BitcoinTestnet,
+        Chain::Regtest => Currency::Regtest,
     }
 }
 
-#[allow(dead_code)]
-pub fn compact_btc_to_bech32(btc_network: Network) -> bitcoin_bech32::constants::Network {
-    match btc_network {
-        Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
-        Network::Testnet => bitcoin_bech32::constants::Network::Testnet,
-        Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
+pub fn compact_btc_to_bech32(chain: Chain) -> bitcoin_bech32::constants::Network {
+    match chain {
+        Chain::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
+        Chain::Testnet => bitcoin_bech32::constants::Network::Testnet,
+        // same tb HRP
+        Chain::Signet => bitcoin_bech32::constants::Network::Testnet,
+        Chain::Regtest => bitcoin_bech32::constants::Network::Regtest,
     }
 }
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::ParseError;
+use crate::RequestFuncs;
+
+/// What a control token allows, each level includes the ones below it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Permission {
+    /// Node info and listings.
+    ReadOnly,
+    /// Read only plus creating invoices and receive addresses.
+    Invoice,
+    /// Everything that moves funds or touches peers and channels.
+    Admin,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 3] = [Permission::ReadOnly, Permission::Invoice, Permission::Admin];
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Permission::ReadOnly => "readonly",
+            Permission::Invoice => "invoice",
+            Permission::Admin => "admin",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "readonly" => Ok(Permission::ReadOnly),
+            "invoice" => Ok(Permission::Invoice),
+            "admin" => Ok(Permission::Admin),
+            _ => Err(ParseError::invalid("permission", format!("unknown level {}", s))),
+        }
+    }
+}
+
+impl RequestFuncs {
+    /// The permission a token needs for this request, `None` if it can be sent without one.
+    pub fn permission(&self) -> Option<Permission> {
+        match self {
+            RequestFuncs::Hello(_) => None,
+            RequestFuncs::GetAddresses
+            | RequestFuncs::GetNodeInfo
+            | RequestFuncs::PeerList
+            | RequestFuncs::ChannelList(_)
+            | RequestFuncs::ChannelPsbtList
+            | RequestFuncs::Subscribe
+            | RequestFuncs::WalletBalance
+            | RequestFuncs::WalletListUnspent => Some(Permission::ReadOnly),
+            RequestFuncs::InvoiceCreate(_) | RequestFuncs::WalletNewAddress => Some(Permission::Invoice),
+            RequestFuncs::PeerConnect(_)
+            | RequestFuncs::ChannelCreate(_)
+            | RequestFuncs::ChannelClose(_)
+            | RequestFuncs::ChannelCloseAll
+            | RequestFuncs::ChannelFund(_)
+            | RequestFuncs::InvoicePay(_)
+            | RequestFuncs::WalletSend(_)
+            | RequestFuncs::TxBump(_) => Some(Permission::Admin),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::error::Error;
+use std::fmt;
+
+/// Why a command line or request payload was rejected.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    InvalidCommand(String),
+    InvalidSubCommand(String),
+    MissingArgument(&'static str),
+    TooManyArguments,
+    InvalidArgument { name: &'static str, reason: String },
+}
+
+impl ParseError {
+    pub(crate) fn invalid(name: &'static str, reason: impl Into<String>) -> Self {
+        ParseError::InvalidArgument { name, reason: reason.into() }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidCommand(cmd) => write!(f, "Invalid Command: {}", cmd),
+            ParseError::InvalidSubCommand(cmd) => write!(f, "Invalid Argument: {}", cmd),
+            ParseError::MissingArgument(name) => write!(f, "Insufficient Arguments: missing {}", name),
+            ParseError::TooManyArguments => write!(f, "Too Many Arguments"),
+            ParseError::InvalidArgument { name, reason } => write!(f, "Invalid {}: {}", name, reason),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Why a frame received from the other side couldn't be decoded.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProtocolError {
+    /// No protocol header, the peer predates versioned framing.
+    NotVersioned,
+    VersionMismatch { ours: u16, min: u16, theirs: u16 },
+    Decode(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtocolError::NotVersioned => write!(f, "Missing protocol header, peer predates versioned framing"),
+            ProtocolError::VersionMismatch { ours, min, theirs } => write!(
+                f,
+                "Protocol version mismatch: peer speaks v{}, we support v{} to v{}",
+                theirs, min, ours
+            ),
+            ProtocolError::Decode(e) => write!(f, "Could not decode message: {}", e),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Something the node did on its own, streamed to clients after `RequestFuncs::Subscribe`.
+/// Hashes, preimages and txids are hex encoded.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub enum NodeEvent {
+    PaymentReceived { payment_hash: String, amount_msat: u64 },
+    PaymentSent { payment_preimage: String },
+    PaymentFailed { payment_hash: String, rejected_by_dest: bool },
+    FundingBroadcast { txid: String },
+    /// An on-chain output we can spend, `descriptor` is one of `static`, `p2wsh` or `p2wpkh`.
+    SpendableOutput { txid: String, vout: u32, descriptor: String },
+    /// A `FundingMode::Psbt` channel is ready to be funded. `psbt` (base64) pays `value_sat` to
+    /// `address` but has no inputs: the client adds its own inputs and change, or builds a PSBT
+    /// paying the same output with its wallet (`walletcreatefundedpsbt`), and hands it back
+    /// signed in a `ChannelFundRequest`.
+    FundingPsbt { temporary_channel_id: String, address: String, value_sat: u64, psbt: String },
+    /// bitcoind refused a transaction of ours or a conflicting spend confirmed, `error` says which.
+    BroadcastFailed { txid: String, error: String },
+}
+
+impl NodeEvent {
+    /// Short name of the event, as used in the json form.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NodeEvent::PaymentReceived { .. } => "payment_received",
+            NodeEvent::PaymentSent { .. } => "payment_sent",
+            NodeEvent::PaymentFailed { .. } => "payment_failed",
+            NodeEvent::FundingBroadcast { .. } => "funding_broadcast",
+            NodeEvent::SpendableOutput { .. } => "spendable_output",
+            NodeEvent::FundingPsbt { .. } => "funding_psbt",
+            NodeEvent::BroadcastFailed { .. } => "broadcast_failed",
+        }
+    }
+}
+
+impl fmt::Display for NodeEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeEvent::PaymentReceived { payment_hash, amount_msat } => {
+                write!(f, "Payment received: {} msat, hash {}", amount_msat, payment_hash)
+            }
+            NodeEvent::PaymentSent { payment_preimage } => {
+                write!(f, "Payment sent, proof {}", payment_preimage)
+            }
+            NodeEvent::PaymentFailed { payment_hash, rejected_by_dest } => write!(
+                f,
+                "Payment failed ({}), hash {}",
+                if *rejected_by_dest { "rejected by destination" } else { "no route" },
+                payment_hash
+            ),
+            NodeEvent::FundingBroadcast { txid } => write!(f, "Funding transaction broadcast: {}", txid),
+            NodeEvent::SpendableOutput { txid, vout, descriptor } => {
+                write!(f, "Spendable {} output at {}:{}", descriptor, txid, vout)
+            }
+            NodeEvent::FundingPsbt { temporary_channel_id, address, value_sat, psbt } => write!(
+                f,
+                "Channel {} waits for {} sat to {}, add inputs, sign and submit: {}",
+                temporary_channel_id, value_sat, address, psbt
+            ),
+            NodeEvent::BroadcastFailed { txid, error } => write!(f, "Broadcast of {} failed: {}", txid, error),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+use crate::error::ProtocolError;
+use crate::Message;
+
+/// Version of the frame layout and of every type carried in it. Bump it whenever an existing
+/// variant or struct changes shape, new enum variants may only be appended.
+pub const PROTOCOL_VERSION: u16 = 6;
+/// Oldest version we can still decode.
+pub const MIN_PROTOCOL_VERSION: u16 = 6;
+
+const MAGIC: [u8; 2] = *b"RB";
+const HEADER_LEN: usize = 4;
+
+/// Largest frame accepted on the stream transports, same as the default of tokio's
+/// `LengthDelimitedCodec` used by the server.
+pub const MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// A message tagged with a request id. Responses carry the id of the request they answer so a
+/// client can match them up on a stream shared by several requests. Id 0 is reserved for errors
+/// that can't be tied to a request, like an undecodable frame.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Frame {
+    pub id: u64,
+    /// Control token of the client, see `Permission`. Unset on responses.
+    pub token: Option<String>,
+    pub message: Message,
+}
+
+impl Frame {
+    pub fn new(id: u64, message: Message) -> Self {
+        Frame { id, token: None, message }
+    }
+
+    pub fn with_token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+/// Encodes a frame behind the `RB` magic and our protocol version.
+pub fn serialize_frame(frame: &Frame) -> Vec<u8> {
+    let mut v = Vec::with_capacity(64);
+    v.extend_from_slice(&MAGIC);
+    v.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    bincode::serialize_into(&mut v, frame).expect("Could not serialize frame");
+    v
+}
+
+/// The protocol version a payload was encoded with, `None` if it has no protocol header.
+pub fn frame_version(v: &[u8]) -> Option<u16> {
+    if v.len() < HEADER_LEN || v[..2] != MAGIC {
+        return None;
+    }
+    Some(u16::from_be_bytes([v[2], v[3]]))
+}
+
+pub fn deserialize_frame(v: &[u8]) -> Result<Frame, ProtocolError> {
+    let version = frame_version(v).ok_or(ProtocolError::NotVersioned)?;
+    if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION {
+        return Err(ProtocolError::VersionMismatch {
+            ours: PROTOCOL_VERSION,
+            min: MIN_PROTOCOL_VERSION,
+            theirs: version,
+        });
+    }
+    bincode::deserialize(&v[HEADER_LEN..]).map_err(|e| ProtocolError::Decode(e.to_string()))
+}
+
+/// Writes a frame prefixed with its length as a 4 byte big endian integer.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &Frame) -> io::Result<()> {
+    let payload = serialize_frame(frame);
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too large"));
+    }
+    writer.write_all(&(payload.len() as u32).to_be_bytes())?;
+    writer.write_all(&payload)?;
+    writer.flush()
+}
+
+/// Reads one length prefixed frame, blocking until it has been received completely.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Frame> {
+    let mut len_buf = [0u8; 4];
+    reader.read_exact(&mut len_buf)?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "frame too large"));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    deserialize_frame(&payload).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+use crate::frame::PROTOCOL_VERSION;
+
+/// Sent by a client before its first request to find out what the server understands.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ClientHello {
+    pub version: u16,
+    pub client: String,
+}
+
+impl ClientHello {
+    pub fn new(client: &str) -> Self {
+        ClientHello { version: PROTOCOL_VERSION, client: client.to_string() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ServerHello {
+    pub version: u16,
+    pub min_version: u16,
+    pub server: String,
+    /// Request groups the server handles, see `RequestFuncs::capability`.
+    pub capabilities: Vec<String>,
+}
+
+impl ServerHello {
+    /// Whether a client speaking `version` can talk to this server.
+    pub fn accepts(&self, version: u16) -> bool {
+        version >= self.min_version && version <= self.version
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
@@ -1,24 +1,80 @@
-use bincode;
 use std::str::FromStr;
 use serde::{Deserialize, Serialize};
 
+mod auth;
+mod error;
+mod events;
+mod frame;
+mod hello;
+mod requests;
+mod wallet;
+pub use auth::Permission;
+pub use error::{ParseError, ProtocolError};
+pub use events::NodeEvent;
+pub use frame::{
+    deserialize_frame, frame_version, read_frame, serialize_frame, write_frame, Frame,
+    MAX_FRAME_LEN, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+pub use hello::{ClientHello, ServerHello};
+pub use requests::{
+    ChannelCloseRequest, ChannelCreateRequest, ChannelFundRequest, ChannelListMode, FeeTarget, FundingMode,
+    InvoiceCreateRequest, InvoicePayRequest, PeerConnectRequest, TxBumpRequest, WalletSendRequest,
+    MAX_FUNDING_SATOSHIS,
+};
+pub use wallet::{ImportMode, KeyImport, WalletBalance};
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum RequestFuncs {
     GetAddresses,
     GetNodeInfo,
-    PeerConnect(String),
-    ChannelCreate(Vec<String>),
-    ChannelClose(String),
+    PeerConnect(PeerConnectRequest),
+    ChannelCreate(ChannelCreateRequest),
+    ChannelClose(ChannelCloseRequest),
     ChannelCloseAll,
-    ChannelList(String),
+    ChannelList(ChannelListMode),
     PeerList,
-    InvoiceCreate(String),
-    InvoicePay(Vec<String>),
+    InvoiceCreate(InvoiceCreateRequest),
+    InvoicePay(InvoicePayRequest),
+    Hello(ClientHello),
+    /// Turns the connection into a stream of `ResponseFuncs::Event`, tagged with this request's id.
+    Subscribe,
+    WalletBalance,
+    WalletNewAddress,
+    WalletSend(WalletSendRequest),
+    WalletListUnspent,
+    ChannelFund(ChannelFundRequest),
+    TxBump(TxBumpRequest),
+    /// The channels opened with `FundingMode::Psbt` still waiting for `ChannelFund`.
+    ChannelPsbtList,
+}
+
+impl RequestFuncs {
+    /// The capability a server has to announce in its `ServerHello` to handle this request.
+    pub fn capability(&self) -> Option<&'static str> {
+        match self {
+            RequestFuncs::GetAddresses | RequestFuncs::GetNodeInfo => Some("info"),
+            RequestFuncs::PeerConnect(_) | RequestFuncs::PeerList => Some("peer"),
+            RequestFuncs::ChannelCreate(_)
+            | RequestFuncs::ChannelClose(_)
+            | RequestFuncs::ChannelCloseAll
+            | RequestFuncs::ChannelList(_)
+            | RequestFuncs::ChannelFund(_)
+            | RequestFuncs::ChannelPsbtList => Some("channel"),
+            RequestFuncs::InvoiceCreate(_) | RequestFuncs::InvoicePay(_) => Some("invoice"),
+            RequestFuncs::Subscribe => Some("events"),
+            RequestFuncs::WalletBalance
+            | RequestFuncs::WalletNewAddress
+            | RequestFuncs::WalletSend(_)
+            | RequestFuncs::WalletListUnspent => Some("wallet"),
+            RequestFuncs::TxBump(_) => Some("tx"),
+            RequestFuncs::Hello(_) => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub enum ResponseFuncs {
-    GetAddresses(Vec<String>),
+    GetAddresses(KeyImport),
     GetNodeInfo(String),
     PeerConnect,
     ChannelCreate(String),
@@ -29,6 +85,23 @@ pub enum ResponseFuncs {
     InvoiceCreate(String),
     InvoicePay,
     Error(String),
+    Hello(ServerHello),
+    /// Acknowledges `RequestFuncs::Subscribe`, events follow.
+    Subscribed,
+    Event(NodeEvent),
+    WalletBalance(WalletBalance),
+    WalletNewAddress(String),
+    /// Txid of the transaction sending the funds.
+    WalletSend(String),
+    /// JSON encoded unspent outputs of the bitcoind wallet.
+    WalletListUnspent(Vec<String>),
+    /// Txid of the funding transaction built from the PSBT.
+    ChannelFund(String),
+    /// Txid of the replacement or of the child paying for the transaction.
+    TxBump(String),
+    /// JSON encoded temporary channel id, funding address, value and unsigned PSBT of every
+    /// channel waiting for its funding.
+    ChannelPsbtList(Vec<String>),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -41,20 +114,29 @@ pub fn serialize_message(msg: Message) -> Vec<u8> {
     bincode::serialize(&msg).expect("Could not serialize message")
 }
 
-pub fn deserialize_message(v: Vec<u8>) -> Message {
-    bincode::deserialize(&v).expect("Could not deserialize message")
-}
-
-pub struct ProtocalParseError {
-    pub msg: String
+/// Decodes a bare message as sent by clients from before versioned framing.
+pub fn deserialize_message(v: &[u8]) -> Result<Message, ProtocolError> {
+    bincode::deserialize(v).map_err(|e| ProtocolError::Decode(e.to_string()))
 }
 
 impl FromStr for RequestFuncs {
-    type Err = ProtocalParseError; 
+    type Err = ParseError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let cmd_value: Vec<&str> = s.split(',').collect();
+        let arg = |idx: usize, name: &'static str| -> Result<&str, ParseError> {
+            match cmd_value.get(idx) {
+                Some(value) if !value.is_empty() => Ok(value),
+                _ => Err(ParseError::MissingArgument(name)),
+            }
+        };
+        let max_args = |len: usize| -> Result<(), ParseError> {
+            if cmd_value.len() > len {
+                return Err(ParseError::TooManyArguments);
+            }
+            Ok(())
+        };
         let cmd = cmd_value[0];
-        let sub_command = cmd_value[1];
+        let sub_command = arg(1, "sub command")?;
         match cmd {
             "info" => {
                  match sub_command {
@@ -65,80 +147,116 @@ impl FromStr for RequestFuncs {
                         Ok(RequestFuncs::GetNodeInfo)
                     }
                     _ => {
-                        Err(ProtocalParseError{ msg: String::from("Invalid Argument") })
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
                     }
                 }
             }
             "peer" => {
                 match sub_command {
                     "connect" => {
-                        let value = cmd_value[2].to_string();
-                        Ok(RequestFuncs::PeerConnect(value))
+                        max_args(3)?;
+                        Ok(RequestFuncs::PeerConnect(arg(2, "peer")?.parse()?))
                     }
                     "list" => {
                         Ok(RequestFuncs::PeerList)
                     }
                     _ => {
-                        Err(ProtocalParseError{ msg: String::from("Invalid Argument") })
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
                     }
                 }
             }
             "channel" => {
                 match sub_command {
                     "create" => {
-                        if cmd_value.len() != 5 {
-                            return Err(ProtocalParseError{ msg: String::from("Insufficient Arguments") });
+                        let mut req = ChannelCreateRequest::new(
+                            arg(2, "node_id")?,
+                            arg(3, "value_sat")?,
+                            arg(4, "push_msat")?,
+                        )?;
+                        for option in cmd_value.iter().skip(5) {
+                            req.set_option(option)?;
                         }
-                        let args: Vec<String> = cmd_value[2..]
-                            .into_iter()
-                            .map(|v| {
-                                v.to_string()
-                            }).collect();
-                        Ok(RequestFuncs::ChannelCreate(args))
+                        req.validate()?;
+                        Ok(RequestFuncs::ChannelCreate(req))
                     }
                     "kill" => {
-                        if cmd_value.len() != 3 {
-                            return Err(ProtocalParseError{ msg: String::from("Insufficient Arguments") });
-                        }
-                        let channel = cmd_value[2].to_string();
-                        Ok(RequestFuncs::ChannelClose(channel))
+                        max_args(3)?;
+                        Ok(RequestFuncs::ChannelClose(arg(2, "channel_id")?.parse()?))
+                    }
+                    "fund" => {
+                        max_args(4)?;
+                        let req = ChannelFundRequest::new(arg(2, "temporary_channel_id")?, arg(3, "psbt")?)?;
+                        Ok(RequestFuncs::ChannelFund(req))
                     }
                     "killall" => {
                         Ok(RequestFuncs::ChannelCloseAll)
                     }
                     "list" => {
-                        if cmd_value.len() != 3 {
-                            return Err(ProtocalParseError{ msg: String::from("Insufficient Arguments") });
-                        }
-                        let mode = cmd_value[2].to_string();
-                        Ok(RequestFuncs::ChannelList(mode))
+                        max_args(3)?;
+                        Ok(RequestFuncs::ChannelList(arg(2, "mode")?.parse()?))
+                    }
+                    "psbts" => {
+                        Ok(RequestFuncs::ChannelPsbtList)
                     }
                     _ => {
-                        Err(ProtocalParseError{ msg: String::from("Invalid Argument") })
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
                     }
                 }
             }
             "invoice" => {
                 match sub_command {
                     "create" =>  {
-                        let value = cmd_value[2].to_string();
-                        Ok(RequestFuncs::InvoiceCreate(value))
+                        max_args(3)?;
+                        Ok(RequestFuncs::InvoiceCreate(arg(2, "amount")?.parse()?))
                     }
                     "pay" => {
-                        let args: Vec<String> = cmd_value[2..]
-                            .into_iter()
-                            .map(|v| {
-                                v.to_string()
-                            }).collect();
-                        Ok(RequestFuncs::InvoicePay(args))
+                        max_args(4)?;
+                        let req = InvoicePayRequest::new(
+                            arg(2, "invoice")?,
+                            cmd_value.get(3).cloned(),
+                        )?;
+                        Ok(RequestFuncs::InvoicePay(req))
                     }
                     _ => {
-                        Err(ProtocalParseError{ msg: String::from("Invalid Argument") })
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
+                    }
+                }
+            }
+            "wallet" => {
+                match sub_command {
+                    "balance" => {
+                        Ok(RequestFuncs::WalletBalance)
+                    }
+                    "newaddress" => {
+                        Ok(RequestFuncs::WalletNewAddress)
+                    }
+                    "send" => {
+                        max_args(4)?;
+                        let req = WalletSendRequest::new(arg(2, "address")?, arg(3, "amount_sat")?)?;
+                        Ok(RequestFuncs::WalletSend(req))
+                    }
+                    "listunspent" => {
+                        Ok(RequestFuncs::WalletListUnspent)
+                    }
+                    _ => {
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
+                    }
+                }
+            }
+            "tx" => {
+                match sub_command {
+                    "bump" => {
+                        max_args(4)?;
+                        let req = TxBumpRequest::new(arg(2, "txid")?, arg(3, "feerate")?)?;
+                        Ok(RequestFuncs::TxBump(req))
+                    }
+                    _ => {
+                        Err(ParseError::InvalidSubCommand(sub_command.to_string()))
                     }
                 }
             }
             _ => {
-                Err(ProtocalParseError{ msg: String::from("Invalid Command") })
+                Err(ParseError::InvalidCommand(cmd.to_string()))
             }
         }
     }
@@ -149,9 +267,175 @@ mod tests {
     use super::*;
     #[test]
     fn it_works() {
-        let a = Message::Request(RequestFuncs::PrintSomething("".to_string()));
+        let a = Message::Request(RequestFuncs::ChannelList(ChannelListMode::All));
         let ser = serialize_message(a.clone());
-        let der = deserialize_message(ser);
-        assert_eq!(a, der);
+        let der = deserialize_message(&ser);
+        assert_eq!(Ok(a), der);
+    }
+
+    #[test]
+    fn permissions() {
+        assert_eq!(RequestFuncs::Hello(ClientHello::new("test")).permission(), None);
+        assert_eq!("channel,list,all".parse::<RequestFuncs>().unwrap().permission(), Some(Permission::ReadOnly));
+        assert_eq!("invoice,create,100".parse::<RequestFuncs>().unwrap().permission(), Some(Permission::Invoice));
+        assert_eq!(RequestFuncs::ChannelCloseAll.permission(), Some(Permission::Admin));
+        assert!(Permission::Admin > Permission::Invoice && Permission::Invoice > Permission::ReadOnly);
+        for perm in Permission::ALL.iter() {
+            assert_eq!(perm.to_string().parse::<Permission>(), Ok(*perm));
+        }
+
+        let frame = Frame::new(1, Message::Request(RequestFuncs::GetNodeInfo)).with_token(Some("admin:00".to_string()));
+        assert_eq!(deserialize_frame(&serialize_frame(&frame)), Ok(frame));
+    }
+
+    #[test]
+    fn decode_garbage() {
+        assert!(deserialize_message(&[]).is_err());
+        assert!(deserialize_message(&[0xff; 32]).is_err());
+        assert!(deserialize_frame(b"RB").is_err());
+        let mut ser = serialize_frame(&Frame::new(1, Message::Request(RequestFuncs::GetNodeInfo)));
+        ser.truncate(ser.len() - 1);
+        ser.extend_from_slice(&[0xff; 8]);
+        match deserialize_frame(&ser) {
+            Err(ProtocolError::Decode(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let a = Frame::new(7, Message::Response(ResponseFuncs::ChannelList(vec!["{}".to_string(); 200])));
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &a).unwrap();
+        write_frame(&mut buf, &a).unwrap();
+        let mut cursor = std::io::Cursor::new(buf);
+        assert_eq!(read_frame(&mut cursor).unwrap(), a);
+        assert_eq!(read_frame(&mut cursor).unwrap(), a);
+        assert!(read_frame(&mut cursor).is_err());
+    }
+
+    const NODE_ID: &str = "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619";
+
+    #[test]
+    fn parse_channel_create() {
+        let req: RequestFuncs = format!("channel,create,{}@127.0.0.1:9736,2000000,100500000", NODE_ID)
+            .parse()
+            .unwrap();
+        assert_eq!(req, RequestFuncs::ChannelCreate(ChannelCreateRequest {
+            node_id: NODE_ID.to_string(),
+            host: Some("127.0.0.1:9736".to_string()),
+            value_sat: 2000000,
+            push_msat: 100500000,
+            funding: FundingMode::Wallet,
+            utxos: vec![],
+            fee: None,
+            wallet: None,
+        }));
+        let req: RequestFuncs = format!("channel,create,{},2000000,0,psbt", NODE_ID).parse().unwrap();
+        if let RequestFuncs::ChannelCreate(req) = req {
+            assert_eq!(req.host, None);
+            assert_eq!(req.funding, FundingMode::Psbt);
+        } else {
+            panic!("wrong request");
+        }
+        assert!(format!("channel,create,{},2000000,0,cold", NODE_ID).parse::<RequestFuncs>().is_err());
+        // Every channel is announced, there is no option for it
+        assert!(format!("channel,create,{},2000000,0,private", NODE_ID).parse::<RequestFuncs>().is_err());
+
+        let utxo = format!("{}:1", "cd".repeat(32));
+        let req: RequestFuncs = format!("channel,create,{},2000000,0,utxo={},feerate=12,wallet=hot", NODE_ID, utxo)
+            .parse()
+            .unwrap();
+        if let RequestFuncs::ChannelCreate(req) = req {
+            assert_eq!(req.utxos, vec![utxo.clone()]);
+            assert_eq!(req.fee, Some(FeeTarget::SatPerVbyte(12)));
+            assert_eq!(req.wallet, Some("hot".to_string()));
+        } else {
+            panic!("wrong request");
+        }
+        assert!(format!("channel,create,{},2000000,0,utxo=abcd:1", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!(format!("channel,create,{},2000000,0,conf=0", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!(format!("channel,create,{},2000000,0,wallet=a/b", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!(format!("channel,create,{},2000000,0,psbt,conf=6", NODE_ID).parse::<RequestFuncs>().is_err());
+
+        let channel_id = "ab".repeat(32);
+        let req: RequestFuncs = format!("channel,fund,{},cHNidP8BAHECAAAAAQ==", channel_id).parse().unwrap();
+        assert_eq!(req, RequestFuncs::ChannelFund(ChannelFundRequest {
+            temporary_channel_id: channel_id.clone(),
+            psbt: "cHNidP8BAHECAAAAAQ==".to_string(),
+        }));
+        assert_eq!(req.permission(), Some(Permission::Admin));
+        assert!(format!("channel,fund,{},not base64!", channel_id).parse::<RequestFuncs>().is_err());
+        let req: RequestFuncs = "channel,psbts,".parse().unwrap();
+        assert_eq!(req, RequestFuncs::ChannelPsbtList);
+        assert_eq!(req.permission(), Some(Permission::ReadOnly));
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        assert_eq!("info".parse::<RequestFuncs>(), Err(ParseError::MissingArgument("sub command")));
+        assert_eq!("peer,connect".parse::<RequestFuncs>(), Err(ParseError::MissingArgument("peer")));
+        assert_eq!("invoice,create".parse::<RequestFuncs>(), Err(ParseError::MissingArgument("amount")));
+        assert_eq!(
+            format!("channel,create,{},2000000", NODE_ID).parse::<RequestFuncs>(),
+            Err(ParseError::MissingArgument("push_msat"))
+        );
+        assert!(format!("channel,create,{},lots,0", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!(format!("channel,create,{},1000,1000001", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!("channel,create,02abc,1000,0".parse::<RequestFuncs>().is_err());
+        assert!("channel,kill,abcd".parse::<RequestFuncs>().is_err());
+        assert!("channel,list,some".parse::<RequestFuncs>().is_err());
+        assert!(format!("peer,connect,{}", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!(format!("peer,connect,{}@host", NODE_ID).parse::<RequestFuncs>().is_err());
+        assert!("invoice,pay,garbage".parse::<RequestFuncs>().is_err());
+        assert_eq!("wallet,list".parse::<RequestFuncs>(), Err(ParseError::InvalidSubCommand("list".to_string())));
+        assert_eq!("coins,list".parse::<RequestFuncs>(), Err(ParseError::InvalidCommand("coins".to_string())));
+    }
+
+    #[test]
+    fn parse_wallet_send() {
+        let address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        assert_eq!(
+            format!("wallet,send,{},150000", address).parse::<RequestFuncs>(),
+            Ok(RequestFuncs::WalletSend(WalletSendRequest { address: address.to_string(), amount_sat: 150000 }))
+        );
+        assert_eq!("wallet,send".parse::<RequestFuncs>(), Err(ParseError::MissingArgument("address")));
+        assert!(format!("wallet,send,{},0", address).parse::<RequestFuncs>().is_err());
+        assert!("wallet,send,not-an-address,1000".parse::<RequestFuncs>().is_err());
+        assert_eq!("wallet,balance,".parse::<RequestFuncs>().unwrap().permission(), Some(Permission::ReadOnly));
+        assert_eq!(RequestFuncs::WalletNewAddress.permission(), Some(Permission::Invoice));
+    }
+
+    #[test]
+    fn parse_tx_bump() {
+        let txid = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+        assert_eq!(
+            format!("tx,bump,{},25", txid).parse::<RequestFuncs>(),
+            Ok(RequestFuncs::TxBump(TxBumpRequest { txid: txid.to_string(), sat_per_vbyte: 25 }))
+        );
+        assert_eq!(format!("tx,bump,{}", txid).parse::<RequestFuncs>(), Err(ParseError::MissingArgument("feerate")));
+        assert!(format!("tx,bump,{},0", txid).parse::<RequestFuncs>().is_err());
+        assert!("tx,bump,abcd,25".parse::<RequestFuncs>().is_err());
+        assert_eq!(format!("tx,bump,{},25", txid).parse::<RequestFuncs>().unwrap().permission(), Some(Permission::Admin));
+    }
+
+    #[test]
+    fn frame_version_check() {
+        let frame = Frame::new(3, Message::Request(RequestFuncs::Hello(ClientHello::new("test"))));
+        let mut ser = serialize_frame(&frame);
+        assert_eq!(frame_version(&ser), Some(PROTOCOL_VERSION));
+        assert_eq!(deserialize_frame(&ser), Ok(frame));
+
+        ser[2..4].copy_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+        match deserialize_frame(&ser) {
+            Err(e @ ProtocolError::VersionMismatch { .. }) => {
+                assert!(e.to_string().contains(&format!("v{}", PROTOCOL_VERSION + 1)));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let legacy = serialize_message(Message::Request(RequestFuncs::GetNodeInfo));
+        assert_eq!(frame_version(&legacy), None);
+        assert_eq!(deserialize_frame(&legacy), Err(ProtocolError::NotVersioned));
     }
 }
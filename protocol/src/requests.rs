@@ -0,0 +1,412 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::ParseError;
+
+/// Largest channel rust-lightning will fund (2^24 sat, no wumbo).
+pub const MAX_FUNDING_SATOSHIS: u64 = 1 << 24;
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn check_node_id(node_id: &str) -> Result<(), ParseError> {
+    if node_id.len() != 66 || !is_hex(node_id) {
+        return Err(ParseError::invalid("node_id", "expected 33 hex encoded bytes"));
+    }
+    if !node_id.starts_with("02") && !node_id.starts_with("03") {
+        return Err(ParseError::invalid("node_id", "not a compressed public key"));
+    }
+    Ok(())
+}
+
+fn check_host(host: &str) -> Result<(), ParseError> {
+    match host.rfind(':') {
+        Some(idx) if idx > 0 => match host[idx + 1..].parse::<u16>() {
+            Ok(port) if port != 0 => Ok(()),
+            _ => Err(ParseError::invalid("host", format!("bad port in {}", host))),
+        },
+        _ => Err(ParseError::invalid("host", format!("expected <host>:<port>, got {}", host))),
+    }
+}
+
+fn parse_amount(name: &'static str, value: &str) -> Result<u64, ParseError> {
+    value
+        .parse::<u64>()
+        .map_err(|_| ParseError::invalid(name, format!("{} is not a whole number", value)))
+}
+
+/// Splits `<node_id>[@<host>:<port>]`.
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.find('@') {
+        Some(idx) => (target[..idx].to_string(), Some(target[idx + 1..].to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PeerConnectRequest {
+    pub node_id: String,
+    pub host: String,
+}
+
+impl PeerConnectRequest {
+    pub fn validate(&self) -> Result<(), ParseError> {
+        check_node_id(&self.node_id)?;
+        check_host(&self.host)
+    }
+}
+
+impl FromStr for PeerConnectRequest {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match split_target(s) {
+            (node_id, Some(host)) => {
+                let req = PeerConnectRequest { node_id, host };
+                req.validate()?;
+                Ok(req)
+            }
+            (_, None) => Err(ParseError::invalid("peer", "expected <node_id>@<host>:<port>")),
+        }
+    }
+}
+
+impl fmt::Display for PeerConnectRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.node_id, self.host)
+    }
+}
+
+/// Where the funds of a new channel come from.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum FundingMode {
+    /// Funded and signed by the bitcoind wallet of the node.
+    Wallet,
+    /// The node hands out an unsigned PSBT paying the funding output, see `ChannelFundRequest`.
+    Psbt,
+}
+
+impl FromStr for FundingMode {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wallet" => Ok(FundingMode::Wallet),
+            "psbt" => Ok(FundingMode::Psbt),
+            _ => Err(ParseError::invalid("funding", format!("expected wallet or psbt, got {}", s))),
+        }
+    }
+}
+
+/// Fee of a funding transaction, bitcoind estimates one when unset.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum FeeTarget {
+    SatPerVbyte(u64),
+    /// Confirmation target in blocks.
+    Blocks(u16),
+}
+
+/// Highest feerate we accept, anything above is most likely a typo.
+const MAX_SAT_PER_VBYTE: u64 = 10_000;
+/// Furthest confirmation target bitcoind can estimate.
+const MAX_CONF_TARGET: u16 = 1008;
+
+fn check_utxo(utxo: &str) -> Result<(), ParseError> {
+    match utxo.find(':') {
+        Some(64) if is_hex(&utxo[..64]) && utxo[65..].parse::<u32>().is_ok() => Ok(()),
+        _ => Err(ParseError::invalid("utxo", format!("expected <txid>:<vout>, got {}", utxo))),
+    }
+}
+
+// Goes into the bitcoind url, keep it to characters that need no escaping
+fn check_wallet(wallet: &str) -> Result<(), ParseError> {
+    let allowed = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.';
+    if wallet.is_empty() || !wallet.bytes().all(allowed) {
+        return Err(ParseError::invalid("wallet", format!("{} is not a supported wallet name", wallet)));
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ChannelCreateRequest {
+    pub node_id: String,
+    pub host: Option<String>,
+    pub value_sat: u64,
+    pub push_msat: u64,
+    pub funding: FundingMode,
+    /// Coin control for `FundingMode::Wallet`: the only outputs (`<txid>:<vout>`) the funding
+    /// transaction may spend, any if empty.
+    pub utxos: Vec<String>,
+    pub fee: Option<FeeTarget>,
+    /// bitcoind wallet paying for the channel, the default wallet if unset.
+    pub wallet: Option<String>,
+}
+
+impl ChannelCreateRequest {
+    /// Builds a request from `<node_id>[@<host>:<port>]`, the channel value in satoshis and the
+    /// amount in millisatoshis pushed to the remote side.
+    pub fn new(target: &str, value_sat: &str, push_msat: &str) -> Result<Self, ParseError> {
+        let (node_id, host) = split_target(target);
+        let req = ChannelCreateRequest {
+            node_id,
+            host,
+            value_sat: parse_amount("value_sat", value_sat)?,
+            push_msat: parse_amount("push_msat", push_msat)?,
+            funding: FundingMode::Wallet,
+            utxos: vec![],
+            fee: None,
+            wallet: None,
+        };
+        req.validate()?;
+        Ok(req)
+    }
+
+    pub fn validate(&self) -> Result<(), ParseError> {
+        check_node_id(&self.node_id)?;
+        if let Some(host) = &self.host {
+            check_host(host)?;
+        }
+        if self.value_sat == 0 || self.value_sat >= MAX_FUNDING_SATOSHIS {
+            return Err(ParseError::invalid(
+                "value_sat",
+                format!("must be between 1 and {}", MAX_FUNDING_SATOSHIS - 1),
+            ));
+        }
+        if self.push_msat > self.value_sat * 1000 {
+            return Err(ParseError::invalid("push_msat", "can't push more than the channel value"));
+        }
+        for utxo in &self.utxos {
+            check_utxo(utxo)?;
+        }
+        match self.fee {
+            Some(FeeTarget::SatPerVbyte(rate)) if rate == 0 || rate > MAX_SAT_PER_VBYTE => {
+                return Err(ParseError::invalid("feerate", format!("must be between 1 and {} sat/vB", MAX_SAT_PER_VBYTE)));
+            }
+            Some(FeeTarget::Blocks(blocks)) if blocks == 0 || blocks > MAX_CONF_TARGET => {
+                return Err(ParseError::invalid("conf_target", format!("must be between 1 and {} blocks", MAX_CONF_TARGET)));
+            }
+            _ => {}
+        }
+        if let Some(wallet) = &self.wallet {
+            check_wallet(wallet)?;
+        }
+        if self.funding == FundingMode::Psbt && (!self.utxos.is_empty() || self.fee.is_some() || self.wallet.is_some()) {
+            return Err(ParseError::invalid("funding", "coin control only applies to wallet funding"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ChannelCloseRequest {
+    pub channel_id: String,
+}
+
+impl ChannelCloseRequest {
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.channel_id.len() != 64 || !is_hex(&self.channel_id) {
+            return Err(ParseError::invalid("channel_id", "expected 32 hex encoded bytes"));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChannelCloseRequest {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let req = ChannelCloseRequest { channel_id: s.to_string() };
+        req.validate()?;
+        Ok(req)
+    }
+}
+
+impl ChannelCreateRequest {
+    /// Applies one of the trailing options of `channel,create`: `psbt`, `utxo=<txid>:<vout>`
+    /// (repeatable), `feerate=<sat/vB>`, `conf=<blocks>` or `wallet=<name>`. Channels are always
+    /// announced, rust-lightning only takes that from the node wide config.
+    pub fn set_option(&mut self, option: &str) -> Result<(), ParseError> {
+        let (key, value) = match option.find('=') {
+            Some(idx) => (&option[..idx], Some(&option[idx + 1..])),
+            None => (option, None),
+        };
+        match (key, value) {
+            ("psbt", None) => self.funding = FundingMode::Psbt,
+            ("utxo", Some(utxo)) => self.utxos.push(utxo.to_string()),
+            ("feerate", Some(rate)) => self.fee = Some(FeeTarget::SatPerVbyte(parse_amount("feerate", rate)?)),
+            ("conf", Some(blocks)) => {
+                let blocks = blocks
+                    .parse()
+                    .map_err(|_| ParseError::invalid("conf_target", format!("{} is not a number of blocks", blocks)))?;
+                self.fee = Some(FeeTarget::Blocks(blocks));
+            }
+            ("wallet", Some(wallet)) => self.wallet = Some(wallet.to_string()),
+            _ => {
+                return Err(ParseError::invalid(
+                    "option",
+                    format!("expected psbt, utxo=, feerate=, conf= or wallet=, got {}", option),
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hands the signed PSBT of a `FundingMode::Psbt` channel back to the node.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ChannelFundRequest {
+    pub temporary_channel_id: String,
+    /// Base64, as produced by bitcoind and most wallets.
+    pub psbt: String,
+}
+
+impl ChannelFundRequest {
+    pub fn new(temporary_channel_id: &str, psbt: &str) -> Result<Self, ParseError> {
+        let req = ChannelFundRequest {
+            temporary_channel_id: temporary_channel_id.to_string(),
+            psbt: psbt.to_string(),
+        };
+        req.validate()?;
+        Ok(req)
+    }
+
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.temporary_channel_id.len() != 64 || !is_hex(&self.temporary_channel_id) {
+            return Err(ParseError::invalid("temporary_channel_id", "expected 32 hex encoded bytes"));
+        }
+        let is_base64 = |b: u8| b.is_ascii_alphanumeric() || b == b'+' || b == b'/' || b == b'=';
+        if self.psbt.is_empty() || !self.psbt.bytes().all(is_base64) {
+            return Err(ParseError::invalid("psbt", "expected a base64 encoded PSBT"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ChannelListMode {
+    All,
+    Live,
+}
+
+impl FromStr for ChannelListMode {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(ChannelListMode::All),
+            "live" => Ok(ChannelListMode::Live),
+            _ => Err(ParseError::invalid("mode", format!("expected all or live, got {}", s))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InvoiceCreateRequest {
+    pub amount_pico_btc: u64,
+}
+
+impl InvoiceCreateRequest {
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.amount_pico_btc == 0 {
+            return Err(ParseError::invalid("amount", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for InvoiceCreateRequest {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let req = InvoiceCreateRequest { amount_pico_btc: parse_amount("amount", s)? };
+        req.validate()?;
+        Ok(req)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct InvoicePayRequest {
+    pub invoice: String,
+    /// Only used when the invoice doesn't carry an amount itself.
+    pub amount_msat: Option<u64>,
+}
+
+impl InvoicePayRequest {
+    pub fn new(invoice: &str, amount_msat: Option<&str>) -> Result<Self, ParseError> {
+        let req = InvoicePayRequest {
+            invoice: invoice.to_string(),
+            amount_msat: match amount_msat {
+                Some(amt) => Some(parse_amount("amount_msat", amt)?),
+                None => None,
+            },
+        };
+        req.validate()?;
+        Ok(req)
+    }
+
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if !self.invoice.to_lowercase().starts_with("ln") {
+            return Err(ParseError::invalid("invoice", "not a bolt11 invoice"));
+        }
+        if self.amount_msat == Some(0) {
+            return Err(ParseError::invalid("amount_msat", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Largest amount that could ever be sent on-chain, 21M bitcoin.
+const MAX_SATOSHIS: u64 = 21_000_000 * 100_000_000;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WalletSendRequest {
+    pub address: String,
+    pub amount_sat: u64,
+}
+
+impl WalletSendRequest {
+    pub fn new(address: &str, amount_sat: &str) -> Result<Self, ParseError> {
+        let req = WalletSendRequest {
+            address: address.to_string(),
+            amount_sat: parse_amount("amount_sat", amount_sat)?,
+        };
+        req.validate()?;
+        Ok(req)
+    }
+
+    /// Only catches obvious typos, bitcoind has the final say on the address.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.address.len() < 26 || self.address.len() > 90 || !self.address.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(ParseError::invalid("address", format!("{} is not a bitcoin address", self.address)));
+        }
+        if self.amount_sat == 0 || self.amount_sat > MAX_SATOSHIS {
+            return Err(ParseError::invalid("amount_sat", format!("must be between 1 and {}", MAX_SATOSHIS)));
+        }
+        Ok(())
+    }
+}
+
+/// Raises the feerate of a transaction the node broadcast, see `ln_cmd::tx::bump`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TxBumpRequest {
+    pub txid: String,
+    pub sat_per_vbyte: u64,
+}
+
+impl TxBumpRequest {
+    pub fn new(txid: &str, sat_per_vbyte: &str) -> Result<Self, ParseError> {
+        let req = TxBumpRequest {
+            txid: txid.to_string(),
+            sat_per_vbyte: parse_amount("feerate", sat_per_vbyte)?,
+        };
+        req.validate()?;
+        Ok(req)
+    }
+
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.txid.len() != 64 || !is_hex(&self.txid) {
+            return Err(ParseError::invalid("txid", format!("{} is not a transaction id", self.txid)));
+        }
+        if self.sat_per_vbyte == 0 || self.sat_per_vbyte > MAX_SAT_PER_VBYTE {
+            return Err(ParseError::invalid("feerate", format!("must be between 1 and {} sat/vB", MAX_SAT_PER_VBYTE)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// On-chain funds of the node, in satoshis.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct WalletBalance {
+    /// Confirmed funds of the bitcoind wallet.
+    pub confirmed_sat: u64,
+    pub unconfirmed_sat: u64,
+    /// Our side of the open channels, the channel reserve included.
+    pub channels_sat: u64,
+    /// Outputs of closed channels still waiting to be swept to the wallet.
+    pub claimable_sat: u64,
+}
+
+/// How the claim and cooperative close keys of the node went into the bitcoind wallet.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum ImportMode {
+    /// `importprivkey` into a legacy wallet.
+    Legacy,
+    /// `importdescriptors` of `wpkh(...)` into a descriptor wallet.
+    Descriptor,
+}
+
+impl fmt::Display for ImportMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportMode::Legacy => write!(f, "legacy"),
+            ImportMode::Descriptor => write!(f, "descriptor"),
+        }
+    }
+}
+
+/// Outcome of the key import at startup, with the bech32 addresses of the keys.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct KeyImport {
+    /// None when bitcoind has no wallet loaded.
+    pub mode: Option<ImportMode>,
+    /// Whether the wallet owns every address afterwards.
+    pub imported: bool,
+    pub error: Option<String>,
+    pub addresses: Vec<String>,
+}
@@ -0,0 +1,101 @@
+use bitcoin_hashes::hmac::{Hmac, HmacEngine};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use ln_manager::ln_bridge::utils::hex_str;
+use protocol::Permission;
+use rand::{thread_rng, Rng};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+// Control tokens are `<permission>:<hex hmac-sha256(root key, permission)>`. The root key never
+// leaves `<lndata>/auth`, one token file per permission is written next to it for the cli.
+pub struct Auth {
+    root_key: [u8; 32],
+}
+
+fn write_private(path: &str, content: &[u8]) -> Result<(), String> {
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|e| format!("Could not create {}: {}", path, e))?;
+    f.write_all(content)
+        .and_then(|_| f.sync_all())
+        .map_err(|e| format!("Could not write {}: {}", path, e))
+}
+
+impl Auth {
+    // Loads the root key, generating it and the token files at first start
+    pub fn init(data_path: &str) -> Result<Self, String> {
+        let dir = format!("{}/auth", data_path);
+        let key_path = format!("{}/root_key", dir);
+        if let Ok(key) = fs::read(&key_path) {
+            if key.len() != 32 {
+                return Err(format!("{} is corrupted, remove the auth directory to regenerate it", key_path));
+            }
+            let mut root_key = [0; 32];
+            root_key.copy_from_slice(&key);
+            return Ok(Auth { root_key });
+        }
+
+        fs::create_dir_all(&dir).map_err(|e| format!("Could not create {}: {}", dir, e))?;
+        let mut root_key = [0; 32];
+        thread_rng().fill_bytes(&mut root_key);
+        let auth = Auth { root_key };
+        for perm in Permission::ALL.iter() {
+            write_private(&format!("{}/{}.token", dir, perm), auth.token(*perm).as_bytes())?;
+        }
+        // Written last so an interrupted first start generates everything again
+        write_private(&key_path, &root_key)?;
+        info!("Generated control credentials in {}", dir);
+        Ok(auth)
+    }
+
+    pub fn token(&self, perm: Permission) -> String {
+        format!("{}:{}", perm, hex_str(&self.mac(perm)))
+    }
+
+    fn mac(&self, perm: Permission) -> [u8; 32] {
+        let mut engine = HmacEngine::<sha256::Hash>::new(&self.root_key);
+        engine.input(perm.to_string().as_bytes());
+        Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+    }
+
+    // The permission a token grants, None if it wasn't issued by us
+    pub fn verify(&self, token: &str) -> Option<Permission> {
+        let mut parts = token.trim().splitn(2, ':');
+        let perm: Permission = parts.next()?.parse().ok()?;
+        let mac = parts.next()?;
+        let expected = hex_str(&self.mac(perm));
+        if mac.len() != expected.len() {
+            return None;
+        }
+        // Constant time compare so the mac can't be guessed byte by byte
+        let diff = mac
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff == 0 {
+            Some(perm)
+        } else {
+            None
+        }
+    }
+
+    pub fn authorize(&self, req: &protocol::RequestFuncs, token: Option<&str>) -> Result<(), String> {
+        let needed = match req.permission() {
+            Some(needed) => needed,
+            None => return Ok(()),
+        };
+        match token.and_then(|t| self.verify(t)) {
+            Some(granted) if granted >= needed => Ok(()),
+            Some(granted) => Err(format!(
+                "Permission denied: request needs a {} token, got {}",
+                needed, granted
+            )),
+            None => Err("Authentication required: pass a valid token with --credentials".to_string()),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::lightning::chain::keysinterface::KeysInterface;
+use crate::ln_cmd::auth::Auth;
+use crate::ln_cmd::tasks::Probe;
+use crate::ln_cmd::utils;
+use crate::ln_manager::ln_cmd::channel::{self, ChannelC};
+use crate::ln_manager::ln_cmd::invoice::InvoiceC;
+use crate::ln_manager::ln_cmd::peer::{self, PeerC};
+use crate::ln_manager::ln_cmd::{tx, wallet};
+use ln_manager::LnManager;
+use protocol;
+
+// Request groups this server handles, announced in the hello exchange
+const CAPABILITIES: &[&str] = &["info", "peer", "channel", "invoice", "events", "wallet", "tx"];
+
+// Shared by every control transport, wallet requests wait on bitcoind
+pub async fn handle_request(
+    req: protocol::RequestFuncs,
+    ln_mgr: &LnManager<Probe>,
+) -> protocol::ResponseFuncs {
+    match req {
+        protocol::RequestFuncs::GetAddresses => {
+            let addresses = utils::imported_addresses::get(
+                ln_mgr.settings.lightning.lndata.clone(),
+                ln_mgr.network.clone(),
+            );
+            protocol::ResponseFuncs::GetAddresses(protocol::KeyImport { addresses, ..ln_mgr.key_import.clone() })
+        }
+        protocol::RequestFuncs::GetNodeInfo => {
+            let node_info = utils::node_info::get(&ln_mgr.keys.get_node_secret());
+            protocol::ResponseFuncs::GetNodeInfo(node_info)
+        }
+        protocol::RequestFuncs::PeerConnect(req) => match ln_mgr.connect(req) {
+            Ok(_) => protocol::ResponseFuncs::PeerConnect,
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::PeerList => {
+            let nodes = ln_mgr.list();
+            protocol::ResponseFuncs::PeerList(nodes)
+        }
+        protocol::RequestFuncs::ChannelCreate(req) => {
+            let connected = peer::ensure_connected(
+                &req.node_id,
+                req.host.as_ref(),
+                &ln_mgr.peer_manager,
+                ln_mgr.event_notify.clone(),
+                ln_mgr.larva.clone(),
+            );
+            match connected.await.and_then(|_| ln_mgr.fund_channel(req)) {
+                Ok(channel) => protocol::ResponseFuncs::ChannelCreate(channel),
+                Err(e) => protocol::ResponseFuncs::Error(e),
+            }
+        }
+        protocol::RequestFuncs::ChannelClose(req) => match ln_mgr.close(req) {
+            Ok(channel) => protocol::ResponseFuncs::ChannelClose(channel),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::ChannelFund(req) => {
+            let funded = channel::fund_psbt(
+                req,
+                &ln_mgr.rpc_client,
+                &ln_mgr.channel_manager,
+                &ln_mgr.fundings,
+                ln_mgr.event_notify.clone(),
+            );
+            match funded.await {
+                Ok(txid) => protocol::ResponseFuncs::ChannelFund(txid),
+                Err(e) => protocol::ResponseFuncs::Error(e),
+            }
+        }
+        protocol::RequestFuncs::ChannelCloseAll => {
+            ln_mgr.force_close_all();
+            protocol::ResponseFuncs::ChannelCloseAll
+        }
+        protocol::RequestFuncs::ChannelList(mode) => {
+            protocol::ResponseFuncs::ChannelList(ln_mgr.channel_list(mode))
+        }
+        protocol::RequestFuncs::ChannelPsbtList => {
+            protocol::ResponseFuncs::ChannelPsbtList(channel::psbt_list(&ln_mgr.fundings))
+        }
+        protocol::RequestFuncs::InvoiceCreate(req) => match ln_mgr.create_invoice(req) {
+            Ok(invoice_res) => protocol::ResponseFuncs::InvoiceCreate(invoice_res),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::InvoicePay(req) => match ln_mgr.pay(req) {
+            Ok(_) => protocol::ResponseFuncs::InvoicePay,
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        // Only a stream connection can carry events, see `stream_srv`
+        protocol::RequestFuncs::Subscribe => {
+            protocol::ResponseFuncs::Error("Subscriptions need a tcp or unix connection".to_string())
+        }
+        protocol::RequestFuncs::WalletBalance => {
+            match wallet::balance(&ln_mgr.rpc_client, &ln_mgr.channel_manager, &ln_mgr.sweeper).await {
+                Ok(balance) => protocol::ResponseFuncs::WalletBalance(balance),
+                Err(e) => protocol::ResponseFuncs::Error(e),
+            }
+        }
+        protocol::RequestFuncs::WalletNewAddress => match wallet::new_address(&ln_mgr.rpc_client).await {
+            Ok(address) => protocol::ResponseFuncs::WalletNewAddress(address),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::WalletSend(req) => match wallet::send(&ln_mgr.rpc_client, req).await {
+            Ok(txid) => protocol::ResponseFuncs::WalletSend(txid),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::WalletListUnspent => match wallet::list_unspent(&ln_mgr.rpc_client).await {
+            Ok(utxos) => protocol::ResponseFuncs::WalletListUnspent(utxos),
+            Err(e) => protocol::ResponseFuncs::Error(e),
+        },
+        protocol::RequestFuncs::TxBump(req) => {
+            let bumped = tx::bump(
+                req,
+                &ln_mgr.rpc_client,
+                ln_mgr.network,
+                &ln_mgr.chain_broadcaster,
+                &ln_mgr.sweeper,
+            );
+            match bumped.await {
+                Ok(txid) => protocol::ResponseFuncs::TxBump(txid),
+                Err(e) => protocol::ResponseFuncs::Error(e),
+            }
+        }
+        protocol::RequestFuncs::Hello(client) => {
+            info!("Control client {} speaks protocol v{}", &client.client, client.version);
+            protocol::ResponseFuncs::Hello(hello())
+        }
+    }
+}
+
+// Checks the token of a decoded frame before handing its request to `handle_request`
+pub async fn handle_frame(frame: protocol::Frame, ln_mgr: &LnManager<Probe>, auth: &Auth) -> protocol::Frame {
+    let resp = match frame.message {
+        protocol::Message::Request(req) => match auth.authorize(&req, frame.token.as_ref().map(|t| t.as_str())) {
+            Ok(_) => handle_request(req, ln_mgr).await,
+            Err(e) => {
+                warn!("Refused control request {:?}: {}", req.permission(), e);
+                protocol::ResponseFuncs::Error(e)
+            }
+        },
+        _ => protocol::ResponseFuncs::Error("Unknown request".to_string()),
+    };
+    protocol::Frame::new(frame.id, protocol::Message::Response(resp))
+}
+
+pub fn hello() -> protocol::ServerHello {
+    protocol::ServerHello {
+        version: protocol::PROTOCOL_VERSION,
+        min_version: protocol::MIN_PROTOCOL_VERSION,
+        server: format!("rustbolt {}", env!("CARGO_PKG_VERSION")),
+        capabilities: CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+// Answer for a frame we couldn't decode, tied to no request (id 0)
+pub fn decode_error(e: &protocol::ProtocolError) -> protocol::Frame {
+    let reason = match e {
+        protocol::ProtocolError::VersionMismatch { theirs, .. } => format!(
+            "Client speaks protocol v{} but rustbolt supports v{} to v{}, please upgrade the older side",
+            theirs,
+            protocol::MIN_PROTOCOL_VERSION,
+            protocol::PROTOCOL_VERSION,
+        ),
+        _ => e.to_string(),
+    };
+    protocol::Frame::new(0, protocol::Message::Response(protocol::ResponseFuncs::Error(reason)))
+}
@@ -1,2 +1,4 @@
+pub mod auth;
+pub mod dispatch;
 pub mod tasks;
 pub mod utils;
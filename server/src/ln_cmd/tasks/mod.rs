@@ -1,5 +1,7 @@
 pub mod ln_mgr;
 pub mod node;
+pub mod rpc_srv;
+pub mod stream_srv;
 pub mod udp_srv;
 use futures::future::Future;
 use futures::task::{Context, Poll};
@@ -1,6 +1,8 @@
-use crate::ln_cmd::tasks::{ln_mgr, udp_srv};
+use crate::ln_cmd::auth::Auth;
+use crate::ln_cmd::tasks::{ln_mgr, rpc_srv, stream_srv, udp_srv};
 use crate::ln_cmd::tasks::{Arg, Probe, TaskFn};
 use crate::ln_manager::executor::Larva;
+use std::sync::Arc;
 
 fn node(mut args: Vec<Arg>, exec: Probe) -> Result<(), String> {
     let executor = exec.clone();
@@ -10,8 +12,40 @@ fn node(mut args: Vec<Arg>, exec: Probe) -> Result<(), String> {
         .collect();
     let node_conf = args;
     let _ = exec.spawn_task(async move {
-        let ln_mgr = ln_mgr::gen(ln_conf, executor.clone()).await?;
-        let _ = udp_srv::gen(node_conf, executor.clone(), ln_mgr).await;
+        let ln_mgr = Arc::new(ln_mgr::gen(ln_conf, executor.clone()).await?);
+        let auth = match Auth::init(&ln_mgr.settings.lightning.lndata) {
+            Ok(auth) => Arc::new(auth),
+            Err(e) => {
+                error!("{}", e);
+                return Err(());
+            }
+        };
+
+        let stream_conf = node_conf.clone();
+        let stream_mgr = ln_mgr.clone();
+        let stream_exec = executor.clone();
+        let stream_auth = auth.clone();
+        let _ = executor.spawn_task(async move {
+            if let Err(e) = stream_srv::gen(stream_conf, stream_exec, stream_mgr, stream_auth).await {
+                error!("{}", e);
+            }
+            Ok(())
+        });
+
+        let rpc_conf = node_conf.clone();
+        let rpc_mgr = ln_mgr.clone();
+        let rpc_exec = executor.clone();
+        let rpc_auth = auth.clone();
+        let _ = executor.spawn_task(async move {
+            if let Err(e) = rpc_srv::gen(rpc_conf, rpc_exec, rpc_mgr, rpc_auth).await {
+                error!("{}", e);
+            }
+            Ok(())
+        });
+
+        if let Err(e) = udp_srv::gen(node_conf, executor.clone(), ln_mgr, auth).await {
+            error!("{}", e);
+        }
         Ok(())
     });
     Ok(())
@@ -0,0 +1,356 @@
+use crate::ln_cmd::auth::Auth;
+use crate::ln_cmd::dispatch;
+use crate::ln_cmd::tasks::{Arg, Probe};
+use crate::ln_node::settings::Settings as NodeSettings;
+use futures::TryStreamExt;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use ln_manager::LnManager;
+use protocol;
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+const UNAUTHORIZED: i64 = -32001;
+
+// Largest request body read, signed PSBTs included
+const MAX_BODY_LEN: usize = 1024 * 1024;
+// Without a valid token only the permission-less calls go through, and they are small
+const MAX_ANONYMOUS_BODY_LEN: usize = 4 * 1024;
+
+// JSON-RPC over http for clients that can't speak bincode, a thin layer over `dispatch`
+pub async fn gen(
+    arg: Vec<Arg>,
+    _exec: Probe,
+    ln_mgr: Arc<LnManager<Probe>>,
+    auth: Arc<Auth>,
+) -> Result<(), String> {
+    let node_conf: NodeSettings = match &arg[0] {
+        Arg::NodeConf(conf) => conf.clone(),
+        _ => return Err("Missing node settings".to_string()),
+    };
+    let address = match node_conf.rpc {
+        Some(rpc) => rpc.address,
+        None => return Ok(()),
+    };
+    let addr: SocketAddr = address
+        .parse()
+        .map_err(|e| format!("Invalid rpc address {}: {}", &address, e))?;
+
+    let make_svc = make_service_fn(move |_| {
+        let ln_mgr = ln_mgr.clone();
+        let auth = auth.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| serve(req, ln_mgr.clone(), auth.clone())))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .map_err(|e| format!("Could not bind {}: {}", &address, e))?
+        .serve(make_svc);
+    info!("Lightning Server Running on: {} (json-rpc)", &address);
+    server.await.map_err(|e| format!("JSON-RPC server failed: {}", e))
+}
+
+async fn serve(
+    req: Request<Body>,
+    ln_mgr: Arc<LnManager<Probe>>,
+    auth: Arc<Auth>,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::POST {
+        let mut resp = Response::new(Body::from("JSON-RPC requests must be POSTed"));
+        *resp.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(resp);
+    }
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim_start_matches("Bearer ").to_string());
+    let max_len = match token.as_ref().and_then(|t| auth.verify(t)) {
+        Some(_) => MAX_BODY_LEN,
+        None => MAX_ANONYMOUS_BODY_LEN,
+    };
+    let body = match read_body(req.into_body(), max_len).await? {
+        Some(body) => body,
+        None => {
+            let mut resp = Response::new(Body::from(format!("Request bodies are limited to {} bytes", max_len)));
+            *resp.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+            return Ok(resp);
+        }
+    };
+    let token = token.as_ref().map(|t| t.as_str());
+
+    let reply = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(calls)) => {
+            if calls.is_empty() {
+                Some(error_reply(Value::Null, INVALID_REQUEST, "Empty batch".to_string()))
+            } else {
+                let mut replies = vec![];
+                for call in calls {
+                    replies.extend(handle_call(call, &ln_mgr, &auth, token).await);
+                }
+                // A batch of notifications gets no reply at all
+                if replies.is_empty() {
+                    None
+                } else {
+                    Some(Value::Array(replies))
+                }
+            }
+        }
+        Ok(call) => handle_call(call, &ln_mgr, &auth, token).await,
+        Err(e) => Some(error_reply(Value::Null, PARSE_ERROR, e.to_string())),
+    };
+    let mut resp = match reply {
+        Some(reply) => Response::new(Body::from(reply.to_string())),
+        None => {
+            let mut resp = Response::new(Body::empty());
+            *resp.status_mut() = StatusCode::NO_CONTENT;
+            return Ok(resp);
+        }
+    };
+    resp.headers_mut()
+        .insert("Content-Type", hyper::header::HeaderValue::from_static("application/json"));
+    Ok(resp)
+}
+
+// The body, or None once it grows past `max_len`
+async fn read_body(mut body: Body, max_len: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut buf = Vec::new();
+    while let Some(chunk) = body.try_next().await? {
+        if buf.len() + chunk.len() > max_len {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf))
+}
+
+fn error_reply(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+// The reply to one call, None for a notification (a request without an id)
+async fn handle_call(call: Value, ln_mgr: &LnManager<Probe>, auth: &Auth, token: Option<&str>) -> Option<Value> {
+    let notification = call.is_object() && call.get("id").is_none();
+    let reply = reply_to(call, ln_mgr, auth, token).await;
+    if notification {
+        None
+    } else {
+        Some(reply)
+    }
+}
+
+async fn reply_to(call: Value, ln_mgr: &LnManager<Probe>, auth: &Auth, token: Option<&str>) -> Value {
+    let id = call.get("id").cloned().unwrap_or(Value::Null);
+    if call.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+        return error_reply(id, INVALID_REQUEST, "Expected a JSON-RPC 2.0 request".to_string());
+    }
+    let method = match call.get("method").and_then(|v| v.as_str()) {
+        Some(method) => method,
+        None => return error_reply(id, INVALID_REQUEST, "Missing method".to_string()),
+    };
+    let params = Params(call.get("params").cloned().unwrap_or(Value::Null));
+
+    let reqs = match to_requests(method, &params) {
+        Ok(Some(reqs)) => reqs,
+        Ok(None) => return error_reply(id, METHOD_NOT_FOUND, format!("Unknown method {}", method)),
+        Err(e) => return error_reply(id, INVALID_PARAMS, e),
+    };
+    let mut results = Vec::with_capacity(reqs.len());
+    for req in reqs {
+        if let Err(e) = auth.authorize(&req, token) {
+            return error_reply(id, UNAUTHORIZED, e);
+        }
+        match dispatch::handle_request(req, ln_mgr).await {
+            protocol::ResponseFuncs::Error(e) => return error_reply(id, SERVER_ERROR, e),
+            resp => results.push(to_json(resp)),
+        }
+    }
+    let result = match results.len() {
+        1 => results.pop().unwrap(),
+        // getinfo merges the node id and the imported addresses
+        _ => results.into_iter().fold(json!({}), |mut acc, r| {
+            if let (Some(acc), Some(r)) = (acc.as_object_mut(), r.as_object()) {
+                acc.extend(r.clone());
+            }
+            acc
+        }),
+    };
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+// Positional (array) or named (object) params
+struct Params(Value);
+
+impl Params {
+    fn get(&self, idx: usize, name: &str) -> Option<String> {
+        let value = match &self.0 {
+            Value::Array(values) => values.get(idx),
+            Value::Object(values) => values.get(name),
+            _ => None,
+        }?;
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Null => None,
+            v => Some(v.to_string()),
+        }
+    }
+
+    fn require(&self, idx: usize, name: &str) -> Result<String, String> {
+        self.get(idx, name).ok_or_else(|| format!("Missing param {}", name))
+    }
+}
+
+fn to_requests(method: &str, params: &Params) -> Result<Option<Vec<protocol::RequestFuncs>>, String> {
+    let req = match method {
+        "getinfo" => {
+            return Ok(Some(vec![
+                protocol::RequestFuncs::GetNodeInfo,
+                protocol::RequestFuncs::GetAddresses,
+            ]))
+        }
+        "connect" => {
+            let node_id = params.require(0, "node_id")?;
+            let req: protocol::PeerConnectRequest = match params.get(1, "host") {
+                Some(host) => format!("{}@{}", node_id, host).parse(),
+                None => node_id.parse(),
+            }
+            .map_err(|e: protocol::ParseError| e.to_string())?;
+            protocol::RequestFuncs::PeerConnect(req)
+        }
+        "listpeers" => protocol::RequestFuncs::PeerList,
+        "openchannel" => {
+            let mut target = params.require(0, "node_id")?;
+            if let Some(host) = params.get(1, "host") {
+                target = format!("{}@{}", target, host);
+            }
+            let value_sat = params.require(2, "value_sat")?;
+            let push_msat = params.get(3, "push_msat").unwrap_or_else(|| "0".to_string());
+            let mut req = protocol::ChannelCreateRequest::new(&target, &value_sat, &push_msat)
+                .map_err(|e| e.to_string())?;
+            if let Some(funding) = params.get(4, "funding") {
+                req.funding = funding.parse().map_err(|e: protocol::ParseError| e.to_string())?;
+            }
+            if let Some(utxos) = params.get(5, "utxos") {
+                let utxos: Vec<String> = serde_json::from_str(&utxos)
+                    .map_err(|_| format!("Invalid utxos: {} is not an array of <txid>:<vout>", utxos))?;
+                for utxo in utxos {
+                    req.set_option(&format!("utxo={}", utxo)).map_err(|e| e.to_string())?;
+                }
+            }
+            if let Some(feerate) = params.get(6, "feerate") {
+                req.set_option(&format!("feerate={}", feerate)).map_err(|e| e.to_string())?;
+            }
+            if let Some(conf_target) = params.get(7, "conf_target") {
+                req.set_option(&format!("conf={}", conf_target)).map_err(|e| e.to_string())?;
+            }
+            if let Some(wallet) = params.get(8, "wallet") {
+                req.set_option(&format!("wallet={}", wallet)).map_err(|e| e.to_string())?;
+            }
+            req.validate().map_err(|e| e.to_string())?;
+            protocol::RequestFuncs::ChannelCreate(req)
+        }
+        "fundchannel" => {
+            let req = protocol::ChannelFundRequest::new(
+                &params.require(0, "temporary_channel_id")?,
+                &params.require(1, "psbt")?,
+            )
+            .map_err(|e| e.to_string())?;
+            protocol::RequestFuncs::ChannelFund(req)
+        }
+        "listpsbts" => protocol::RequestFuncs::ChannelPsbtList,
+        "closechannel" => {
+            let req = params.require(0, "channel_id")?.parse().map_err(|e: protocol::ParseError| e.to_string())?;
+            protocol::RequestFuncs::ChannelClose(req)
+        }
+        "listchannels" => {
+            let mode = params.get(0, "mode").unwrap_or_else(|| "all".to_string());
+            protocol::RequestFuncs::ChannelList(mode.parse().map_err(|e: protocol::ParseError| e.to_string())?)
+        }
+        "createinvoice" => {
+            let req = params
+                .require(0, "amount_pico_btc")?
+                .parse()
+                .map_err(|e: protocol::ParseError| e.to_string())?;
+            protocol::RequestFuncs::InvoiceCreate(req)
+        }
+        "pay" => {
+            let invoice = params.require(0, "invoice")?;
+            let amount = params.get(1, "amount_msat");
+            let req = protocol::InvoicePayRequest::new(&invoice, amount.as_ref().map(|a| a.as_str()))
+                .map_err(|e| e.to_string())?;
+            protocol::RequestFuncs::InvoicePay(req)
+        }
+        "getbalance" => protocol::RequestFuncs::WalletBalance,
+        "getnewaddress" => protocol::RequestFuncs::WalletNewAddress,
+        "sendtoaddress" => {
+            let req = protocol::WalletSendRequest::new(&params.require(0, "address")?, &params.require(1, "amount_sat")?)
+                .map_err(|e| e.to_string())?;
+            protocol::RequestFuncs::WalletSend(req)
+        }
+        "listunspent" => protocol::RequestFuncs::WalletListUnspent,
+        "bumpfee" => {
+            let req = protocol::TxBumpRequest::new(&params.require(0, "txid")?, &params.require(1, "feerate")?)
+                .map_err(|e| e.to_string())?;
+            protocol::RequestFuncs::TxBump(req)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(vec![req]))
+}
+
+fn to_json(resp: protocol::ResponseFuncs) -> Value {
+    match resp {
+        protocol::ResponseFuncs::GetAddresses(import) => json!({
+            "imported_addresses": import.addresses,
+            "import_mode": import.mode.map(|m| m.to_string()),
+            "imported": import.imported,
+            "error": import.error,
+        }),
+        protocol::ResponseFuncs::GetNodeInfo(node_id) => json!({ "node_id": node_id }),
+        protocol::ResponseFuncs::PeerConnect
+        | protocol::ResponseFuncs::ChannelCloseAll
+        | protocol::ResponseFuncs::InvoicePay => json!(true),
+        protocol::ResponseFuncs::PeerList(peers) => json!(peers),
+        protocol::ResponseFuncs::ChannelCreate(channel_id) => json!({ "channel_id": channel_id }),
+        protocol::ResponseFuncs::ChannelClose(channel_id) => json!({ "channel_id": channel_id }),
+        protocol::ResponseFuncs::ChannelList(channels) => Value::Array(
+            channels
+                .iter()
+                .map(|c| serde_json::from_str(c).unwrap_or(Value::Null))
+                .collect(),
+        ),
+        protocol::ResponseFuncs::InvoiceCreate(invoice) => json!({ "invoice": invoice }),
+        protocol::ResponseFuncs::Hello(server) => json!({ "server": server.server }),
+        protocol::ResponseFuncs::Subscribed => json!(true),
+        protocol::ResponseFuncs::Event(event) => json!({ "event": event.to_string() }),
+        protocol::ResponseFuncs::Error(e) => json!({ "error": e }),
+        protocol::ResponseFuncs::ChannelFund(txid) => json!({ "funding_txid": txid }),
+        protocol::ResponseFuncs::WalletBalance(balance) => json!(balance),
+        protocol::ResponseFuncs::WalletNewAddress(address) => json!({ "address": address }),
+        protocol::ResponseFuncs::WalletSend(txid) => json!({ "txid": txid }),
+        protocol::ResponseFuncs::TxBump(txid) => json!({ "txid": txid }),
+        protocol::ResponseFuncs::WalletListUnspent(utxos) => Value::Array(
+            utxos
+                .iter()
+                .map(|u| serde_json::from_str(u).unwrap_or(Value::Null))
+                .collect(),
+        ),
+        protocol::ResponseFuncs::ChannelPsbtList(psbts) => Value::Array(
+            psbts
+                .iter()
+                .map(|p| serde_json::from_str(p).unwrap_or(Value::Null))
+                .collect(),
+        ),
+    }
+}
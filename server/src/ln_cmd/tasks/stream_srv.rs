@@ -0,0 +1,188 @@
+use crate::ln_cmd::auth::Auth;
+use crate::ln_cmd::dispatch;
+use crate::ln_cmd::tasks::{Arg, Probe};
+use crate::ln_manager::executor::Larva;
+use crate::ln_node::settings::Settings as NodeSettings;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+use ln_manager::LnManager;
+use protocol;
+use std::fs;
+use std::sync::Arc;
+use tokio::codec::{Framed, LengthDelimitedCodec};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::tcp::TcpListener;
+use tokio::net::UnixListener;
+
+// Length prefixed control channel over tcp (and a unix socket if configured)
+pub async fn gen(
+    arg: Vec<Arg>,
+    exec: Probe,
+    ln_mgr: Arc<LnManager<Probe>>,
+    auth: Arc<Auth>,
+) -> Result<(), String> {
+    let node_conf: NodeSettings = match &arg[0] {
+        Arg::NodeConf(conf) => conf.clone(),
+        _ => return Err("Missing node settings".to_string()),
+    };
+
+    if let Some(path) = node_conf.server.unix_socket.clone() {
+        // A socket file left behind by an earlier run would make bind fail
+        let _ = fs::remove_file(&path);
+        match UnixListener::bind(&path) {
+            Ok(listener) => {
+                info!("Lightning Server Running on: {} (unix)", &path);
+                let unix_exec = exec.clone();
+                let unix_mgr = ln_mgr.clone();
+                let unix_auth = auth.clone();
+                let _ = exec.spawn_task(async move {
+                    let mut incoming = listener.incoming();
+                    while let Some(stream) = incoming.next().await {
+                        match stream {
+                            Ok(stream) => {
+                                let _ = unix_exec.spawn_task(serve(stream, unix_exec.clone(), unix_mgr.clone(), unix_auth.clone()));
+                            }
+                            Err(e) => error!("Couldn't accept a control connection: {}", e),
+                        }
+                    }
+                    Ok(())
+                });
+            }
+            Err(e) => error!("Could not bind control socket {}: {}", &path, e),
+        }
+    }
+
+    let address = node_conf.server.address.clone();
+    let listener = TcpListener::bind(&address)
+        .await
+        .map_err(|e| format!("Could not bind {}: {}", &address, e))?;
+    info!("Lightning Server Running on: {} (tcp)", &address);
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        match stream {
+            Ok(stream) => {
+                let _ = exec.spawn_task(serve(stream, exec.clone(), ln_mgr.clone(), auth.clone()));
+            }
+            Err(e) => error!("Couldn't accept a control connection: {}", e),
+        }
+    }
+    Ok(())
+}
+
+// Requests of one connection running at once, reading stops until one of them is done
+const MAX_IN_FLIGHT: usize = 16;
+// Responses waiting for the socket before the requests producing them wait too
+const RESPONSE_BUFFER: usize = 16;
+
+// Every request runs as its own task, responses go back through a channel in the order they
+// finish and the client matches them up by id
+async fn serve<S>(
+    stream: S,
+    exec: Probe,
+    ln_mgr: Arc<LnManager<Probe>>,
+    auth: Arc<Auth>,
+) -> Result<(), ()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut writer, mut reader) = Framed::new(stream, LengthDelimitedCodec::new()).split();
+    let (mut responses, mut send_stream) = mpsc::channel::<bytes::Bytes>(RESPONSE_BUFFER);
+    let _ = exec.spawn_task(async move {
+        if let Err(e) = writer.send_all(&mut send_stream).await {
+            debug!("Failed to write control response: {}", e);
+        }
+        Ok(())
+    });
+    // One () per finished request, so never more than MAX_IN_FLIGHT of them queued
+    let (done, mut finished) = mpsc::unbounded::<()>();
+    let mut in_flight = 0;
+    let mut subscribed = false;
+
+    loop {
+        while let Ok(Some(())) = finished.try_next() {
+            in_flight -= 1;
+        }
+        while in_flight >= MAX_IN_FLIGHT {
+            let _ = finished.next().await;
+            in_flight -= 1;
+        }
+        let chunk = match reader.next().await {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => {
+                debug!("Control connection closed: {}", e);
+                break;
+            }
+            None => break,
+        };
+        match protocol::deserialize_frame(&chunk) {
+            Ok(frame) => {
+                if let protocol::Message::Request(protocol::RequestFuncs::Subscribe) = frame.message {
+                    let token = frame.token.as_ref().map(|t| t.as_str());
+                    if auth.authorize(&protocol::RequestFuncs::Subscribe, token).is_ok() {
+                        if subscribed {
+                            let resp = protocol::Frame::new(
+                                frame.id,
+                                protocol::Message::Response(protocol::ResponseFuncs::Error(
+                                    "This connection is subscribed already".to_string(),
+                                )),
+                            );
+                            let _ = responses.send(bytes::Bytes::from(protocol::serialize_frame(&resp))).await;
+                            continue;
+                        }
+                        subscribed = true;
+                        let _ = exec.spawn_task(stream_events(responses.clone(), frame.id, ln_mgr.clone()));
+                        continue;
+                    }
+                }
+                let mut responses = responses.clone();
+                let done = done.clone();
+                let ln_mgr = ln_mgr.clone();
+                let auth = auth.clone();
+                in_flight += 1;
+                let _ = exec.spawn_task(async move {
+                    let resp = dispatch::handle_frame(frame, &ln_mgr, &auth).await;
+                    let _ = responses.send(bytes::Bytes::from(protocol::serialize_frame(&resp))).await;
+                    let _ = done.unbounded_send(());
+                    Ok(())
+                });
+            }
+            Err(e) => {
+                warn!("Received undecodable control frame: {}", e);
+                let resp = dispatch::decode_error(&e);
+                let _ = responses.send(bytes::Bytes::from(protocol::serialize_frame(&resp))).await;
+                // Nothing else this client sends will decode either
+                if let protocol::ProtocolError::Decode(_) = e {
+                    continue;
+                }
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Forwards node events to a subscribed connection until the client goes away. A client that
+// doesn't read its events blocks this task, and is dropped by `EventSubscribers` in the end.
+async fn stream_events(
+    mut responses: mpsc::Sender<bytes::Bytes>,
+    id: u64,
+    ln_mgr: Arc<LnManager<Probe>>,
+) -> Result<(), ()> {
+    let mut events = ln_mgr.event_subscribers.subscribe();
+    let ack = protocol::Frame::new(id, protocol::Message::Response(protocol::ResponseFuncs::Subscribed));
+    if responses.send(bytes::Bytes::from(protocol::serialize_frame(&ack))).await.is_err() {
+        return Ok(());
+    }
+    while let Some(event) = events.next().await {
+        let frame = protocol::Frame::new(id, protocol::Message::Response(protocol::ResponseFuncs::Event(event)));
+        if responses.send(bytes::Bytes::from(protocol::serialize_frame(&frame))).await.is_err() {
+            debug!("Event subscriber went away");
+            return Ok(());
+        }
+    }
+    // Dropped for lagging, the client stops watching on an error
+    let error = protocol::ResponseFuncs::Error("Too many events not read, subscription dropped".to_string());
+    let frame = protocol::Frame::new(id, protocol::Message::Response(error));
+    let _ = responses.send(bytes::Bytes::from(protocol::serialize_frame(&frame))).await;
+    Ok(())
+}
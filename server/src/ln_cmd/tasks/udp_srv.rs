@@ -1,103 +1,108 @@
-use crate::lightning::chain::keysinterface::KeysInterface;
+use crate::ln_cmd::auth::Auth;
+use crate::ln_cmd::dispatch;
 use crate::ln_cmd::tasks::{Arg, Probe};
-use crate::ln_cmd::utils;
-use crate::ln_manager::ln_cmd::channel::ChannelC;
-use crate::ln_manager::ln_cmd::invoice::InvoiceC;
-use crate::ln_manager::ln_cmd::peer::PeerC;
+use crate::ln_manager::executor::Larva;
 use crate::ln_node::settings::Settings as NodeSettings;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
 use ln_manager::LnManager;
 use protocol;
-use std::net::UdpSocket;
-use std::thread;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
 
-pub async fn gen(arg: Vec<Arg>, _exec: Probe, ln_mgr: LnManager<Probe>) -> Result<(), String> {
-    let node_conf: Option<&NodeSettings> = match &arg[0] {
-        Arg::NodeConf(conf) => Some(conf),
-        _ => None,
+// Datagrams handled at once, all clients together
+const MAX_IN_FLIGHT: usize = 64;
+// Responses waiting for the socket before the requests producing them wait too
+const RESPONSE_BUFFER: usize = 64;
+
+// Legacy listener, one frame (or bare `Message` from older clients) per datagram. Each datagram
+// is handled as its own task, a single sender task writes the responses back to their source.
+pub async fn gen(
+    arg: Vec<Arg>,
+    exec: Probe,
+    ln_mgr: Arc<LnManager<Probe>>,
+    auth: Arc<Auth>,
+) -> Result<(), String> {
+    let node_conf: NodeSettings = match &arg[0] {
+        Arg::NodeConf(conf) => conf.clone(),
+        _ => return Err("Missing node settings".to_string()),
     };
-    let node_address = node_conf.unwrap().server.address.clone();
-    info!("Lightning Server Running on: {}", &node_address);
-    let udp_socket = UdpSocket::bind(node_address).expect("Could not bind socket");
+    let node_address = node_conf.server.address.clone();
+    let udp_socket = UdpSocket::bind(&node_address)
+        .await
+        .map_err(|e| format!("Could not bind {}: {}", &node_address, e))?;
+    info!("Lightning Server Running on: {} (udp)", &node_address);
+
+    let (mut recv_half, mut send_half) = udp_socket.split();
+    let (responses, mut send_stream) = mpsc::channel::<(Vec<u8>, SocketAddr)>(RESPONSE_BUFFER);
+    let _ = exec.spawn_task(async move {
+        while let Some((resp, src)) = send_stream.next().await {
+            if let Err(e) = send_half.send_to(&resp, &src).await {
+                warn!("Failed to send a response to {}: {}", src, e);
+            }
+        }
+        Ok(())
+    });
+    // One () per finished datagram, so never more than MAX_IN_FLIGHT of them queued
+    let (done, mut finished) = mpsc::unbounded::<()>();
+    let mut in_flight = 0;
+
+    let mut buf = [0u8; 1500];
     loop {
-        let mut buf = [0u8; 1500];
-        let sock = udp_socket.try_clone().expect("Failed to clone socket");
-        match udp_socket.recv_from(&mut buf) {
+        // Datagrams arriving meanwhile queue in the socket buffer, or get dropped
+        while let Ok(Some(())) = finished.try_next() {
+            in_flight -= 1;
+        }
+        while in_flight >= MAX_IN_FLIGHT {
+            let _ = finished.next().await;
+            in_flight -= 1;
+        }
+        match recv_half.recv_from(&mut buf).await {
             Ok((sz, src)) => {
-                handle_msg(sock, sz, src, buf, &ln_mgr);
+                debug!("Handling connection from {}", src);
+                let msg = buf[..sz].to_vec();
+                let mut responses = responses.clone();
+                let done = done.clone();
+                let ln_mgr = ln_mgr.clone();
+                let auth = auth.clone();
+                in_flight += 1;
+                let _ = exec.spawn_task(async move {
+                    let resp = handle_msg(&msg, src, &ln_mgr, &auth).await;
+                    let _ = responses.send((resp, src)).await;
+                    let _ = done.unbounded_send(());
+                    Ok(())
+                });
             }
             Err(e) => {
                 error!("Couldn't receive a datagram: {}", e);
             }
         }
     }
-    // exit here
-    // FIXME: Unreachable
-    // Ok(())
 }
 
-fn handle_msg(
-    sock: std::net::UdpSocket,
-    sz: usize,
-    src: std::net::SocketAddr,
-    buf: [u8; 1500],
-    ln_mgr: &LnManager<Probe>,
-) {
-    let mut vec = buf.to_vec();
-    vec.resize(sz, 0);
-    let msg = protocol::deserialize_message(vec);
-    let mut resp = protocol::ResponseFuncs::Error("Unkown request".to_string());
-
-    if let protocol::Message::Request(msg) = msg {
-        resp = match msg {
-            protocol::RequestFuncs::GetAddresses => {
-                let addresses = utils::imported_addresses::get(
-                    ln_mgr.settings.lightning.lndata.clone(),
-                    ln_mgr.network.clone(),
-                );
-                protocol::ResponseFuncs::GetAddresses(addresses)
-            }
-            protocol::RequestFuncs::GetNodeInfo => {
-                let node_info = utils::node_info::get(&ln_mgr.keys.get_node_secret());
-                protocol::ResponseFuncs::GetNodeInfo(node_info)
-            }
-            protocol::RequestFuncs::PeerConnect(addr) => {
-                ln_mgr.connect(addr);
-                protocol::ResponseFuncs::PeerConnect
-            }
-            protocol::RequestFuncs::PeerList => {
-                let nodes = ln_mgr.list();
-                protocol::ResponseFuncs::PeerList(nodes)
-            }
-            protocol::RequestFuncs::ChannelCreate(args) => match ln_mgr.fund_channel(args) {
-                Ok(channel) => protocol::ResponseFuncs::ChannelCreate(channel),
-                Err(e) => protocol::ResponseFuncs::Error(e),
-            },
-            protocol::RequestFuncs::ChannelClose(id) => match ln_mgr.close(id) {
-                Ok(channel) => protocol::ResponseFuncs::ChannelClose(channel),
-                Err(e) => protocol::ResponseFuncs::Error(e),
-            },
-            protocol::RequestFuncs::ChannelCloseAll => {
-                ln_mgr.force_close_all();
-                protocol::ResponseFuncs::ChannelCloseAll
+async fn handle_msg(msg: &[u8], src: SocketAddr, ln_mgr: &LnManager<Probe>, auth: &Auth) -> Vec<u8> {
+    if protocol::frame_version(msg).is_some() {
+        let resp = match protocol::deserialize_frame(msg) {
+            Ok(frame) => dispatch::handle_frame(frame, ln_mgr, auth).await,
+            Err(e) => {
+                warn!("Received undecodable control datagram from {}: {}", src, e);
+                dispatch::decode_error(&e)
             }
-            protocol::RequestFuncs::ChannelList(mode) => {
-                protocol::ResponseFuncs::ChannelList(ln_mgr.channel_list(&mode))
+        };
+        protocol::serialize_frame(&resp)
+    } else {
+        // Clients from before versioned framing send a bare message and expect one back, they
+        // can't send a token so all they get is an explanation
+        let resp = match protocol::deserialize_message(msg) {
+            Ok(_) => protocol::ResponseFuncs::Error(
+                "Authentication required, please upgrade rbcli".to_string(),
+            ),
+            Err(e) => {
+                warn!("Received undecodable control datagram from {}: {}", src, e);
+                protocol::ResponseFuncs::Error(e.to_string())
             }
-            protocol::RequestFuncs::InvoiceCreate(amount) => match ln_mgr.create_invoice(amount) {
-                Ok(invoice_res) => protocol::ResponseFuncs::InvoiceCreate(invoice_res),
-                Err(e) => protocol::ResponseFuncs::Error(e),
-            },
-            protocol::RequestFuncs::InvoicePay(args) => match ln_mgr.pay(args) {
-                Ok(_) => protocol::ResponseFuncs::InvoicePay,
-                Err(e) => protocol::ResponseFuncs::Error(e),
-            },
-        }
+        };
+        protocol::serialize_message(protocol::Message::Response(resp))
     }
-
-    thread::spawn(move || {
-        let resp_msg = protocol::Message::Response(resp);
-        let ser = protocol::serialize_message(resp_msg);
-        debug!("Handling connection from {}", src);
-        sock.send_to(&ser, &src).expect("Failed to send a response");
-    });
 }
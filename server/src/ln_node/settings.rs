@@ -3,11 +3,18 @@ use config::{Config, ConfigError, File}; // clap-rs
 #[derive(Deserialize, Debug, Clone)]
 pub struct Server {
     pub address: String,
+    pub unix_socket: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Rpc {
+    pub address: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Settings {
     pub server: Server,
+    pub rpc: Option<Rpc>,
 }
 
 impl Settings {
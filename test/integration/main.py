@@ -119,6 +119,9 @@ def run_server(server_id, build_dir, version, env):
     return server
 
 def run_cli(build_dir, env, cmd):
+    # node 1 listens on 8123, node 2 on 8124, see test/conf
+    server_id = int(cmd[cmd.index("-n") + 1].split(":")[-1]) - 8122
+    cmd = ["--credentials", build_dir + "ln/data_{}/auth/admin.token".format(server_id)] + cmd
     print_exec("kcov --exclude-pattern=/.cargo,/usr/lib {}/coverage/ rbcli {}".format(env["home"], " ".join(cmd)))
     cli_bin =  build_dir + env["cli"]["bin"]
     return json.loads(subprocess.check_output([
@@ -126,7 +129,7 @@ def run_cli(build_dir, env, cmd):
         "--exclude-pattern=/.cargo,/usr/lib ",
         "{}/coverage/".format(env["home"]),
         cli_bin,
-        "-j"
+        "--format", "json"
     ] + cmd).decode('ascii'))
 
 def fund(addr, amount, cli):